@@ -1,15 +1,29 @@
-use crate::{kernel_state, memory};
-use types::{FrameAllocator, Pager};
+use crate::{cpu, kernel_state, memory};
+use alloc::vec::Vec;
+use types::{FrameAllocator, MapFlags, Pager};
 
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use x86_64::{
-    registers::control::Cr3,
-    structures::paging::{self, Mapper, OffsetPageTable, Page, PageTable, PhysFrame, Translate},
+    registers::{
+        control::Cr3,
+        model_specific::{Efer, EferFlags},
+    },
+    structures::paging::{
+        self, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags as Flags, PhysFrame,
+        Translate,
+    },
 };
 
 /// The size of a page (and frame) on this architecture
 pub const PAGE_SIZE: usize = 4096;
 
+/// Where the bootloader mapped all of physical memory, or 0 if [`init`]
+/// hasn't been called yet
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+/// Whether EFER.NXE is set, without which the no-execute bit is reserved
+static NX_ENABLED: AtomicBool = AtomicBool::new(false);
+
 /// Init the memory subsystem
 ///
 /// # Safety
@@ -22,6 +36,13 @@ pub unsafe fn init(
 ) -> (PagerImpl, FrameAllocImpl) {
     // TODO: Figure out a way to pass platform-specific info
 
+    PHYSICAL_MEMORY_OFFSET.store(phys_offset.0, Ordering::Relaxed);
+
+    if cpu::features().nx {
+        Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
+        NX_ENABLED.store(true, Ordering::Relaxed);
+    }
+
     // Obtain the level 4 page table
     let (l4_table_frame, _) = Cr3::read();
     let phys = l4_table_frame.start_address();
@@ -35,12 +56,27 @@ pub unsafe fn init(
     (pager, frame_alloc)
 }
 
+/// Get the virtual address through which a physical address can be accessed
+///
+/// Returns `None` before the memory subsystem has been initialized
+pub fn phys_to_virt(addr: memory::PhysAddr) -> Option<memory::VirtAddr> {
+    match PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed) {
+        0 => None,
+        offset => Some(memory::VirtAddr(offset + addr.0)),
+    }
+}
+
 /// Reserve a frame for use
 pub fn allocate_frame() -> Option<memory::PhysAddr> {
-    x86_64::instructions::interrupts::without_interrupts(|| {
-        let mut lock = kernel_state().frame_alloc.lock();
-        lock.next()
-    })
+    kernel_state().frame_alloc.lock().next()
+}
+
+/// Give back a frame returned by [`allocate_frame`]
+///
+/// # Safety
+/// Nothing may use the frame anymore
+pub unsafe fn deallocate_frame(frame: memory::PhysAddr) {
+    kernel_state().frame_alloc.lock().deallocate(frame)
 }
 
 pub struct PagerImpl(OffsetPageTable<'static>);
@@ -50,29 +86,81 @@ unsafe impl Pager for PagerImpl {
         self.0.translate_addr(addr.into()).map(|a| a.into())
     }
 
-    unsafe fn map(&mut self, addr: memory::VirtAddr, to: memory::PhysAddr) -> Option<()> {
-        crate::println!("Mapping 0x{:x} -> 0x{:x}", addr.0, to.0);
+    fn flags(&self, addr: memory::VirtAddr) -> Option<MapFlags> {
+        use paging::mapper::TranslateResult;
 
+        match self.0.translate(addr.into()) {
+            TranslateResult::Mapped { flags, .. } => Some(MapFlags {
+                writable: flags.contains(Flags::WRITABLE),
+                user: flags.contains(Flags::USER_ACCESSIBLE),
+                executable: !flags.contains(Flags::NO_EXECUTE),
+            }),
+            _ => None,
+        }
+    }
+
+    unsafe fn map_with_flags(
+        &mut self,
+        addr: memory::VirtAddr,
+        to: memory::PhysAddr,
+        map_flags: MapFlags,
+    ) -> Option<()> {
         let page = Page::<paging::Size4KiB>::containing_address(addr.into());
         let frame = PhysFrame::containing_address(to.into());
-        let flags = paging::PageTableFlags::PRESENT | paging::PageTableFlags::WRITABLE;
+        let flags = page_flags(map_flags);
+        // The parent tables must allow everything the page allows, since
+        // the CPU checks permissions at every level
+        let mut parent_flags = Flags::PRESENT | Flags::WRITABLE;
+        if map_flags.user {
+            parent_flags |= Flags::USER_ACCESSIBLE;
+        }
 
         let lock = &mut kernel_state().frame_alloc.lock();
         let frame_allocator: &mut FrameAllocImpl = &mut *lock;
 
         self.0
-            .map_to(page, frame, flags, frame_allocator)
+            .map_to_with_table_flags(page, frame, flags, parent_flags, frame_allocator)
             .ok()?
             .flush();
 
         Some(())
     }
+
+    unsafe fn unmap(&mut self, addr: memory::VirtAddr) -> Option<memory::PhysAddr> {
+        let page = Page::<paging::Size4KiB>::containing_address(addr.into());
+        let (frame, flush) = self.0.unmap(page).ok()?;
+        flush.flush();
+        Some(frame.start_address().into())
+    }
+
+    unsafe fn update_flags(&mut self, addr: memory::VirtAddr, map_flags: MapFlags) -> Option<()> {
+        let page = Page::<paging::Size4KiB>::containing_address(addr.into());
+        self.0.update_flags(page, page_flags(map_flags)).ok()?.flush();
+        Some(())
+    }
+}
+
+/// The flags of a page table entry with the given permissions
+fn page_flags(map_flags: MapFlags) -> Flags {
+    let mut flags = Flags::PRESENT;
+    if map_flags.writable {
+        flags |= Flags::WRITABLE;
+    }
+    if map_flags.user {
+        flags |= Flags::USER_ACCESSIBLE;
+    }
+    if !map_flags.executable && NX_ENABLED.load(Ordering::Relaxed) {
+        flags |= Flags::NO_EXECUTE;
+    }
+    flags
 }
 
 /// A FrameAllocator that returns usable frames from the bootloader's memory map.
 pub struct FrameAllocImpl {
     memory_map: &'static MemoryMap,
     next: usize,
+    /// Frames given back, which are handed out again before new ones
+    free: Vec<memory::PhysAddr>,
 }
 
 impl FrameAllocImpl {
@@ -87,6 +175,7 @@ impl FrameAllocImpl {
         FrameAllocImpl {
             memory_map,
             next: 0,
+            free: Vec::new(),
         }
     }
 
@@ -104,10 +193,16 @@ impl FrameAllocImpl {
 
 unsafe impl FrameAllocator for FrameAllocImpl {
     fn next(&mut self) -> Option<memory::PhysAddr> {
-        let frame = self.usable_frames().nth(self.next);
-        assert_eq!(frame.unwrap().0 % 4096, 0);
+        if let Some(frame) = self.free.pop() {
+            return Some(frame);
+        }
+        let frame = self.usable_frames().nth(self.next)?;
         self.next += 1;
-        frame
+        Some(frame)
+    }
+
+    unsafe fn deallocate(&mut self, frame: memory::PhysAddr) {
+        self.free.push(frame);
     }
 }
 
@@ -0,0 +1,78 @@
+//! Switching between kernel stacks
+//!
+//! `context_switch` saves the callee-saved registers and `rflags` on the
+//! current stack, stores the stack pointer, loads the stack pointer of the
+//! next task and pops its registers: the caller-saved registers are already
+//! saved by the compiler around the call. A task that never ran has a stack
+//! prepared by [`init_stack`], which makes `context_switch` "return" into
+//! `task_trampoline`.
+
+use core::arch::global_asm;
+
+extern "C" {
+    /// Save the current stack pointer into `prev_rsp`, and continue on the
+    /// stack saved in `next_rsp`
+    pub fn context_switch(prev_rsp: *mut u64, next_rsp: u64);
+
+    /// The first code run by a new task
+    fn task_trampoline();
+}
+
+/// The number of registers popped by `context_switch` before returning
+const SAVED_REGISTERS: usize = 7;
+
+/// Prepare a new stack, so that switching to it calls `task_start(arg)`
+///
+/// Returns the stack pointer to switch to.
+///
+/// # Safety
+///
+/// `top` must be the 16-byte aligned end of a stack that isn't in use.
+pub unsafe fn init_stack(top: u64, arg: u64) -> u64 {
+    let top = top as *mut u64;
+    top.sub(1)
+        .write(task_trampoline as unsafe extern "C" fn() as usize as u64);
+
+    // rflags, rbp, rbx, r12, r13, r14, r15: interrupts start disabled,
+    // and r12 holds the argument
+    let registers = [0x2, 0, 0, arg, 0, 0, 0];
+    let rsp = top.sub(1 + SAVED_REGISTERS);
+    for (i, value) in registers.iter().rev().enumerate() {
+        rsp.add(i).write(*value);
+    }
+    rsp as u64
+}
+
+global_asm!(
+    r#"
+.section .text
+.global context_switch
+context_switch:
+    pushfq
+    push rbp
+    push rbx
+    push r12
+    push r13
+    push r14
+    push r15
+    mov [rdi], rsp
+
+    mov rsp, rsi
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    pop rbp
+    popfq
+    ret
+
+.global task_trampoline
+task_trampoline:
+    // The stack is 16-byte aligned here, as `init_stack` left it
+    mov rdi, r12
+    call {task_start}
+    ud2
+"#,
+    task_start = sym super::task_start,
+);
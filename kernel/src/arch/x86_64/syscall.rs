@@ -0,0 +1,132 @@
+//! Entry points for system calls
+//!
+//! `syscall` doesn't switch stacks, so `syscall_entry` stashes the user
+//! stack pointer and loads `rsp0` from the TSS itself (interrupts are
+//! disabled on entry through `SFMASK`, so nothing can run in between). Both
+//! `syscall_entry` and the `int 0x80` fallback build a [`SyscallFrame`] on
+//! the kernel stack, and pass it to `dispatch`.
+
+use core::{arch::global_asm, mem::offset_of};
+
+use x86_64::structures::tss::TaskStateSegment;
+
+/// The user state saved when entering a system call
+///
+/// The layout of this struct must match the order in which the entry stubs
+/// push registers onto the stack.
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct SyscallFrame {
+    /// The syscall number on entry, and the result on exit
+    pub rax: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub r10: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub rip: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+}
+
+impl SyscallFrame {
+    /// The syscall arguments, in the order of the Linux ABI
+    pub fn args(&self) -> [u64; 6] {
+        [self.rdi, self.rsi, self.rdx, self.r10, self.r8, self.r9]
+    }
+}
+
+extern "C" {
+    /// The target of `syscall`, to be written to `LSTAR`
+    pub fn syscall_entry();
+
+    /// The `int 0x80` handler, to be installed in the IDT
+    pub fn syscall_int80();
+}
+
+/// Where `rsp0` lives in the TSS, for `syscall_entry`
+const RSP0_OFFSET: usize = offset_of!(TaskStateSegment, privilege_stack_table);
+
+global_asm!(
+    r#"
+.section .bss
+syscall_user_rsp:
+    .zero 8
+
+.section .text
+.global syscall_entry
+syscall_entry:
+    // rcx holds the user rip, and r11 the user rflags
+    mov [rip + syscall_user_rsp], rsp
+    mov rsp, [rip + {tss} + {rsp0}]
+
+    push qword ptr [rip + syscall_user_rsp]
+    push r11
+    push rcx
+    push r9
+    push r8
+    push r10
+    push rdx
+    push rsi
+    push rdi
+    push rax
+
+    // rsp0 is 16-byte aligned, and 10 registers were pushed
+    mov rdi, rsp
+    sti
+    call {dispatch}
+    cli
+
+    pop rax
+    pop rdi
+    pop rsi
+    pop rdx
+    pop r10
+    pop r8
+    pop r9
+    pop rcx
+    pop r11
+    pop rsp
+    sysretq
+
+.global syscall_int80
+syscall_int80:
+    // Copy user rsp, rflags and rip from the interrupt frame
+    push qword ptr [rsp + 24]
+    push qword ptr [rsp + 24]
+    push qword ptr [rsp + 16]
+    push r9
+    push r8
+    push r10
+    push rdx
+    push rsi
+    push rdi
+    push rax
+
+    // Unlike `syscall`, `int` preserves rcx and r11
+    push rcx
+    push r11
+    sub rsp, 8
+    lea rdi, [rsp + 24]
+    sti
+    call {dispatch}
+    cli
+    add rsp, 8
+    pop r11
+    pop rcx
+
+    pop rax
+    pop rdi
+    pop rsi
+    pop rdx
+    pop r10
+    pop r8
+    pop r9
+    add rsp, 24
+    iretq
+"#,
+    tss = sym crate::gdt::TSS,
+    rsp0 = const RSP0_OFFSET,
+    dispatch = sym super::dispatch,
+);
@@ -0,0 +1,205 @@
+//! Low-level entry points for CPU exceptions
+//!
+//! The `x86-interrupt` calling convention only gives handlers access to the
+//! [`InterruptStackFrame`](x86_64::structures::idt::InterruptStackFrame), so
+//! every exception instead enters through a small assembly stub. The stub
+//! pushes a dummy error code (if the CPU didn't push one), the vector number
+//! and all the general purpose registers, and then calls into Rust with a
+//! pointer to the resulting [`TrapFrame`].
+
+use core::{arch::global_asm, fmt};
+
+/// The state of the CPU at the time an exception was raised
+///
+/// The layout of this struct must match the order in which `trap_common`
+/// pushes registers onto the stack.
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct TrapFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+
+    pub vector: u64,
+    pub error_code: u64,
+
+    // Pushed by the CPU
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+impl TrapFrame {
+    /// Whether the exception was raised while running in ring 3
+    pub fn from_user(&self) -> bool {
+        self.cs & 0b11 == 3
+    }
+}
+
+impl fmt::Display for TrapFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let regs = [
+            ("RAX", self.rax),
+            ("RBX", self.rbx),
+            ("RCX", self.rcx),
+            ("RDX", self.rdx),
+            ("RSI", self.rsi),
+            ("RDI", self.rdi),
+            ("RBP", self.rbp),
+            ("RSP", self.rsp),
+            ("R8 ", self.r8),
+            ("R9 ", self.r9),
+            ("R10", self.r10),
+            ("R11", self.r11),
+            ("R12", self.r12),
+            ("R13", self.r13),
+            ("R14", self.r14),
+            ("R15", self.r15),
+            ("RIP", self.rip),
+            ("RFL", self.rflags),
+        ];
+
+        for line in regs.chunks(3) {
+            for (name, value) in line {
+                write!(f, "{}={:016x} ", name, value)?;
+            }
+            writeln!(f)?;
+        }
+        write!(f, "CS={:04x} SS={:04x}", self.cs, self.ss)
+    }
+}
+
+/// The number of architecturally defined exception vectors
+pub const EXCEPTION_COUNT: usize = 32;
+
+extern "C" {
+    /// The addresses of the entry stubs, indexed by exception vector
+    static trap_stub_table: [u64; EXCEPTION_COUNT];
+}
+
+/// Get the address of the entry stub for the given exception vector
+pub fn stub_addr(vector: u8) -> x86_64::VirtAddr {
+    x86_64::VirtAddr::new(unsafe { trap_stub_table[vector as usize] })
+}
+
+global_asm!(
+    r#"
+.macro TRAP_NOERR vector
+trap_stub_\vector:
+    push 0
+    push \vector
+    jmp trap_common
+.endm
+
+.macro TRAP_ERR vector
+trap_stub_\vector:
+    push \vector
+    jmp trap_common
+.endm
+
+.section .text
+trap_common:
+    push rax
+    push rbx
+    push rcx
+    push rdx
+    push rsi
+    push rdi
+    push rbp
+    push r8
+    push r9
+    push r10
+    push r11
+    push r12
+    push r13
+    push r14
+    push r15
+
+    mov rdi, rsp
+    cld
+    call {dispatch}
+
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop r11
+    pop r10
+    pop r9
+    pop r8
+    pop rbp
+    pop rdi
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rbx
+    pop rax
+
+    // Skip the vector and the error code
+    add rsp, 16
+    iretq
+
+TRAP_NOERR 0
+TRAP_NOERR 1
+TRAP_NOERR 2
+TRAP_NOERR 3
+TRAP_NOERR 4
+TRAP_NOERR 5
+TRAP_NOERR 6
+TRAP_NOERR 7
+TRAP_ERR   8
+TRAP_NOERR 9
+TRAP_ERR   10
+TRAP_ERR   11
+TRAP_ERR   12
+TRAP_ERR   13
+TRAP_ERR   14
+TRAP_NOERR 15
+TRAP_NOERR 16
+TRAP_ERR   17
+TRAP_NOERR 18
+TRAP_NOERR 19
+TRAP_NOERR 20
+TRAP_ERR   21
+TRAP_NOERR 22
+TRAP_NOERR 23
+TRAP_NOERR 24
+TRAP_NOERR 25
+TRAP_NOERR 26
+TRAP_NOERR 27
+TRAP_NOERR 28
+TRAP_ERR   29
+TRAP_ERR   30
+TRAP_NOERR 31
+
+.section .rodata
+.global trap_stub_table
+.balign 8
+trap_stub_table:
+    .quad trap_stub_0,  trap_stub_1,  trap_stub_2,  trap_stub_3
+    .quad trap_stub_4,  trap_stub_5,  trap_stub_6,  trap_stub_7
+    .quad trap_stub_8,  trap_stub_9,  trap_stub_10, trap_stub_11
+    .quad trap_stub_12, trap_stub_13, trap_stub_14, trap_stub_15
+    .quad trap_stub_16, trap_stub_17, trap_stub_18, trap_stub_19
+    .quad trap_stub_20, trap_stub_21, trap_stub_22, trap_stub_23
+    .quad trap_stub_24, trap_stub_25, trap_stub_26, trap_stub_27
+    .quad trap_stub_28, trap_stub_29, trap_stub_30, trap_stub_31
+
+.section .text
+"#,
+    dispatch = sym super::exceptions::dispatch,
+);
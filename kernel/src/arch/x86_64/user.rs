@@ -0,0 +1,82 @@
+//! Switching between ring 0 and ring 3
+//!
+//! `user_enter` works like `setjmp`: it saves the callee-saved registers on
+//! the kernel stack, records where they are, and then jumps to user mode
+//! with `iretq`. The recorded stack pointer is also used as `rsp0`, so
+//! interrupts coming from user mode land right below the saved registers.
+//!
+//! `user_return` is the matching `longjmp`: called from any handler running
+//! on that kernel stack, it throws away everything below the saved
+//! registers and returns from `user_enter`.
+
+use core::arch::global_asm;
+
+extern "C" {
+    /// Jump to `entry` in ring 3 with the given stack, returning when
+    /// [`user_return`] is called with the stack pointer saved in `return_rsp`
+    pub fn user_enter(
+        entry: u64,
+        stack: u64,
+        code_selector: u64,
+        data_selector: u64,
+        return_rsp: *mut u64,
+        kernel_stack: *mut u64,
+    );
+
+    /// Return from the [`user_enter`] call that saved `return_rsp`
+    pub fn user_return(return_rsp: u64) -> !;
+}
+
+global_asm!(
+    r#"
+.section .text
+.global user_enter
+user_enter:
+    pushfq
+    push rbp
+    push rbx
+    push r12
+    push r13
+    push r14
+    push r15
+    mov [r8], rsp
+    mov [r9], rsp
+
+    // The frame for `iretq`: interrupts are enabled in user mode
+    push rcx
+    push rsi
+    push 0x202
+    push rdx
+    push rdi
+
+    // Don't leak kernel data through the registers
+    xor eax, eax
+    xor ebx, ebx
+    xor ecx, ecx
+    xor edx, edx
+    xor esi, esi
+    xor edi, edi
+    xor ebp, ebp
+    xor r8d, r8d
+    xor r9d, r9d
+    xor r10d, r10d
+    xor r11d, r11d
+    xor r12d, r12d
+    xor r13d, r13d
+    xor r14d, r14d
+    xor r15d, r15d
+    iretq
+
+.global user_return
+user_return:
+    mov rsp, rdi
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    pop rbp
+    popfq
+    ret
+"#
+);
@@ -0,0 +1,323 @@
+//! Stack backtraces, symbolized with the kernel's own symbol table
+//!
+//! The kernel is built with frame pointers, so every stack frame starts
+//! with the caller's `rbp`, followed by the return address. Walking this
+//! chain gives the list of return addresses, which are then looked up in
+//! the symbol table of the kernel's ELF image.
+//!
+//! That image is embedded in the boot image by the bootloader's build, and
+//! the bootloader leaves it in memory as a [`Kernel`](MemoryRegionType::Kernel)
+//! region, so it's parsed like any other ELF file.
+
+use alloc::{string::String, vec::Vec};
+use core::{
+    arch::asm,
+    fmt, slice,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use spin::Once;
+use types::{Pager, PhysAddr, VirtAddr};
+
+use crate::{
+    delf::{
+        components::{
+            section::SectionType,
+            sym::{Sym, SymTab, SymType},
+        },
+        ParsedElf,
+    },
+    interrupts::TrapFrame,
+    memory, KERNEL_STATE,
+};
+
+/// The maximum number of frames in a [`Backtrace`]
+pub const MAX_FRAMES: usize = 32;
+
+/// The symbol table of the kernel's image
+static SYMBOLS: Once<SymTab<'static>> = Once::new();
+
+/// Set while a backtrace is being walked, to avoid recursing if walking
+/// the stack faults
+static WALKING: AtomicBool = AtomicBool::new(false);
+
+/// Find the kernel's symbol table, in the image the bootloader left in
+/// `memory_map`
+///
+/// Until this is called, backtraces only contain raw addresses.
+pub fn init(memory_map: &MemoryMap) {
+    // The frames the bootloader gave the kernel's BSS are `Kernel` regions
+    // too, but only the image parses as an ELF file
+    let symtab = memory_map
+        .iter()
+        .filter(|region| region.region_type == MemoryRegionType::Kernel)
+        .filter_map(|region| {
+            let start = memory::phys_to_virt(PhysAddr(region.range.start_addr()))?;
+            let len = region.range.end_addr() - region.range.start_addr();
+            // All of physical memory is mapped, and the frame allocator
+            // never gives out `Kernel` regions
+            Some(unsafe { slice::from_raw_parts(start.0 as *const u8, len as usize) })
+        })
+        .find_map(ParsedElf::parse_or_print_error)
+        .and_then(|file| file.symtab(file.section_with_type(SectionType::SymTab)?));
+    if let Some(symtab) = symtab {
+        SYMBOLS.call_once(|| symtab);
+    }
+}
+
+/// Find the function containing `addr`, and the offset of `addr` in it
+fn lookup(addr: u64) -> Option<(Sym<'static>, u64)> {
+    let symtab = SYMBOLS.get()?;
+    // Functions written in assembly may have no size, and are assumed to
+    // extend up to the next one
+    let sym = (0..symtab.0.len() / Sym::SIZE)
+        .filter_map(|index| symtab.sym_index(index))
+        .filter(|sym| matches!(sym.r#type, SymType::Func) && !sym.shndx.is_undef())
+        .filter(|sym| sym.value.0 <= addr && (sym.size == 0 || addr < sym.value.0 + sym.size))
+        .max_by_key(|sym| sym.value)?;
+
+    let offset = addr - sym.value.0;
+    Some((sym, offset))
+}
+
+/// A list of return addresses, from the innermost frame outwards
+pub struct Backtrace {
+    frames: [u64; MAX_FRAMES],
+    len: usize,
+}
+
+impl Backtrace {
+    /// Capture a backtrace of the caller
+    #[inline(always)]
+    pub fn capture() -> Self {
+        let rbp: u64;
+        unsafe { asm!("mov {}, rbp", out(reg) rbp) };
+
+        let mut bt = Self::empty();
+        bt.walk(rbp);
+        bt
+    }
+
+    /// Capture the backtrace of the code that was interrupted by an exception
+    pub fn from_trap(frame: &TrapFrame) -> Self {
+        let mut bt = Self::empty();
+        bt.push(frame.rip);
+        bt.walk(frame.rbp);
+        bt
+    }
+
+    /// The captured return addresses
+    pub fn frames(&self) -> &[u64] {
+        &self.frames[..self.len]
+    }
+
+    fn empty() -> Self {
+        Self {
+            frames: [0; MAX_FRAMES],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, addr: u64) {
+        if self.len < MAX_FRAMES {
+            self.frames[self.len] = addr;
+            self.len += 1;
+        }
+    }
+
+    /// Follow the chain of saved frame pointers starting at `rbp`
+    fn walk(&mut self, mut rbp: u64) {
+        if WALKING.swap(true, Ordering::Acquire) {
+            return;
+        }
+
+        while self.len < MAX_FRAMES && is_readable(rbp) {
+            let (next, ret) = unsafe {
+                let frame = rbp as *const u64;
+                (*frame, *frame.add(1))
+            };
+            if ret == 0 {
+                break;
+            }
+            self.push(ret);
+
+            // The stack grows downwards, so callers' frames are always above
+            if next <= rbp {
+                break;
+            }
+            rbp = next;
+        }
+
+        WALKING.store(false, Ordering::Release);
+    }
+}
+
+/// Whether a stack frame (saved `rbp` and return address) can be read at `addr`
+fn is_readable(addr: u64) -> bool {
+    if addr == 0 || !addr.is_multiple_of(8) {
+        return false;
+    }
+    // Non-canonical addresses would cause a general protection fault
+    if (0x0000_8000_0000_0000..0xFFFF_8000_0000_0000).contains(&addr) {
+        return false;
+    }
+
+    // If the pager is busy (e.g. the panic happened while mapping memory)
+    // we can't check, so trust the frame pointer
+    let pager = match KERNEL_STATE.get().and_then(|state| state.pager.try_lock()) {
+        Some(pager) => pager,
+        None => return true,
+    };
+    pager.translate(VirtAddr(addr)).is_some() && pager.translate(VirtAddr(addr + 8)).is_some()
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Backtrace:")?;
+        for (i, &addr) in self.frames().iter().enumerate() {
+            // Return addresses point after the call instruction, which may
+            // be the first byte of the next function
+            let lookup_addr = if i == 0 { addr } else { addr - 1 };
+            match lookup(lookup_addr) {
+                Some((sym, offset)) => writeln!(
+                    f,
+                    "#{} {:#018x} {}+{:#x}",
+                    i,
+                    addr,
+                    demangle(&sym.name),
+                    offset + (addr - lookup_addr)
+                )?,
+                None => writeln!(f, "#{} {:#018x} <unknown>", i, addr)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Demangle a legacy Rust symbol name
+///
+/// `_ZN6kernel6memory9init_heap17h0123456789abcdefE` becomes
+/// `kernel::memory::init_heap`. Anything else is returned unchanged.
+pub fn demangle(name: &str) -> String {
+    let inner = match name.strip_prefix("_ZN").and_then(|n| n.strip_suffix('E')) {
+        Some(inner) => inner,
+        None => return String::from(name),
+    };
+
+    let mut parts = Vec::new();
+    let mut rest = inner;
+    while !rest.is_empty() {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let len: usize = match rest[..digits].parse() {
+            Ok(len) if digits + len <= rest.len() => len,
+            _ => return String::from(name),
+        };
+        parts.push(&rest[digits..digits + len]);
+        rest = &rest[digits + len..];
+    }
+
+    // Drop the trailing hash
+    if let Some(last) = parts.last() {
+        if last.len() == 17
+            && last.starts_with('h')
+            && last[1..].bytes().all(|b| b.is_ascii_hexdigit())
+        {
+            parts.pop();
+        }
+    }
+
+    let mut out = String::new();
+    for (i, part) in parts.iter().enumerate() {
+        if i != 0 {
+            out.push_str("::");
+        }
+        unescape(part, &mut out);
+    }
+    out
+}
+
+/// Replace the `$..$` escapes used by legacy mangling
+fn unescape(mut part: &str, out: &mut String) {
+    // Identifiers starting with `$` get an additional underscore
+    if part.starts_with("_$") {
+        part = &part[1..];
+    }
+
+    while !part.is_empty() {
+        if let Some(rest) = part.strip_prefix("..") {
+            out.push_str("::");
+            part = rest;
+        } else if part.starts_with('$') {
+            let end = match part[1..].find('$') {
+                Some(end) => end + 1,
+                None => {
+                    out.push_str(part);
+                    return;
+                }
+            };
+            let escape = &part[1..end];
+            match escape {
+                "SP" => out.push('@'),
+                "BP" => out.push('*'),
+                "RF" => out.push('&'),
+                "LT" => out.push('<'),
+                "GT" => out.push('>'),
+                "LP" => out.push('('),
+                "RP" => out.push(')'),
+                "C" => out.push(','),
+                _ => match escape
+                    .strip_prefix('u')
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .and_then(core::char::from_u32)
+                {
+                    Some(c) => out.push(c),
+                    None => out.push_str(&part[..=end]),
+                },
+            }
+            part = &part[end + 1..];
+        } else {
+            let next = part
+                .find(['$', '.'])
+                .map(|i| if i == 0 { 1 } else { i })
+                .unwrap_or(part.len());
+            out.push_str(&part[..next]);
+            part = &part[next..];
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{demangle, lookup, Backtrace};
+    use alloc::string::String;
+
+    #[test_case]
+    fn demangle_legacy() {
+        assert_eq!(
+            demangle("_ZN6kernel6memory9init_heap17h0123456789abcdefE"),
+            "kernel::memory::init_heap"
+        );
+        assert_eq!(
+            demangle(
+                "_ZN4core3ptr46drop_in_place$LT$alloc..vec..Vec$LT$u8$GT$$GT$17h0123456789abcdefE"
+            ),
+            "core::ptr::drop_in_place<alloc::vec::Vec<u8>>"
+        );
+        assert_eq!(demangle("rust_begin_unwind"), "rust_begin_unwind");
+    }
+
+    #[test_case]
+    fn symbols_are_found() {
+        let addr = demangle as fn(&str) -> String as usize as u64;
+        let (sym, offset) = lookup(addr + 1).unwrap();
+        assert_eq!(demangle(&sym.name), "kernel::backtrace::demangle");
+        assert_eq!(offset, 1);
+    }
+
+    #[test_case]
+    fn capture() {
+        let bt = Backtrace::capture();
+        assert!(!bt.frames().is_empty());
+    }
+}
@@ -0,0 +1,467 @@
+//! Identification of the CPU and of its features
+//!
+//! CPUID is queried once at boot, in [`init`], and the results are kept in
+//! a [`CpuInfo`]. Everything else should check [`features`] instead of
+//! running CPUID again, except for per-CPU values like [`apic_id`].
+
+pub mod msr;
+
+use core::{
+    arch::x86_64::{__cpuid_count, CpuidResult},
+    fmt, str,
+};
+
+use spin::Once;
+
+use crate::serial_println;
+
+/// Run CPUID with the given leaf and subleaf
+pub fn cpuid(leaf: u32, subleaf: u32) -> CpuidResult {
+    __cpuid_count(leaf, subleaf)
+}
+
+/// The initial APIC ID of the running CPU
+pub fn apic_id() -> u32 {
+    cpuid(1, 0).ebx >> 24
+}
+
+/// The CPUID leaves describing features
+#[derive(Debug, Clone, Copy)]
+pub struct Leaves {
+    /// Leaf 1
+    pub basic: CpuidResult,
+    /// Leaf 7, subleaf 0
+    pub structured: CpuidResult,
+    /// Leaf 0x8000_0001
+    pub extended: CpuidResult,
+    /// Leaf 0x8000_0007
+    pub power: CpuidResult,
+}
+
+const EMPTY: CpuidResult = CpuidResult {
+    eax: 0,
+    ebx: 0,
+    ecx: 0,
+    edx: 0,
+};
+
+macro_rules! features {
+    ($($(#[$doc: meta])* $name: ident: $leaf: ident . $reg: ident [$bit: expr],)*) => {
+        /// The features supported by the CPU
+        #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+        pub struct CpuFeatures {
+            $($(#[$doc])* pub $name: bool,)*
+        }
+
+        impl CpuFeatures {
+            pub fn from_leaves(leaves: &Leaves) -> Self {
+                Self {
+                    $($name: leaves.$leaf.$reg & 1 << $bit != 0,)*
+                }
+            }
+        }
+
+        /// The names of the supported features, separated by spaces
+        impl fmt::Display for CpuFeatures {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let mut first = true;
+                $(
+                    if self.$name {
+                        if !first {
+                            write!(f, " ")?;
+                        }
+                        write!(f, stringify!($name))?;
+                        first = false;
+                    }
+                )*
+                Ok(())
+            }
+        }
+    };
+}
+
+features! {
+    fpu: basic.edx[0],
+    /// `rdtsc`
+    tsc: basic.edx[4],
+    /// `rdmsr` and `wrmsr`
+    msr: basic.edx[5],
+    pae: basic.edx[6],
+    /// Machine-check exception
+    mce: basic.edx[7],
+    cx8: basic.edx[8],
+    /// Local APIC
+    apic: basic.edx[9],
+    sep: basic.edx[11],
+    mtrr: basic.edx[12],
+    /// Global pages
+    pge: basic.edx[13],
+    /// Machine-check architecture
+    mca: basic.edx[14],
+    cmov: basic.edx[15],
+    pat: basic.edx[16],
+    clflush: basic.edx[19],
+    mmx: basic.edx[23],
+    /// `fxsave` and `fxrstor`
+    fxsr: basic.edx[24],
+    sse: basic.edx[25],
+    sse2: basic.edx[26],
+    /// More than one logical processor per package
+    htt: basic.edx[28],
+    sse3: basic.ecx[0],
+    pclmulqdq: basic.ecx[1],
+    monitor: basic.ecx[3],
+    ssse3: basic.ecx[9],
+    fma: basic.ecx[12],
+    cx16: basic.ecx[13],
+    /// Process-context identifiers
+    pcid: basic.ecx[17],
+    sse4_1: basic.ecx[19],
+    sse4_2: basic.ecx[20],
+    x2apic: basic.ecx[21],
+    movbe: basic.ecx[22],
+    popcnt: basic.ecx[23],
+    tsc_deadline: basic.ecx[24],
+    aes: basic.ecx[25],
+    /// `xsave`, `xrstor` and XCR0
+    xsave: basic.ecx[26],
+    avx: basic.ecx[28],
+    f16c: basic.ecx[29],
+    rdrand: basic.ecx[30],
+    /// Running under a hypervisor
+    hypervisor: basic.ecx[31],
+    fsgsbase: structured.ebx[0],
+    bmi1: structured.ebx[3],
+    avx2: structured.ebx[5],
+    /// Supervisor-mode execution prevention
+    smep: structured.ebx[7],
+    bmi2: structured.ebx[8],
+    erms: structured.ebx[9],
+    invpcid: structured.ebx[10],
+    avx512f: structured.ebx[16],
+    rdseed: structured.ebx[18],
+    /// Supervisor-mode access prevention
+    smap: structured.ebx[20],
+    clflushopt: structured.ebx[23],
+    umip: structured.ecx[2],
+    pku: structured.ecx[3],
+    /// `syscall` and `sysret`
+    syscall: extended.edx[11],
+    /// The no-execute page bit
+    nx: extended.edx[20],
+    /// 1 GiB pages
+    page1gb: extended.edx[26],
+    rdtscp: extended.edx[27],
+    long_mode: extended.edx[29],
+    /// The cache topology leaf 0x8000_001D (AMD)
+    topology_extensions: extended.ecx[22],
+    /// The TSC runs at a constant rate in every power state
+    invariant_tsc: power.edx[8],
+}
+
+/// The manufacturer of the CPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Vendor {
+    Intel,
+    Amd,
+    Other([u8; 12]),
+}
+
+impl Vendor {
+    /// Decode the vendor string returned by leaf 0
+    pub fn from_leaf0(leaf: CpuidResult) -> Self {
+        let mut id = [0; 12];
+        id[0..4].copy_from_slice(&leaf.ebx.to_le_bytes());
+        id[4..8].copy_from_slice(&leaf.edx.to_le_bytes());
+        id[8..12].copy_from_slice(&leaf.ecx.to_le_bytes());
+        match &id {
+            b"GenuineIntel" => Self::Intel,
+            b"AuthenticAMD" => Self::Amd,
+            _ => Self::Other(id),
+        }
+    }
+}
+
+impl fmt::Display for Vendor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Intel => write!(f, "Intel"),
+            Self::Amd => write!(f, "AMD"),
+            Self::Other(id) => write!(f, "{}", str::from_utf8(id).unwrap_or("unknown")),
+        }
+    }
+}
+
+/// The kind of data held by a cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheKind {
+    Data,
+    Instruction,
+    Unified,
+}
+
+/// A cache, as described by leaf 4 (Intel) or 0x8000_001D (AMD)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheInfo {
+    pub level: u8,
+    pub kind: CacheKind,
+    pub line_size: u32,
+    pub partitions: u32,
+    pub ways: u32,
+    pub sets: u32,
+    /// The number of logical processors sharing this cache
+    pub shared_by: u32,
+}
+
+impl CacheInfo {
+    /// Decode a subleaf of the cache leaf, returning `None` after the last
+    /// cache
+    pub fn from_leaf(leaf: CpuidResult) -> Option<Self> {
+        let kind = match leaf.eax & 0x1F {
+            1 => CacheKind::Data,
+            2 => CacheKind::Instruction,
+            3 => CacheKind::Unified,
+            _ => return None,
+        };
+        Some(Self {
+            level: ((leaf.eax >> 5) & 0b111) as u8,
+            kind,
+            shared_by: ((leaf.eax >> 14) & 0xFFF) + 1,
+            line_size: (leaf.ebx & 0xFFF) + 1,
+            partitions: ((leaf.ebx >> 12) & 0x3FF) + 1,
+            ways: (leaf.ebx >> 22) + 1,
+            sets: leaf.ecx + 1,
+        })
+    }
+
+    /// The total size in bytes
+    pub fn size(&self) -> u32 {
+        self.line_size * self.partitions * self.ways * self.sets
+    }
+}
+
+impl fmt::Display for CacheInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            CacheKind::Data => "d",
+            CacheKind::Instruction => "i",
+            CacheKind::Unified => "",
+        };
+        write!(
+            f,
+            "L{}{} {} KiB ({}-way, {} B lines, shared by {})",
+            self.level,
+            kind,
+            self.size() / 1024,
+            self.ways,
+            self.line_size,
+            self.shared_by
+        )
+    }
+}
+
+/// The maximum number of caches kept in a [`CpuInfo`]
+pub const MAX_CACHES: usize = 8;
+
+/// Everything the kernel knows about the CPU
+#[derive(Debug, Clone)]
+pub struct CpuInfo {
+    pub vendor: Vendor,
+    pub family: u32,
+    pub model: u32,
+    pub stepping: u32,
+    brand: [u8; 48],
+    caches: [Option<CacheInfo>; MAX_CACHES],
+    pub physical_address_bits: u8,
+    pub linear_address_bits: u8,
+    pub features: CpuFeatures,
+    /// What Linux reports to programs as `AT_HWCAP` on x86: EDX of leaf 1
+    pub hwcap: u32,
+}
+
+impl CpuInfo {
+    /// Query CPUID on the running CPU
+    pub fn detect() -> Self {
+        let leaf0 = cpuid(0, 0);
+        let max_leaf = leaf0.eax;
+        let max_extended = cpuid(0x8000_0000, 0).eax;
+        let leaf = |leaf: u32| {
+            let max = if leaf >= 0x8000_0000 {
+                max_extended
+            } else {
+                max_leaf
+            };
+            if leaf <= max {
+                cpuid(leaf, 0)
+            } else {
+                EMPTY
+            }
+        };
+
+        let vendor = Vendor::from_leaf0(leaf0);
+        let basic = leaf(1);
+        let features = CpuFeatures::from_leaves(&Leaves {
+            basic,
+            structured: leaf(7),
+            extended: leaf(0x8000_0001),
+            power: leaf(0x8000_0007),
+        });
+
+        let base_family = (basic.eax >> 8) & 0xF;
+        let base_model = (basic.eax >> 4) & 0xF;
+        let family = if base_family == 0xF {
+            base_family + ((basic.eax >> 20) & 0xFF)
+        } else {
+            base_family
+        };
+        let model = if base_family == 0x6 || base_family == 0xF {
+            base_model | ((basic.eax >> 16) & 0xF) << 4
+        } else {
+            base_model
+        };
+
+        let mut brand = [0; 48];
+        for (i, chunk) in brand.chunks_exact_mut(16).enumerate() {
+            let regs = leaf(0x8000_0002 + i as u32);
+            for (j, reg) in [regs.eax, regs.ebx, regs.ecx, regs.edx].iter().enumerate() {
+                chunk[j * 4..j * 4 + 4].copy_from_slice(&reg.to_le_bytes());
+            }
+        }
+
+        let cache_leaf = match vendor {
+            Vendor::Amd if features.topology_extensions => Some(0x8000_001D),
+            Vendor::Intel if max_leaf >= 4 => Some(4),
+            _ => None,
+        };
+        let mut caches = [None; MAX_CACHES];
+        if let Some(cache_leaf) = cache_leaf {
+            for (i, cache) in caches.iter_mut().enumerate() {
+                *cache = CacheInfo::from_leaf(cpuid(cache_leaf, i as u32));
+                if cache.is_none() {
+                    break;
+                }
+            }
+        }
+
+        let widths = leaf(0x8000_0008).eax;
+        Self {
+            vendor,
+            family,
+            model,
+            stepping: basic.eax & 0xF,
+            brand,
+            caches,
+            physical_address_bits: widths as u8,
+            linear_address_bits: (widths >> 8) as u8,
+            features,
+            hwcap: basic.edx,
+        }
+    }
+
+    /// The marketing name of the CPU
+    pub fn brand(&self) -> &str {
+        let end = self.brand.iter().position(|&b| b == 0).unwrap_or(48);
+        str::from_utf8(&self.brand[..end]).unwrap_or("").trim()
+    }
+
+    /// The caches of the CPU, from the lowest level
+    pub fn caches(&self) -> impl Iterator<Item = &CacheInfo> {
+        self.caches.iter().map_while(Option::as_ref)
+    }
+}
+
+static INFO: Once<CpuInfo> = Once::new();
+
+/// Detect the features of the CPU, and print a summary to serial
+pub fn init() {
+    let info = INFO.call_once(CpuInfo::detect);
+
+    serial_println!(
+        "CPU: {} family {:#x} model {:#x} stepping {}",
+        info.vendor,
+        info.family,
+        info.model,
+        info.stepping
+    );
+    if !info.brand().is_empty() {
+        serial_println!("CPU: {}", info.brand());
+    }
+    for cache in info.caches() {
+        serial_println!("CPU: {}", cache);
+    }
+    serial_println!(
+        "CPU: {} bits physical, {} bits virtual",
+        info.physical_address_bits,
+        info.linear_address_bits
+    );
+    serial_println!("CPU: {}", info.features);
+}
+
+/// The information gathered by [`init`]
+pub fn info() -> &'static CpuInfo {
+    INFO.get().expect("CPU features have not been detected yet")
+}
+
+/// The features of the CPU
+pub fn features() -> &'static CpuFeatures {
+    &info().features
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CacheInfo, CacheKind, CpuFeatures, Leaves, Vendor, EMPTY};
+    use core::arch::x86_64::CpuidResult;
+
+    #[test_case]
+    fn features_from_leaves() {
+        let features = CpuFeatures::from_leaves(&Leaves {
+            basic: CpuidResult {
+                edx: 1 << 9 | 1 << 25,
+                ecx: 1 << 26,
+                ..EMPTY
+            },
+            structured: CpuidResult {
+                ebx: 1 << 20,
+                ..EMPTY
+            },
+            extended: CpuidResult {
+                edx: 1 << 20,
+                ..EMPTY
+            },
+            power: EMPTY,
+        });
+        assert!(features.apic && features.sse && features.xsave);
+        assert!(features.smap && features.nx);
+        assert!(!features.avx && !features.page1gb && !features.invariant_tsc);
+    }
+
+    #[test_case]
+    fn vendor_from_leaf0() {
+        // "GenuineIntel", split across ebx, edx and ecx
+        let leaf = CpuidResult {
+            eax: 0xD,
+            ebx: u32::from_le_bytes(*b"Genu"),
+            edx: u32::from_le_bytes(*b"ineI"),
+            ecx: u32::from_le_bytes(*b"ntel"),
+        };
+        assert_eq!(Vendor::from_leaf0(leaf), Vendor::Intel);
+    }
+
+    #[test_case]
+    fn cache_from_leaf() {
+        // A 32 KiB, 8-way L1 data cache with 64 byte lines, shared by 2
+        let leaf = CpuidResult {
+            eax: 1 | 1 << 5 | 1 << 14,
+            ebx: 63 | 7 << 22,
+            ecx: 63,
+            edx: 0,
+        };
+        let cache = CacheInfo::from_leaf(leaf).unwrap();
+        assert_eq!(cache.kind, CacheKind::Data);
+        assert_eq!(cache.level, 1);
+        assert_eq!(cache.size(), 32 * 1024);
+        assert_eq!(cache.shared_by, 2);
+
+        assert_eq!(CacheInfo::from_leaf(EMPTY), None);
+    }
+}
@@ -0,0 +1,184 @@
+//! Model-specific registers
+//!
+//! Registers with a fixed address implement [`Register`], which ties them
+//! to the type of their value, so that bits are decoded in one place. MSRs
+//! that come in arrays (like the machine-check banks) have helpers to
+//! compute their address, and are accessed with [`read`] and [`write`].
+
+use x86_64::registers::model_specific::Msr;
+
+/// Read an MSR by address
+///
+/// # Safety
+///
+/// The MSR must exist, or `rdmsr` raises #GP.
+pub unsafe fn read(addr: u32) -> u64 {
+    Msr::new(addr).read()
+}
+
+/// Write an MSR by address
+///
+/// # Safety
+///
+/// The MSR must exist, and writing it must not break memory safety.
+pub unsafe fn write(addr: u32, value: u64) {
+    Msr::new(addr).write(value)
+}
+
+/// A model-specific register with a fixed address
+pub trait Register {
+    const ADDR: u32;
+    type Value: From<u64> + Into<u64>;
+
+    /// # Safety
+    ///
+    /// See [`read`].
+    unsafe fn read() -> Self::Value {
+        read(Self::ADDR).into()
+    }
+
+    /// # Safety
+    ///
+    /// See [`write`].
+    unsafe fn write(value: Self::Value) {
+        write(Self::ADDR, value.into())
+    }
+}
+
+macro_rules! registers {
+    ($($(#[$doc: meta])* $name: ident = $addr: expr => $value: ty;)*) => {
+        $(
+            $(#[$doc])*
+            pub struct $name;
+
+            impl Register for $name {
+                const ADDR: u32 = $addr;
+                type Value = $value;
+            }
+        )*
+    };
+}
+
+registers! {
+    /// The time-stamp counter
+    Ia32Tsc = 0x10 => u64;
+    /// The address and mode of the local APIC
+    Ia32ApicBase = 0x1B => ApicBase;
+    /// The machine-check capabilities
+    Ia32McgCap = 0x179 => McgCap;
+    /// The state of the CPU after a machine check
+    Ia32McgStatus = 0x17A => McgStatus;
+    /// Enables machine-check reporting for all banks
+    Ia32McgCtl = 0x17B => u64;
+    /// The page attribute table
+    Ia32Pat = 0x277 => u64;
+    /// The interrupt command register of the local APIC, in x2APIC mode
+    X2ApicIcr = 0x830 => u64;
+}
+
+/// A machine-check bank, whose four MSRs follow each other
+#[derive(Debug, Clone, Copy)]
+pub struct McBank(pub u32);
+
+impl McBank {
+    const BASE: u32 = 0x400;
+
+    pub fn ctl(&self) -> u32 {
+        Self::BASE + 4 * self.0
+    }
+
+    pub fn status(&self) -> u32 {
+        self.ctl() + 1
+    }
+
+    pub fn addr(&self) -> u32 {
+        self.ctl() + 2
+    }
+
+    pub fn misc(&self) -> u32 {
+        self.ctl() + 3
+    }
+}
+
+/// Implement the conversions needed by [`Register::Value`] for a newtype
+macro_rules! value {
+    ($name: ident) => {
+        impl From<u64> for $name {
+            fn from(value: u64) -> Self {
+                Self(value)
+            }
+        }
+
+        impl From<$name> for u64 {
+            fn from(value: $name) -> Self {
+                value.0
+            }
+        }
+    };
+}
+
+/// The value of [`Ia32ApicBase`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApicBase(pub u64);
+value!(ApicBase);
+
+impl ApicBase {
+    /// This is the bootstrap processor
+    pub fn bsp(&self) -> bool {
+        self.0 & 1 << 8 != 0
+    }
+
+    /// The local APIC is in x2APIC mode, and accessed through MSRs
+    pub fn x2apic(&self) -> bool {
+        self.0 & 1 << 10 != 0
+    }
+
+    /// The local APIC is enabled
+    pub fn enabled(&self) -> bool {
+        self.0 & 1 << 11 != 0
+    }
+
+    /// The physical address of the local APIC registers, in xAPIC mode
+    pub fn addr(&self) -> u64 {
+        self.0 & 0x000F_FFFF_FFFF_F000
+    }
+}
+
+/// The value of [`Ia32McgCap`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct McgCap(pub u64);
+value!(McgCap);
+
+impl McgCap {
+    /// The number of machine-check banks
+    pub fn bank_count(&self) -> u32 {
+        (self.0 & 0xFF) as u32
+    }
+
+    /// [`Ia32McgCtl`] is present
+    pub fn ctl_present(&self) -> bool {
+        self.0 & 1 << 8 != 0
+    }
+}
+
+/// The value of [`Ia32McgStatus`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct McgStatus(pub u64);
+value!(McgStatus);
+
+impl McgStatus {
+    /// The interrupted instruction can be restarted
+    pub fn restart_ip_valid(&self) -> bool {
+        self.0 & 1 << 0 != 0
+    }
+
+    /// The error is related to the interrupted instruction
+    pub fn error_ip_valid(&self) -> bool {
+        self.0 & 1 << 1 != 0
+    }
+
+    /// A machine check is in progress
+    pub fn in_progress(&self) -> bool {
+        self.0 & 1 << 2 != 0
+    }
+}
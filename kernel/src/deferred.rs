@@ -0,0 +1,181 @@
+//! Deferred work, run outside of interrupt handlers
+//!
+//! Interrupt handlers should do as little as possible: read the hardware,
+//! and [`schedule`] the rest of the work (the "bottom half"). Scheduled work
+//! is pushed on a lock-free queue, which is drained with interrupts enabled
+//! when the outermost interrupt handler returns, or from the idle loop.
+//!
+//! Since deferred work never runs while interrupts are disabled, it can't
+//! interrupt code holding a lock like the VGA `WRITER`, which is always
+//! taken with interrupts disabled.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crossbeam_queue::ArrayQueue;
+use spin::Once;
+use x86_64::instructions::interrupts;
+
+/// The maximum number of work items waiting to be run
+pub const QUEUE_SIZE: usize = 256;
+
+static QUEUE: Once<ArrayQueue<Work>> = Once::new();
+
+/// Set while the queue is being drained, so that interrupts arriving in
+/// the meantime don't start draining it again
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
+/// The number of work items lost because the queue was full
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy)]
+enum Work {
+    Function(fn(usize), usize),
+    Tasklet(&'static Tasklet),
+}
+
+#[derive(displaydoc::Display, Debug)]
+pub enum DeferError {
+    /// the deferred work queue has not been initialized
+    Uninitialized,
+    /// the deferred work queue is full
+    Full,
+}
+
+/// Allocate the work queue
+///
+/// Must be called after the heap has been initialized
+pub fn init() {
+    QUEUE.call_once(|| ArrayQueue::new(QUEUE_SIZE));
+}
+
+fn push(work: Work) -> Result<(), DeferError> {
+    let queue = QUEUE.get().ok_or(DeferError::Uninitialized)?;
+    queue.push(work).map_err(|_| {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+        DeferError::Full
+    })
+}
+
+/// Run `func(arg)` later, outside of interrupt context
+///
+/// This never blocks, so it's safe to call from interrupt handlers.
+pub fn schedule(func: fn(usize), arg: usize) -> Result<(), DeferError> {
+    push(Work::Function(func, arg))
+}
+
+/// Whether there's any work waiting to be run
+pub fn has_pending() -> bool {
+    QUEUE.get().is_some_and(|queue| !queue.is_empty())
+}
+
+/// The number of work items dropped because the queue was full
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// Run all pending work
+///
+/// Must be called with interrupts enabled. Does nothing if the queue is
+/// already being drained further up the stack.
+pub fn run_pending() {
+    let queue = match QUEUE.get() {
+        Some(queue) => queue,
+        None => return,
+    };
+    if DRAINING.swap(true, Ordering::Acquire) {
+        return;
+    }
+
+    while let Some(work) = queue.pop() {
+        match work {
+            Work::Function(func, arg) => func(arg),
+            Work::Tasklet(tasklet) => {
+                tasklet.scheduled.store(false, Ordering::Release);
+                (tasklet.func)();
+            }
+        }
+    }
+
+    DRAINING.store(false, Ordering::Release);
+}
+
+/// Called by interrupt handlers right before returning
+///
+/// Runs pending work with interrupts enabled, unless this interrupt arrived
+/// while the queue was already being drained.
+pub(crate) fn irq_exit() {
+    if !has_pending() || DRAINING.load(Ordering::Relaxed) {
+        return;
+    }
+
+    interrupts::enable();
+    run_pending();
+    interrupts::disable();
+}
+
+/// A function that runs at most once per scheduling
+///
+/// Scheduling a tasklet that is already pending does nothing, so that
+/// interrupts arriving in bursts don't fill the queue.
+pub struct Tasklet {
+    func: fn(),
+    scheduled: AtomicBool,
+}
+
+impl Tasklet {
+    pub const fn new(func: fn()) -> Self {
+        Self {
+            func,
+            scheduled: AtomicBool::new(false),
+        }
+    }
+
+    /// Run this tasklet later, outside of interrupt context
+    pub fn schedule(&'static self) -> Result<(), DeferError> {
+        if self.scheduled.swap(true, Ordering::AcqRel) {
+            return Ok(());
+        }
+        push(Work::Tasklet(self)).inspect_err(|_| {
+            self.scheduled.store(false, Ordering::Release);
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{run_pending, schedule, Tasklet};
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static SUM: AtomicUsize = AtomicUsize::new(0);
+    static TASKLET_RUNS: AtomicUsize = AtomicUsize::new(0);
+
+    fn add(n: usize) {
+        SUM.fetch_add(n, Ordering::SeqCst);
+    }
+
+    fn count_run() {
+        TASKLET_RUNS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[test_case]
+    fn deferred_function() {
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            schedule(add, 2).unwrap();
+            schedule(add, 3).unwrap();
+        });
+        run_pending();
+        assert_eq!(SUM.load(Ordering::SeqCst), 5);
+    }
+
+    #[test_case]
+    fn tasklet_runs_once() {
+        static TASKLET: Tasklet = Tasklet::new(count_run);
+
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            TASKLET.schedule().unwrap();
+            TASKLET.schedule().unwrap();
+        });
+        run_pending();
+        assert_eq!(TASKLET_RUNS.load(Ordering::SeqCst), 1);
+    }
+}
@@ -1,8 +1,7 @@
 use alloc::{borrow::Cow, vec::Vec};
 
 use crate::delf::{
-    components::{section::SectionHeader, strtab::StrTab},
-    impl_parse_for_enum, parse, Addr,
+    components::strtab::StrTab, errors::GetDynamicEntryError, impl_parse_for_enum, parse, Addr,
 };
 
 use derive_try_from_primitive::TryFromPrimitive;
@@ -11,24 +10,60 @@ use nom::{
     multi::many_till,
 };
 
-#[derive(Copy, Clone)]
-pub struct DynamicSection<'a>(pub &'a SectionHeader<'a>, pub StrTab<'a>);
+/// The dynamic entries of an object, up to the terminating
+/// [`DynamicTag::Null`]
+#[derive(Debug, Clone)]
+pub struct DynamicSection<'a>(pub Vec<DynamicEntry<'a>>);
 
 impl<'a> DynamicSection<'a> {
-    pub fn entry_with_tag(&self, typ: DynamicTag) -> Option<DynamicEntry> {
-        let (_, entries): (_, Vec<DynamicEntry>) = map(
+    /// Parse the entries in `data`
+    ///
+    /// The string table is itself found through a dynamic entry, so strings
+    /// are only read later, by [`resolve_strings`](Self::resolve_strings).
+    pub fn parse(data: &'a [u8]) -> parse::Result<'a, Self> {
+        map(
             many_till(
-                |i| DynamicEntry::parse(i, &self.1),
-                verify(
-                    |i| DynamicEntry::parse(i, &self.1),
-                    |e| e.tag == DynamicTag::Null,
-                ),
+                DynamicEntry::parse,
+                verify(DynamicEntry::parse, |e| e.tag == DynamicTag::Null),
             ),
-            |(entries, _last)| entries,
-        )(self.0.data())
-        .unwrap();
+            |(entries, _last)| Self(entries),
+        )(data)
+    }
+
+    /// Replace the values of the entries that refer to strings (like
+    /// [`DynamicTag::Needed`]) with the strings from `strtab`
+    pub fn resolve_strings(&mut self, strtab: &StrTab<'a>) {
+        use DynamicTag::*;
+        for entry in &mut self.0 {
+            if let AddrOrString::Address(addr) = entry.addr {
+                if matches!(entry.tag, Needed | SoName | RPath | Runpath) {
+                    if let Some(string) = strtab.at(addr) {
+                        entry.addr = AddrOrString::String(string);
+                    }
+                }
+            }
+        }
+    }
 
-        entries.iter().find(|entry| entry.tag == typ).cloned()
+    pub fn entry_with_tag(&self, typ: DynamicTag) -> Option<&DynamicEntry<'a>> {
+        self.0.iter().find(|entry| entry.tag == typ)
+    }
+
+    /// The value of the entry with the given tag, which must not be a string
+    pub fn get(&self, typ: DynamicTag) -> Result<Addr, GetDynamicEntryError> {
+        match self.entry_with_tag(typ) {
+            Some(DynamicEntry {
+                addr: AddrOrString::Address(addr),
+                ..
+            }) => Ok(*addr),
+            _ => Err(GetDynamicEntryError::NotFound(typ)),
+        }
+    }
+
+    /// Every entry with the given tag, in order, since some tags (like
+    /// [`DynamicTag::Needed`]) can appear more than once
+    pub fn entries_with_tag(&self, typ: DynamicTag) -> impl Iterator<Item = &DynamicEntry<'a>> {
+        self.0.iter().filter(move |entry| entry.tag == typ)
     }
 }
 
@@ -40,20 +75,10 @@ pub struct DynamicEntry<'a> {
 }
 
 impl<'a> DynamicEntry<'a> {
-    pub fn parse(i: &'a [u8], strtab: &'a StrTab) -> parse::Result<'a, Self> {
+    pub fn parse(i: &'a [u8]) -> parse::Result<'a, Self> {
         let (i, tag) = DynamicTag::parse(i)?;
         let (i, addr) = Addr::parse(i)?;
-
-        use DynamicTag::*;
-        let addr = if let Some(string) = strtab.at(addr) {
-            if let Needed | SoName | RPath | Runpath = tag {
-                AddrOrString::String(string)
-            } else {
-                AddrOrString::Address(addr)
-            }
-        } else {
-            AddrOrString::Address(addr)
-        };
+        let addr = AddrOrString::Address(addr);
 
         Ok((i, Self { addr, tag }))
     }
@@ -66,12 +91,19 @@ pub enum AddrOrString<'a> {
 }
 
 impl AddrOrString<'_> {
-    pub fn unwrap_string(&self) -> &Cow<str> {
+    pub fn unwrap_string(&self) -> &Cow<'_, str> {
         match self {
             Self::String(i) => i,
             Self::Address(_) => panic!("expected a string but got an address"),
         }
     }
+
+    pub fn unwrap_address(&self) -> Addr {
+        match self {
+            Self::Address(addr) => *addr,
+            Self::String(_) => panic!("expected an address but got a string"),
+        }
+    }
 }
 
 /// The tag of a dynamic entry
@@ -0,0 +1,301 @@
+//! Symbol hash tables, which find dynamic symbols by name
+//!
+//! Both tables map the hash of a name to the indices, in the dynamic symbol
+//! table, of the symbols that may have it. The GNU one also has a bloom
+//! filter, which rules out most of the names that aren't there without
+//! looking at any symbol.
+
+use core::convert::TryInto;
+
+use crate::delf::errors::ReadHashError;
+
+/// A symbol hash table
+#[derive(Debug, Clone, Copy)]
+pub enum HashTable<'a> {
+    /// The one pointed to by `DT_HASH`
+    Sysv(SysvHash<'a>),
+    /// The one pointed to by `DT_GNU_HASH`
+    Gnu(GnuHash<'a>),
+}
+
+impl HashTable<'_> {
+    /// The index of the symbol called `name`
+    ///
+    /// Different names can have the same hash, so `is_match` is called with
+    /// the index of each candidate, and must tell whether that symbol is the
+    /// right one.
+    pub fn lookup(&self, name: &str, is_match: impl FnMut(u32) -> bool) -> Option<u32> {
+        match self {
+            Self::Sysv(table) => table.lookup(name, is_match),
+            Self::Gnu(table) => table.lookup(name, is_match),
+        }
+    }
+
+    /// The number of symbols in the symbol table
+    ///
+    /// Symbol tables don't store their size, so the dynamic linker relies
+    /// on this.
+    pub fn symbol_count(&self) -> Option<usize> {
+        match self {
+            Self::Sysv(table) => Some(table.symbol_count()),
+            Self::Gnu(table) => table.symbol_count(),
+        }
+    }
+}
+
+/// The SysV hash function
+pub fn sysv_hash(name: &str) -> u32 {
+    let mut hash = 0u32;
+    for &byte in name.as_bytes() {
+        hash = (hash << 4).wrapping_add(byte as u32);
+        let high = hash & 0xF000_0000;
+        hash ^= high >> 24;
+        hash &= !high;
+    }
+    hash
+}
+
+/// The GNU hash function (Bernstein's hash)
+pub fn gnu_hash(name: &str) -> u32 {
+    name.as_bytes().iter().fold(5381u32, |hash, &byte| {
+        hash.wrapping_mul(33).wrapping_add(byte as u32)
+    })
+}
+
+/// A SysV hash table
+///
+/// A name's hash picks a bucket, which holds the index of the first
+/// candidate symbol. Each symbol's chain entry holds the index of the next
+/// one, until index 0.
+#[derive(Debug, Clone, Copy)]
+pub struct SysvHash<'a> {
+    buckets: &'a [u8],
+    chains: &'a [u8],
+}
+
+impl<'a> SysvHash<'a> {
+    /// Parse a table, which starts at `data`
+    pub fn parse(data: &'a [u8]) -> Result<Self, ReadHashError> {
+        let parse = || {
+            let nbucket = word(data, 0)? as usize;
+            let nchain = word(data, 1)? as usize;
+            let buckets = data.get(8..)?.get(..4 * nbucket)?;
+            let chains = data.get(8 + 4 * nbucket..)?.get(..4 * nchain)?;
+            Some(Self { buckets, chains })
+        };
+        let table = parse().ok_or(ReadHashError::Truncated)?;
+        if table.buckets.is_empty() {
+            return Err(ReadHashError::NoBuckets);
+        }
+        Ok(table)
+    }
+
+    /// The number of symbols in the symbol table
+    pub fn symbol_count(&self) -> usize {
+        self.chains.len() / 4
+    }
+
+    fn lookup(&self, name: &str, mut is_match: impl FnMut(u32) -> bool) -> Option<u32> {
+        let bucket = sysv_hash(name) as usize % (self.buckets.len() / 4);
+        let mut index = word(self.buckets, bucket)?;
+        // Every chain ends, unless the table is corrupt
+        for _ in 0..self.symbol_count() {
+            if index == 0 {
+                return None;
+            }
+            if is_match(index) {
+                return Some(index);
+            }
+            index = word(self.chains, index as usize)?;
+        }
+        None
+    }
+}
+
+/// A GNU hash table
+///
+/// Only the symbols from `symoffset` on are in the table, sorted by bucket.
+/// A name's hash picks a bucket, which holds the index of the first symbol in
+/// it. The chain entries are the hashes of the symbols, with the lowest bit
+/// set on the last one of each bucket.
+#[derive(Debug, Clone, Copy)]
+pub struct GnuHash<'a> {
+    symoffset: u32,
+    bloom_shift: u32,
+    bloom: &'a [u8],
+    buckets: &'a [u8],
+    /// Everything after the buckets, since the number of chain entries is
+    /// not stored anywhere
+    chains: &'a [u8],
+}
+
+impl<'a> GnuHash<'a> {
+    /// Parse a table, which starts at `data`
+    pub fn parse(data: &'a [u8]) -> Result<Self, ReadHashError> {
+        let header = |index| word(data, index).ok_or(ReadHashError::Truncated);
+        let nbuckets = header(0)? as usize;
+        let symoffset = header(1)?;
+        let bloom_size = header(2)? as usize;
+        let bloom_shift = header(3)?;
+        if nbuckets == 0 || bloom_size == 0 {
+            return Err(ReadHashError::NoBuckets);
+        }
+        // The shift is applied to a 32-bit hash
+        if bloom_shift >= 32 {
+            return Err(ReadHashError::InvalidBloomShift(bloom_shift));
+        }
+
+        let parse = || {
+            let data = data.get(16..)?;
+            let bloom = data.get(..8 * bloom_size)?;
+            let data = data.get(8 * bloom_size..)?;
+            let buckets = data.get(..4 * nbuckets)?;
+            let chains = data.get(4 * nbuckets..)?;
+            Some(Self {
+                symoffset,
+                bloom_shift,
+                bloom,
+                buckets,
+                chains,
+            })
+        };
+        parse().ok_or(ReadHashError::Truncated)
+    }
+
+    /// The number of symbols in the symbol table, which ends with the last
+    /// chain of the last non-empty bucket
+    pub fn symbol_count(&self) -> Option<usize> {
+        let last = (0..self.buckets.len() / 4)
+            .filter_map(|bucket| word(self.buckets, bucket))
+            .max()?;
+        if last < self.symoffset {
+            return Some(self.symoffset as usize);
+        }
+
+        let mut index = last;
+        loop {
+            let chain_hash = word(self.chains, (index - self.symoffset) as usize)?;
+            if chain_hash & 1 == 1 {
+                return Some(index as usize + 1);
+            }
+            index += 1;
+        }
+    }
+
+    fn lookup(&self, name: &str, mut is_match: impl FnMut(u32) -> bool) -> Option<u32> {
+        let hash = gnu_hash(name);
+
+        // Two bits of the hash must be set in the filter, or the name isn't
+        // in the table
+        let bloom_index = (hash / 64) as usize % (self.bloom.len() / 8);
+        let bloom_word = u64::from_le_bytes(
+            self.bloom[8 * bloom_index..8 * bloom_index + 8]
+                .try_into()
+                .unwrap(),
+        );
+        let mask = (1 << (hash % 64)) | (1 << ((hash >> self.bloom_shift) % 64));
+        if bloom_word & mask != mask {
+            return None;
+        }
+
+        let mut index = word(self.buckets, hash as usize % (self.buckets.len() / 4))?;
+        if index < self.symoffset {
+            return None;
+        }
+        loop {
+            let chain_hash = word(self.chains, (index - self.symoffset) as usize)?;
+            if hash | 1 == chain_hash | 1 && is_match(index) {
+                return Some(index);
+            }
+            if chain_hash & 1 == 1 {
+                return None;
+            }
+            index += 1;
+        }
+    }
+}
+
+/// The `index`-th 32-bit word of `data`
+fn word(data: &[u8], index: usize) -> Option<u32> {
+    let bytes = data.get(4 * index..4 * index + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{gnu_hash, sysv_hash, GnuHash, HashTable, SysvHash};
+    use crate::delf::{components::dynamic::DynamicTag, errors::ReadHashError, ParsedElf};
+    use alloc::vec::Vec;
+
+    /// A library with both kinds of tables, built from `fixtures/syms.S`
+    static LIBSYMS: &[u8] = include_bytes!("../../../tests/fixtures/libsyms.so");
+
+    #[test_case]
+    fn hash_functions() {
+        let cases = [
+            ("", 0x0000_0000, 0x0000_1505),
+            ("exit", 0x0006_cf04, 0x7c96_7e3f),
+            ("printf", 0x0779_05a6, 0x156b_2bb8),
+            ("_start", 0x066a_a894, 0xeddb_6232),
+            ("syscall_write_with_a_long_name", 0x06c3_b7b5, 0xda07_4274),
+        ];
+        for &(name, sysv, gnu) in cases.iter() {
+            assert_eq!(sysv_hash(name), sysv, "SysV hash of {:?}", name);
+            assert_eq!(gnu_hash(name), gnu, "GNU hash of {:?}", name);
+        }
+    }
+
+    #[test_case]
+    fn both_tables_find_every_defined_symbol() {
+        let file = ParsedElf::parse_or_print_error(LIBSYMS).unwrap();
+        let syms = file.dynamic_symbols().unwrap().syms().unwrap();
+        let dynamic = file.dynamic_section().unwrap().unwrap();
+        let table = |tag| {
+            let addr = dynamic.entry_with_tag(tag).unwrap().addr.unwrap_address();
+            file.data_at_addr(addr).unwrap()
+        };
+        let tables = [
+            HashTable::Sysv(SysvHash::parse(table(DynamicTag::Hash)).unwrap()),
+            HashTable::Gnu(GnuHash::parse(table(DynamicTag::GnuHash)).unwrap()),
+        ];
+
+        let defined: Vec<_> = syms.iter().filter(|sym| !sym.shndx.is_undef()).collect();
+        assert_eq!(defined.len(), 10);
+        for table in tables.iter() {
+            assert_eq!(table.symbol_count(), Some(syms.len()));
+            for sym in &defined {
+                let index = table
+                    .lookup(&sym.name, |i| syms[i as usize].name == sym.name)
+                    .unwrap();
+                assert_eq!(syms[index as usize].value, sym.value);
+            }
+            for &name in ["iota", "missing", ""].iter() {
+                let found = table.lookup(name, |i| {
+                    let sym = &syms[i as usize];
+                    sym.name == name && !sym.shndx.is_undef()
+                });
+                assert_eq!(found, None, "{:?} was found", name);
+            }
+        }
+    }
+
+    #[test_case]
+    fn malformed_gnu_tables_are_rejected() {
+        // One bucket, a bloom filter of one word and one chain entry
+        let mut table = [0u8; 32];
+        for (i, &word) in [1u32, 1, 1, 6].iter().enumerate() {
+            table[4 * i..4 * i + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        assert!(GnuHash::parse(&table).is_ok());
+        assert!(matches!(
+            GnuHash::parse(&table[..24]),
+            Err(ReadHashError::Truncated)
+        ));
+
+        table[12..16].copy_from_slice(&32u32.to_le_bytes());
+        assert!(matches!(
+            GnuHash::parse(&table),
+            Err(ReadHashError::InvalidBloomShift(32))
+        ));
+    }
+}
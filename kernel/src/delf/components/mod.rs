@@ -1,6 +1,7 @@
 //! Components of an ELF file
 
 pub mod dynamic;
+pub mod hash;
 pub mod rela;
 pub mod section;
 pub mod segment;
@@ -1,35 +1,30 @@
 //! Utilities related to parsing of relocations
 
+use alloc::format;
+
 use derive_try_from_primitive::TryFromPrimitive;
 use nom::number::complete::le_u32;
 
 use crate::delf::{impl_parse_for_enum, parse, Addr};
 
-use super::{
-    section::SectionHeader,
-    sym::{Sym, SymTab},
-};
+use super::sym::{Sym, SymTab};
 
+/// Bytes that are to be interpreted as a table of relocations, with the
+/// symbol table they refer to
 #[derive(Clone, Copy)]
-pub struct RelaTable<'a>(pub &'a SectionHeader<'a>, pub SymTab<'a>);
+pub struct RelaTable<'a>(pub &'a [u8], pub SymTab<'a>);
 
 impl<'a> RelaTable<'a> {
-    pub fn rela_index(&'a self, index: usize) -> Option<Rela> {
-        let pos = (Rela::SIZE * index) as u64;
-
-        let data = self.0.data_at(Addr(pos))?;
+    pub fn rela_index(&self, index: usize) -> Option<Rela<'a>> {
+        let data = self.0.get(Rela::SIZE * index..)?;
         let rela = Rela::parse(data, &self.1).ok()?.1;
 
         Some(rela)
     }
 
-    pub fn iter(&'a self) -> impl Iterator<Item = Rela<'a>> {
-        (0..self.0.data().len() / Rela::SIZE)
-            .map(|i| Rela::SIZE * i)
-            .map(move |i| {
-                let data = self.0.data();
-                &data[i..Rela::SIZE + i]
-            })
+    pub fn iter(self) -> impl Iterator<Item = Rela<'a>> {
+        self.0
+            .chunks_exact(Rela::SIZE)
             .flat_map(move |slice| Rela::parse(slice, &self.1))
             .map(|(_, rela)| rela)
     }
@@ -47,15 +42,13 @@ pub struct Rela<'a> {
 impl<'a> Rela<'a> {
     pub const SIZE: usize = 24;
 
-    pub fn parse(i: parse::Input<'a>, symtab: &'a SymTab<'a>) -> parse::Result<'a, Self> {
-        let (i, offset) = Addr::parse(i)?;
+    pub fn parse(full_input: parse::Input<'a>, symtab: &SymTab<'a>) -> parse::Result<'a, Self> {
+        let (i, offset) = Addr::parse(full_input)?;
         let (i, typ) = RelocationType::parse(i)?;
         let (i, sym) = le_u32(i)?;
         let (i, addend) = Addr::parse(i)?;
 
-        let sym = symtab
-            .sym_index(sym as usize)
-            .expect("Symbol index not found");
+        let sym = symbol(full_input, symtab, sym)?;
         Ok((
             i,
             Rela {
@@ -79,26 +72,47 @@ pub struct Rel<'a> {
 impl<'a> Rel<'a> {
     pub const SIZE: usize = 16;
 
-    pub fn parse(i: parse::Input<'a>, symtab: &'a SymTab) -> parse::Result<'a, Self> {
-        let (i, offset) = Addr::parse(i)?;
+    pub fn parse(full_input: parse::Input<'a>, symtab: &SymTab<'a>) -> parse::Result<'a, Self> {
+        let (i, offset) = Addr::parse(full_input)?;
         let (i, typ) = RelocationType::parse(i)?;
         let (i, sym) = le_u32(i)?;
 
-        let sym = symtab.sym_index(sym as usize).unwrap();
+        let sym = symbol(full_input, symtab, sym)?;
 
         Ok((i, Rel { offset, typ, sym }))
     }
 }
 
+/// Reads the symbol a relocation refers to
+fn symbol<'a>(
+    full_input: parse::Input<'a>,
+    symtab: &SymTab<'a>,
+    index: u32,
+) -> Result<Sym<'a>, nom::Err<parse::Error<parse::Input<'a>>>> {
+    symtab.sym_index(index as usize).ok_or_else(|| {
+        nom::Err::Failure(parse::Error::from_string(
+            full_input,
+            format!("Symbol {} not found", index),
+        ))
+    })
+}
+
 /// The type of a relocation
 #[repr(u32)]
 #[derive(Debug, TryFromPrimitive, Clone, Copy, PartialEq, Eq)]
 pub enum RelocationType {
     _64 = 1,
+    Pc32 = 2,
+    Plt32 = 4,
     Copy = 5,
     GlobDat = 6,
     JumpSlot = 7,
     Relative = 8,
+    GotPcRel = 9,
+    _32 = 10,
+    _32S = 11,
     IRelative = 37,
+    GotPcRelX = 41,
+    RexGotPcRelX = 42,
 }
 impl_parse_for_enum!(RelocationType, le_u32);
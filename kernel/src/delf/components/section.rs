@@ -11,6 +11,13 @@ use nom::{
 
 use crate::delf::{impl_parse_for_enum, parse, Addr};
 
+/// The section is writable at run time
+pub const SHF_WRITE: u64 = 0x1;
+/// The section occupies memory at run time
+pub const SHF_ALLOC: u64 = 0x2;
+/// The section holds code
+pub const SHF_EXECINSTR: u64 = 0x4;
+
 /// An header for a section
 #[derive(Debug, Clone)]
 pub struct SectionHeader<'a> {
@@ -86,6 +93,18 @@ impl SectionIndex {
         self.0 >= 0xff00
     }
 
+    /// Whether the symbol has an absolute value, which doesn't move with
+    /// the object (`SHN_ABS`)
+    pub fn is_abs(&self) -> bool {
+        self.0 == 0xfff1
+    }
+
+    /// Whether the symbol is a common block, which the linker has yet to
+    /// allocate (`SHN_COMMON`)
+    pub fn is_common(&self) -> bool {
+        self.0 == 0xfff2
+    }
+
     pub fn get(&self) -> Option<usize> {
         if self.is_undef() || self.is_special() {
             None
@@ -25,12 +25,12 @@ pub struct ProgramHeader<'a> {
     pub filesz: Addr,
     pub memsz: Addr,
     pub align: Addr,
-    pub data: Vec<u8>,
+    pub data: &'a [u8],
 
     pub contents: SegmentContents<'a>,
 }
 
-impl ProgramHeader<'_> {
+impl<'a> ProgramHeader<'a> {
     /// Get the range where segment data is located in the file
     pub fn file_range(&self) -> Range<Addr> {
         self.offset..self.offset + self.filesz
@@ -42,9 +42,9 @@ impl ProgramHeader<'_> {
     }
 
     /// Parse the program header
-    pub fn parse<'a>(full_input: parse::Input<'a>, i: parse::Input<'a>) -> parse::Result<'a, Self> {
+    pub fn parse(full_input: parse::Input<'a>, i: parse::Input<'a>) -> parse::Result<'a, Self> {
         let (i, r#type) = SegmentType::parse(i)?;
-        let (i, flags): _ = SegmentFlag::parse(i)?;
+        let (i, flags) = SegmentFlag::parse(i)?;
 
         let (i, offset) = Addr::parse(i)?;
         let (i, vaddr) = Addr::parse(i)?;
@@ -75,7 +75,7 @@ impl ProgramHeader<'_> {
             filesz,
             memsz,
             align,
-            data: slice.to_vec(),
+            data: slice,
             contents,
         };
         Ok((i, res))
@@ -90,7 +90,7 @@ impl fmt::Debug for ProgramHeader<'_> {
             self.file_range(),
             self.mem_range(),
             self.align,
-            &[
+            [
                 (SegmentFlag::Read, "R"),
                 (SegmentFlag::Write, "W"),
                 (SegmentFlag::Execute, "X")
@@ -1,18 +1,18 @@
 use alloc::{borrow::Cow, string::String};
 
-use crate::delf::{Addr, components::section::SectionHeader};
+use crate::delf::Addr;
 
-/// A section whose content is to be interpreted as a symbol table
+/// Bytes that are to be interpreted as a string table
 ///
-/// An instance can be constructed with [`crate::ParsedElf::strtab`]
+/// An instance can be constructed with [`crate::delf::ParsedElf::strtab`]
 #[derive(Debug, Copy, Clone)]
-pub struct StrTab<'a>(pub &'a SectionHeader<'a>);
+pub struct StrTab<'a>(pub &'a [u8]);
 
 impl<'a> StrTab<'a> {
     /// Read the string at the given offset
-    pub fn at(&self, offset: Addr) -> Option<Cow<str>> {
+    pub fn at(&self, offset: Addr) -> Option<Cow<'a, str>> {
         self.0
-            .data_at(offset)?
+            .get(offset.0 as usize..)?
             .split(|&i| i == 0)
             .next()
             .map(|string| String::from_utf8_lossy(string))
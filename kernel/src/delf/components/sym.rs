@@ -1,6 +1,6 @@
 //! Utilities related to parsing of the symbol table
 
-use alloc::{borrow::Cow, vec::Vec};
+use alloc::{borrow::Cow, format, vec::Vec};
 
 use derive_try_from_primitive::TryFromPrimitive;
 use nom::{
@@ -9,29 +9,29 @@ use nom::{
     sequence::tuple,
 };
 
-use crate::delf::{parse, Addr};
+use crate::delf::{errors::ReadSymsError, parse, Addr};
 
-use super::{
-    section::{SectionHeader, SectionIndex},
-    strtab::StrTab,
-};
+use super::{section::SectionIndex, strtab::StrTab};
 
+/// Bytes that are to be interpreted as a symbol table, with the string table
+/// holding the names of the symbols
 #[derive(Clone, Copy)]
-pub struct SymTab<'a>(pub &'a SectionHeader<'a>, pub StrTab<'a>);
+pub struct SymTab<'a>(pub &'a [u8], pub StrTab<'a>);
 
 impl<'a> SymTab<'a> {
-    pub fn sym_index(&self, index: usize) -> Option<Sym> {
-        let data = self.0.data_at(Addr((24 * index) as _))?;
+    pub fn sym_index(&self, index: usize) -> Option<Sym<'a>> {
+        let data = self.0.get(Sym::SIZE * index..)?;
         let sym = Sym::parse(&self.1, data).ok()?;
         Some(sym.1)
     }
 
-    pub fn syms(&self) -> Vec<Sym> {
-        let data = self.0.data();
+    pub fn syms(&self) -> Result<Vec<Sym<'a>>, ReadSymsError> {
+        let data = self.0;
         let n = data.len() / Sym::SIZE;
 
-        let (_, res) = nom::multi::many_m_n(n, n, |i| Sym::parse(&self.1, i))(data).unwrap();
-        res
+        let (_, res) = nom::multi::many_m_n(n, n, |i| Sym::parse(&self.1, i))(data)
+            .map_err(|err| ReadSymsError::ParsingError(format!("{:?}", err)))?;
+        Ok(res)
     }
 }
 
@@ -42,6 +42,7 @@ pub enum SymBind {
     Local = 0,
     Global = 1,
     Weak = 2,
+    GnuUnique = 10,
 }
 
 /// The type of a symbol
@@ -53,7 +54,9 @@ pub enum SymType {
     Func = 2,
     Section = 3,
     File = 4,
-    Unknown1 = 10,
+    Common = 5,
+    Tls = 6,
+    GnuIFunc = 10,
 }
 
 crate::impl_parse_for_bitenum!(SymBind, 4_usize);
@@ -73,7 +76,7 @@ pub struct Sym<'a> {
 impl<'a> Sym<'a> {
     pub const SIZE: usize = 24;
 
-    pub fn parse(strtab: &'a StrTab, i: &'a [u8]) -> parse::Result<'a, Self> {
+    pub fn parse(strtab: &StrTab<'a>, full_input: &'a [u8]) -> parse::Result<'a, Self> {
         use nom::bits::bits;
 
         let (i, (name, (bind, r#type), _reserved, shndx, value, size)) = tuple((
@@ -83,8 +86,13 @@ impl<'a> Sym<'a> {
             map(le_u16, SectionIndex),
             Addr::parse,
             le_u64,
-        ))(i)?;
-        let name = strtab.at(name).unwrap();
+        ))(full_input)?;
+        let name = strtab.at(name).ok_or_else(|| {
+            nom::Err::Failure(parse::Error::from_string(
+                full_input,
+                format!("Symbol name {:?} is out of the string table", name),
+            ))
+        })?;
 
         let res = Self {
             name,
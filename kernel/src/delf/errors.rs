@@ -11,10 +11,16 @@ pub enum ReadRelaError {
     DynamicEntryNotFound(GetDynamicEntryError),
     /// Dynamic section not found
     DynamicSectionNotFound,
+    /// Could not read the dynamic entries: {0}
+    ReadDynamicError(ReadDynamicError),
     /// Object file does not contain a `SHT_RELA` section
     RelaSegmentNotFound,
     /// Object file does not contain a `SHT_REL` section
     RelSegmentNotFound,
+    /// Only `DT_RELA` relocations are supported, but `DT_PLTREL` is {0}
+    UnsupportedPltRel(u64),
+    /// Could not read the symbols relocations refer to: {0}
+    ReadSymsError(ReadSymsError),
     /// Parsing error: {0}
     ParsingError(String),
 }
@@ -36,15 +42,41 @@ pub enum GetStringError {
     StringNotFound,
 }
 
+/// An error that occurred while trying to read the dynamic entries
+#[derive(Display, Debug)]
+pub enum ReadDynamicError {
+    /// Dynamic segment does not fit in the file
+    Truncated,
+    /// Parsing error: {0}
+    ParsingError(String),
+}
+
+/// An error that occurred while trying to read a symbol hash table
+#[derive(Display, Debug)]
+pub enum ReadHashError {
+    /// Hash table is truncated
+    Truncated,
+    /// Hash table has no buckets
+    NoBuckets,
+    /// Bloom filter shift {0} is not less than 32
+    InvalidBloomShift(u32),
+}
+
 /// An error that occurred while trying to read symbols
 #[derive(Display, Debug)]
 pub enum ReadSymsError {
     /// Dynamic entry not found: {0}
     DynamicEntryNotFound(GetDynamicEntryError),
+    /// Could not read the dynamic entries: {0}
+    ReadDynamicError(ReadDynamicError),
     /// SymTab section not found
     SymTabSectionNotFound,
     /// SymTab segment not found
     SymTabSegmentNotFound,
+    /// Symbol count unknown, since there is no hash table
+    UnknownSymbolCount,
+    /// Could not read the symbol hash table: {0}
+    ReadHashError(ReadHashError),
     /// Parsing error: {0}
     ParsingError(String),
 }
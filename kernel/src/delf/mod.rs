@@ -4,18 +4,24 @@ pub mod components;
 pub mod errors;
 pub mod parse;
 pub mod process;
+pub mod relocation;
+pub mod stack;
 
 use components::{
-    dynamic::DynamicSection,
+    dynamic::{DynamicSection, DynamicTag},
+    hash::{GnuHash, HashTable, SysvHash},
     rela::RelaTable,
     section::{SectionHeader, SectionType},
-    segment::ProgramHeader,
+    segment::{ProgramHeader, SegmentType},
     strtab::StrTab,
-    sym::SymTab,
+    sym::{Sym, SymTab},
 };
+use errors::{GetDynamicEntryError, ReadDynamicError, ReadHashError, ReadRelaError, ReadSymsError};
 
 use core::fmt::{self, Write};
 use alloc::{
+    format,
+    vec,
     vec::Vec,
     string::String,
 };
@@ -47,7 +53,7 @@ pub struct ParsedElf<'a> {
 
 impl<'a> ParsedElf<'a> {
     /// Parse an Elf file given some bytes
-    pub fn parse(input: parse::Input<'a>) -> parse::Result<Self> {
+    pub fn parse(input: parse::Input<'a>) -> parse::Result<'a, Self> {
         let (i, (elf_header, program_headers, section_headers)) = ElfHeader::parse(input)?;
 
         let res = Self {
@@ -86,43 +92,182 @@ impl<'a> ParsedElf<'a> {
             .map(|(i, _)| i)
     }
 
-    pub fn strtab(&self, index: usize) -> Option<StrTab> {
-        if self.section_headers[index].typ == SectionType::StrTab {
-            Some(StrTab(&self.section_headers[index]))
+    pub fn strtab(&self, index: usize) -> Option<StrTab<'a>> {
+        let sh = self.section_headers.get(index)?;
+        if sh.typ == SectionType::StrTab {
+            Some(StrTab(sh.data()))
         } else {
             None
         }
     }
 
-    pub fn symtab(&self, index: usize) -> Option<SymTab> {
-        if let SectionType::SymTab | SectionType::DynSym = self.section_headers[index].typ {
-            let symtab = &self.section_headers[index];
-            let strtab = self
-                .strtab(self.section_headers[index].link as usize)
-                .unwrap();
-            Some(SymTab(symtab, strtab))
+    pub fn symtab(&self, index: usize) -> Option<SymTab<'a>> {
+        let sh = self.section_headers.get(index)?;
+        if let SectionType::SymTab | SectionType::DynSym = sh.typ {
+            Some(SymTab(sh.data(), self.strtab(sh.link as usize)?))
         } else {
             None
         }
     }
 
-    pub fn rela(&self, index: usize) -> Option<RelaTable> {
-        let sh = &self.section_headers[index];
+    pub fn rela(&self, index: usize) -> Option<RelaTable<'a>> {
+        let sh = self.section_headers.get(index)?;
         if let SectionType::Rela = sh.typ {
-            Some(RelaTable(
-                &self.section_headers[index],
-                self.symtab(sh.link as usize)?,
-            ))
+            Some(RelaTable(sh.data(), self.symtab(sh.link as usize)?))
         } else {
             None
         }
     }
 
-    pub fn dynamic_section(&self) -> Option<DynamicSection> {
-        self.section_headers
+    /// The dynamic entries, from the `PT_DYNAMIC` segment
+    ///
+    /// Like everything the dynamic linker uses, they don't depend on the
+    /// section headers, which stripped objects may not have.
+    pub fn dynamic_section(&self) -> Result<Option<DynamicSection<'a>>, ReadDynamicError> {
+        let ph = match self
+            .program_headers
             .iter()
-            .find(|sh| sh.typ == SectionType::Dynamic)
-            .map(|sh| DynamicSection(sh, self.strtab(sh.link as usize).unwrap()))
+            .find(|ph| ph.typ == SegmentType::Dynamic)
+        {
+            Some(ph) => ph,
+            None => return Ok(None),
+        };
+        let data = self
+            .full_content
+            .get(ph.offset.into()..(ph.offset + ph.filesz).into())
+            .ok_or(ReadDynamicError::Truncated)?;
+        let (_, mut dynamic) = DynamicSection::parse(data)
+            .map_err(|err| ReadDynamicError::ParsingError(format!("{:?}", err)))?;
+        let strtab = self.dynamic_strtab(&dynamic);
+        dynamic.resolve_strings(&strtab);
+        Ok(Some(dynamic))
+    }
+
+    /// The string table of the dynamic entries, which is found through the
+    /// entries themselves
+    fn dynamic_strtab(&self, dynamic: &DynamicSection<'a>) -> StrTab<'a> {
+        let strtab = dynamic
+            .get(DynamicTag::StrTab)
+            .ok()
+            .zip(dynamic.get(DynamicTag::StrSz).ok())
+            .and_then(|(addr, size)| self.data_at_addr(addr)?.get(..size.into()))
+            .unwrap_or_default();
+        StrTab(strtab)
+    }
+
+    /// The symbol table used for dynamic linking, from `DT_SYMTAB`
+    ///
+    /// Its size is not recorded anywhere, so it's taken from the hash table.
+    pub fn dynamic_symbols(&self) -> Result<SymTab<'a>, ReadSymsError> {
+        let dynamic = self
+            .dynamic_section()
+            .map_err(ReadSymsError::ReadDynamicError)?
+            .ok_or(ReadSymsError::DynamicEntryNotFound(
+                GetDynamicEntryError::NotFound(DynamicTag::SymTab),
+            ))?;
+        let addr = dynamic
+            .get(DynamicTag::SymTab)
+            .map_err(ReadSymsError::DynamicEntryNotFound)?;
+        if let Ok(size) = dynamic.get(DynamicTag::SymEnt) {
+            if size.0 as usize != Sym::SIZE {
+                return Err(ReadSymsError::ParsingError(format!(
+                    "unsupported symbol size {}",
+                    size.0
+                )));
+            }
+        }
+
+        let count = self
+            .hash_table(&dynamic)
+            .map_err(ReadSymsError::ReadHashError)?
+            .ok_or(ReadSymsError::UnknownSymbolCount)?
+            .symbol_count()
+            .ok_or(ReadSymsError::UnknownSymbolCount)?;
+        let data = self
+            .data_at_addr(addr)
+            .and_then(|data| data.get(..count * Sym::SIZE))
+            .ok_or(ReadSymsError::SymTabSegmentNotFound)?;
+        Ok(SymTab(data, self.dynamic_strtab(&dynamic)))
+    }
+
+    /// The relocations the dynamic linker must apply: first the ones from
+    /// `DT_RELA`, then the ones for the PLT, from `DT_JMPREL`
+    pub fn dynamic_relocations(&self) -> Result<Vec<RelaTable<'a>>, ReadRelaError> {
+        let dynamic = match self
+            .dynamic_section()
+            .map_err(ReadRelaError::ReadDynamicError)?
+        {
+            Some(dynamic) => dynamic,
+            None => return Ok(vec![]),
+        };
+        if let Ok(kind) = dynamic.get(DynamicTag::PltRel) {
+            if kind.0 != DynamicTag::Rela as u64 {
+                return Err(ReadRelaError::UnsupportedPltRel(kind.0));
+            }
+        }
+
+        let tables = [
+            (DynamicTag::Rela, DynamicTag::RelaSz),
+            (DynamicTag::JmpRel, DynamicTag::PltRelSz),
+        ];
+        let mut data = Vec::new();
+        for &(addr, size) in tables.iter() {
+            let addr = match dynamic.get(addr) {
+                Ok(addr) => addr,
+                Err(_) => continue,
+            };
+            let size = dynamic
+                .get(size)
+                .map_err(ReadRelaError::DynamicEntryNotFound)?;
+            data.push(
+                self.data_at_addr(addr)
+                    .and_then(|data| data.get(..size.into()))
+                    .ok_or(ReadRelaError::RelaSegmentNotFound)?,
+            );
+        }
+        if data.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let symtab = self
+            .dynamic_symbols()
+            .map_err(ReadRelaError::ReadSymsError)?;
+        Ok(data
+            .into_iter()
+            .map(|data| RelaTable(data, symtab))
+            .collect())
+    }
+
+    /// The symbol hash table, preferring the GNU one if there are both
+    pub fn hash_table(
+        &self,
+        dynamic: &DynamicSection<'a>,
+    ) -> Result<Option<HashTable<'a>>, ReadHashError> {
+        let table = |tag| {
+            let addr = dynamic.get(tag).ok()?;
+            Some(self.data_at_addr(addr).ok_or(ReadHashError::Truncated))
+        };
+
+        if let Some(data) = table(DynamicTag::GnuHash) {
+            return Ok(Some(HashTable::Gnu(GnuHash::parse(data?)?)));
+        }
+        if let Some(data) = table(DynamicTag::Hash) {
+            return Ok(Some(HashTable::Sysv(SysvHash::parse(data?)?)));
+        }
+        Ok(None)
+    }
+
+    /// The contents of the file that get loaded at `addr` (before
+    /// relocation), up to the end of their segment
+    pub fn data_at_addr(&self, addr: Addr) -> Option<&'a [u8]> {
+        let ph = self
+            .program_headers
+            .iter()
+            .filter(|ph| ph.typ == SegmentType::Load)
+            .find(|ph| ph.vaddr <= addr && addr < ph.vaddr + ph.filesz)?;
+        let start = ph.offset + (addr - ph.vaddr);
+        self.full_content
+            .get(start.into()..(ph.offset + ph.filesz).into())
     }
 }
 
@@ -177,18 +322,24 @@ impl ElfHeader {
         let (i, sh_count) = u16_usize(i)?;
         let (i, sh_strtab) = u16_usize(i)?;
 
-        let ph_slices = full_input[ph_offset.into()..].chunks(ph_entsize);
+        // Relocatable objects have no program headers
         let mut program_headers = Vec::new();
-        for ph_slice in ph_slices.take(ph_count) {
-            let (_, ph) = ProgramHeader::parse(full_input, ph_slice)?;
-            program_headers.push(ph);
+        if ph_count > 0 {
+            let ph_slices = full_input[ph_offset.into()..].chunks(ph_entsize);
+            for ph_slice in ph_slices.take(ph_count) {
+                let (_, ph) = ProgramHeader::parse(full_input, ph_slice)?;
+                program_headers.push(ph);
+            }
         }
 
-        let sh_slices = (&full_input[sh_offset.into()..]).chunks(sh_entsize);
+        // Stripped objects may have no section headers at all
         let mut section_headers = Vec::new();
-        for sh_slice in sh_slices.take(sh_count) {
-            let (_, sh) = SectionHeader::parse(full_input, sh_slice)?;
-            section_headers.push(sh);
+        if sh_count > 0 {
+            let sh_slices = full_input[sh_offset.into()..].chunks(sh_entsize);
+            for sh_slice in sh_slices.take(sh_count) {
+                let (_, sh) = SectionHeader::parse(full_input, sh_slice)?;
+                section_headers.push(sh);
+            }
         }
 
         let file_header = Self {
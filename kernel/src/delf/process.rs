@@ -1,23 +1,36 @@
 //! Loading of ELF objects, with dependencies
 
-use crate::{delf::{
-    components::{
-        rela::{Rela, RelocationType},
-        section::SectionType,
-        segment::{SegmentFlag, SegmentType},
-        sym::Sym,
+use crate::{
+    delf::{
+        components::{
+            dynamic::{AddrOrString, DynamicSection, DynamicTag},
+            hash::HashTable,
+            rela::{Rela, RelaTable, RelocationType},
+            section::{SectionHeader, SectionType, SHF_ALLOC, SHF_EXECINSTR, SHF_WRITE},
+            segment::{SegmentFlag, SegmentType},
+            sym::{Sym, SymBind},
+        },
+        errors::{ReadRelaError, ReadSymsError},
+        relocation::{self, Value},
+        stack, Addr, ElfType, ParsedElf,
     },
-    errors::{ReadRelaError, ReadSymsError},
-    Addr, ParsedElf,
-}, kernel_state, println};
-use types::{FrameAllocator, Pager, VirtAddr};
+    cpu, kernel_state,
+    memory::{AddressSpace, MapError, PAGE_SIZE},
+    ramdisk::{self, Ramdisk},
+    random,
+    user::{self, UserExit, USER_END},
+};
+use types::{MapFlags, Pager, VirtAddr};
 
 use core::{
     cmp::{max, min},
     ops::Range,
+    slice,
 };
 use alloc::{
-    boxed::Box,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    format,
+    sync::Arc,
     vec,
     vec::Vec,
     string::{String, ToString},
@@ -34,23 +47,58 @@ use enumflags2::BitFlags;
 /// (files), that need to be mapped (loaded) in different memory areas, with
 /// the right permission
 ///
-/// This struct represents a list of [`Object`]s
+/// This struct represents a list of [`Object`]s, along with the user
+/// memory they were mapped to
 #[derive(Debug)]
 pub struct Process {
     pub objects: Vec<Object>,
-    // pub search_path: Vec<PathBuf>,
-    // pub objects_by_path: BTreeMap<PathBuf, usize>,
+    /// Where objects are read from
+    pub ramdisk: Arc<Ramdisk>,
+    /// The directories searched for dependencies, after the ones the object
+    /// needing them asks for
+    pub search_path: Vec<String>,
+    /// The objects read from the ramdisk, by path
+    pub objects_by_path: BTreeMap<String, usize>,
     pub files: Vec<Vec<u8>>,
+    /// Every page mapped for the objects and the stack, which are unmapped
+    /// when the process is dropped
+    pub address_space: AddressSpace,
+}
+
+/// The top of the user stack
+pub const STACK_TOP: u64 = USER_END - PAGE_SIZE as u64;
+/// The size of the user stack
+pub const STACK_SIZE: usize = 16 * PAGE_SIZE;
+
+/// The lowest base of position-independent objects
+const DYN_BASE: u64 = 0x0000_2000_0000_0000;
+/// How many different bases position-independent objects can get, which
+/// spreads them over 1 TiB
+const DYN_BASE_PAGES: u64 = 1 << 28;
+/// How many random bases to try before giving up
+const DYN_BASE_ATTEMPTS: usize = 16;
+
+impl Default for Process {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl<'a> Process {
-    /// Create a new, empty [`Process`]
+impl Process {
+    /// Create a new, empty [`Process`], which can't load dependencies
     pub fn new() -> Self {
+        Self::with_ramdisk(Arc::new(Ramdisk::new()))
+    }
+
+    /// Create a new, empty [`Process`], which loads objects from `ramdisk`
+    pub fn with_ramdisk(ramdisk: Arc<Ramdisk>) -> Self {
         Self {
             objects: vec![],
-            // search_path: vec!["/usr/lib".into()],
-            // objects_by_path: HashMap::new(),
+            ramdisk,
+            search_path: vec!["/lib".into(), "/usr/lib".into()],
+            objects_by_path: BTreeMap::new(),
             files: vec![],
+            address_space: AddressSpace::new(),
         }
     }
 
@@ -61,9 +109,37 @@ impl<'a> Process {
     /// dependencies, they will get skipped. If that's not desidred,
     /// you may be looking for the [`load_obj_and_deps`](Self::load_obj_and_deps)
     /// method
-    pub fn load_object<'b: 'a>(&mut self, input: &'static [u8]) -> Result<usize, LoadError> {
-        let file = ParsedElf::parse_or_print_error(input)
-            .ok_or_else(|| LoadError::ParseError)?;
+    pub fn load_object(&mut self, input: &'static [u8]) -> Result<usize, LoadError> {
+        let file = ParsedElf::parse_or_print_error(input).ok_or(LoadError::ParseError)?;
+        // Relocatable objects have sections, but no segments to load
+        if file.elf_header.typ == ElfType::Rel {
+            return self.load_relocatable(file);
+        }
+
+        // Only objects with dynamic entries take part in dynamic linking
+        let dynamic = file
+            .dynamic_section()
+            .map_err(ReadSymsError::ReadDynamicError)?;
+        let (syms, hash) = match &dynamic {
+            Some(dynamic) => {
+                let hash = file
+                    .hash_table(dynamic)
+                    .map_err(ReadSymsError::ReadHashError)?;
+                (file.dynamic_symbols()?.syms()?, hash)
+            }
+            None => (vec![], None),
+        };
+        let relocations: Vec<_> = file
+            .dynamic_relocations()?
+            .into_iter()
+            .flat_map(RelaTable::iter)
+            .collect();
+        // The GOT entry of a symbol is the one its GLOB_DAT fills
+        let got = relocations
+            .iter()
+            .filter(|rel| rel.typ == RelocationType::GlobDat)
+            .map(|rel| (rel.sym.name.to_string(), rel.offset))
+            .collect();
 
         let load_segments = || {
             file.program_headers
@@ -79,218 +155,718 @@ impl<'a> Process {
             })
             .ok_or(LoadError::NoLoadSegments)?;
 
-        let base = Addr(0xDEAD_BEEF_0000);
+        let base = self.choose_base(file.elf_header.typ, &mem_range)?;
 
-        println!("loading segments at {:?}", base);
+        let address_space = &mut self.address_space;
         let segments = load_segments()
             .filter(|&ph| ph.memsz.0 > 0)
             .map(|ph| -> Result<_, LoadError> {
                 let vaddr = Addr(ph.vaddr.0 & !0xFFF);
                 let padding = ph.vaddr - vaddr;
-                let offset = ph.offset - padding;
-                let filesz = ph.filesz + padding;
-
-                unsafe { 
-                    let mut addr = base + vaddr;
-                    let physaddr = kernel_state().allocate_frame();
-                    kernel_state().pager.lock().map(VirtAddr(addr.0), physaddr).unwrap();
+                let mem_range = (base + ph.vaddr)..(base + ph.vaddr + ph.memsz);
 
-                    addr.as_mut_slice(filesz.into()).copy_from_slice(&input[offset.into()..][..filesz.into()]);
-                };
-
-                // Zero out BSS
-                if ph.memsz > ph.filesz {
-                    let mut zero_start = base + ph.mem_range().start + ph.filesz;
-                    let zero_len = ph.memsz - ph.filesz;
-                    unsafe {
-                        for i in zero_start.as_mut_slice(zero_len.into()) {
-                            *i = 0u8;
-                        }
-                    }
-                }
+                let data = input
+                    .get(ph.offset.into()..(ph.offset + ph.filesz).into())
+                    .filter(|_| ph.filesz <= ph.memsz)
+                    .ok_or(LoadError::InvalidSegment)?;
+                fill(address_space, &mem_range, data)?;
 
                 Ok(Segment {
                     padding,
                     flags: ph.flags,
+                    mem_range,
                 })
             })
             .collect::<Result<Vec<_>, _>>()?;
 
         self.objects.push(Object {
+            path: None,
             base,
             mem_range,
             file,
+            dynamic,
             segments,
+            syms,
+            hash,
+            relocations,
+            got,
+            plt: BTreeMap::new(),
         });
 
         Ok(self.objects.len() - 1)
     }
 
-    /*
-    /// Load an object and all its dependencies
+    /// Load a relocatable object (`ET_REL`), from its section headers
     ///
-    /// This method reads the file at the given `path`, and loads it
-    /// to memory, with all its dependencies
-    pub fn load_obj_and_deps<'b: 'a>(&mut self, input: &'b [u8]) -> Result<usize, LoadError> {
-        let index = self.load_object(input)?;
+    /// Its allocated sections are laid out like a linker would, with code,
+    /// read-only data and writable data on different pages. The object has
+    /// no GOT and no PLT, so the loader makes them: GOT entries for
+    /// GOT-relative relocations, and PLT entries for calls to functions
+    /// other objects define, which may be too far for a 32-bit displacement.
+    fn load_relocatable(&mut self, file: ParsedElf<'static>) -> Result<usize, LoadError> {
+        use RelocationType as RT;
+
+        let sections = &file.section_headers;
+        let in_file = |sh: &SectionHeader| {
+            let end = sh.off.0.checked_add(sh.size.0);
+            sh.typ == SectionType::NoBits
+                || end.is_some_and(|end| end <= file.full_content.len() as u64)
+        };
+        if !sections.iter().all(in_file) {
+            return Err(LoadError::InvalidSection);
+        }
+
+        let symtab = file
+            .section_with_type(SectionType::SymTab)
+            .and_then(|index| file.symtab(index))
+            .ok_or(ReadSymsError::SymTabSectionNotFound)?;
+        let syms = symtab.syms()?;
+
+        // Only the relocations of allocated sections are applied, and each
+        // is kept with the section it applies to
+        let is_alloc = |index: u32| {
+            let sh = sections.get(index as usize);
+            sh.is_some_and(|sh| sh.flags & SHF_ALLOC != 0)
+        };
+        let mut relocations = vec![];
+        for (index, sh) in sections.iter().enumerate() {
+            if sh.typ != SectionType::Rela || !is_alloc(sh.info) {
+                continue;
+            }
+            let table = file
+                .rela(index)
+                .ok_or(ReadSymsError::SymTabSectionNotFound)?;
+            for entry in table.0.chunks_exact(Rela::SIZE) {
+                let (_, rel) = Rela::parse(entry, &table.1)
+                    .map_err(|err| ReadRelaError::ParsingError(format!("{:?}", err)))?;
+                relocations.push((sh.info as usize, rel));
+            }
+        }
 
-        let mut a = vec![index];
-        while !a.is_empty() {
-            use DynamicTag::Needed;
-            a = a
-                .into_iter()
-                .map(|index| self.objects[index].file.clone())
-                .flat_map(|file| {
-                    let file = Box::leak(Box::new(file));
-                    let dyn_sect = Box::leak(Box::new(file.dynamic_section()?));
-                    let dyn_entry = Box::leak(Box::new(dyn_sect.entry_with_tag(Needed)?));
-                    Some(dyn_entry.addr.unwrap_string())
+        // Functions defined elsewhere are called through a PLT entry, which
+        // jumps through their GOT entry
+        let mut got_syms = BTreeMap::new();
+        let mut plt_names = BTreeSet::new();
+        for (_, rel) in &relocations {
+            let name = rel.sym.name.to_string();
+            let plt = rel.typ == RT::Plt32 && rel.sym.shndx.is_undef();
+            if plt && !name.is_empty() {
+                plt_names.insert(name.clone());
+            }
+            let got = matches!(rel.typ, RT::GotPcRel | RT::GotPcRelX | RT::RexGotPcRelX);
+            if (got || plt) && !name.is_empty() {
+                got_syms.entry(name).or_insert_with(|| rel.sym.clone());
+            }
+        }
+
+        let mut chunks: Vec<_> = sections
+            .iter()
+            .enumerate()
+            .filter(|(_, sh)| sh.flags & SHF_ALLOC != 0)
+            .map(|(index, sh)| {
+                let mut flags = BitFlags::from(SegmentFlag::Read);
+                if sh.flags & SHF_WRITE != 0 {
+                    flags |= SegmentFlag::Write;
+                }
+                if sh.flags & SHF_EXECINSTR != 0 {
+                    flags |= SegmentFlag::Execute;
+                }
+                (Chunk::Section(index), flags, sh.addralign.0, sh.size.0)
+            })
+            .collect();
+        chunks.push((
+            Chunk::Got,
+            SegmentFlag::Read | SegmentFlag::Write,
+            8,
+            8 * got_syms.len() as u64,
+        ));
+        chunks.push((
+            Chunk::Plt,
+            SegmentFlag::Read | SegmentFlag::Execute,
+            PLT_ENTRY_SIZE,
+            PLT_ENTRY_SIZE * plt_names.len() as u64,
+        ));
+        // Code first, then read-only data, then writable data, each
+        // starting on a new page
+        chunks.sort_by_key(|&(_, flags, ..)| {
+            (
+                flags.contains(SegmentFlag::Write),
+                !flags.contains(SegmentFlag::Execute),
+            )
+        });
+
+        let mut layout = vec![];
+        let mut offsets = vec![None; sections.len()];
+        let (mut got, mut plt) = (Addr(0), Addr(0));
+        let mut end = 0;
+        for (index, &(chunk, flags, align, size)) in chunks.iter().enumerate() {
+            let mut align = align.max(1);
+            if index == 0 || chunks[index - 1].1 != flags {
+                align = align.max(PAGE_SIZE as u64);
+            }
+            let start = align_up(end, align).ok_or(LoadError::InvalidSection)?;
+            end = start.checked_add(size).ok_or(LoadError::InvalidSection)?;
+            match chunk {
+                Chunk::Section(index) => offsets[index] = Some(Addr(start)),
+                Chunk::Got => got = Addr(start),
+                Chunk::Plt => plt = Addr(start),
+            }
+            layout.push((chunk, flags, Addr(start)..Addr(end)));
+        }
+        if end == 0 {
+            return Err(LoadError::NoLoadSegments);
+        }
+
+        // Symbols are relative to their section, until it's laid out
+        let place = |mut sym: Sym<'static>| {
+            if sym.shndx.is_common() {
+                return Err(LoadError::CommonSymbol(sym.name.to_string()));
+            }
+            let index = sym.shndx.get();
+            if let Some(offset) = index.and_then(|index| offsets.get(index).copied().flatten()) {
+                sym.value = offset + sym.value;
+            }
+            Ok(sym)
+        };
+        let syms = syms.into_iter().map(place).collect::<Result<_, _>>()?;
+        let mut relocations = relocations
+            .into_iter()
+            .map(|(target, mut rel)| {
+                // Only allocated sections have relocations left, and they
+                // were all laid out
+                rel.offset = offsets[target].unwrap_or(Addr(0)) + rel.offset;
+                rel.sym = place(rel.sym)?;
+                Ok(rel)
+            })
+            .collect::<Result<Vec<_>, LoadError>>()?;
+
+        let mut got_entries = BTreeMap::new();
+        for (slot, (name, sym)) in got_syms.into_iter().enumerate() {
+            let offset = got + Addr(8 * slot as u64);
+            relocations.push(Rela {
+                offset,
+                typ: RT::GlobDat,
+                sym: place(sym)?,
+                addend: Addr(0),
+            });
+            got_entries.insert(name, offset);
+        }
+        let mut plt_entries = BTreeMap::new();
+        let mut plt_data = vec![];
+        for (slot, name) in plt_names.into_iter().enumerate() {
+            let offset = plt + Addr(PLT_ENTRY_SIZE * slot as u64);
+            // jmp *got_entry(%rip), then traps
+            let got_entry = got_entries[&name];
+            let displacement = got_entry.0.wrapping_sub(offset.0 + 6) as u32;
+            plt_data.extend_from_slice(&[0xFF, 0x25]);
+            plt_data.extend_from_slice(&displacement.to_le_bytes());
+            plt_data.resize(plt_data.len() + PLT_ENTRY_SIZE as usize - 6, 0xCC);
+            plt_entries.insert(name, offset);
+        }
+
+        let mem_range = Addr(0)..Addr(end);
+        let base = self.choose_base(ElfType::Rel, &mem_range)?;
+
+        let address_space = &mut self.address_space;
+        let segments = layout
+            .into_iter()
+            .filter(|(.., range)| range.start < range.end)
+            .map(|(chunk, flags, range)| -> Result<_, LoadError> {
+                let mem_range = (base + range.start)..(base + range.end);
+                let data = match chunk {
+                    Chunk::Section(index) if sections[index].typ == SectionType::NoBits => &[],
+                    Chunk::Section(index) => sections[index].data(),
+                    Chunk::Got => &[],
+                    Chunk::Plt => &plt_data[..],
+                };
+                fill(address_space, &mem_range, data)?;
+
+                Ok(Segment {
+                    padding: Addr(range.start.0 % PAGE_SIZE as u64),
+                    flags,
+                    mem_range,
                 })
-                .collect::<Vec<_>>()
-                .into_iter()
-                .map(|dep| self.get_object(&dep))
-                .collect::<Result<Vec<_>, _>>()?
-                .into_iter()
-                .filter_map(GetResult::fresh)
-                .collect();
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.objects.push(Object {
+            path: None,
+            base,
+            mem_range: (base + mem_range.start)..(base + mem_range.end),
+            file,
+            dynamic: None,
+            segments,
+            syms,
+            hash: None,
+            relocations,
+            got: got_entries,
+            plt: plt_entries,
+        });
+
+        Ok(self.objects.len() - 1)
+    }
+
+    /// Load an object and all its dependencies
+    ///
+    /// The object at `path` in the ramdisk is loaded first, then the objects
+    /// it needs, then the ones they need, and so on. Each object is loaded
+    /// once, even if several others need it or they need each other.
+    pub fn load_obj_and_deps(&mut self, path: &str) -> Result<usize, LoadError> {
+        let index = match self.get_object(path, None)? {
+            GetResult::Cached(index) | GetResult::Fresh(index) => index,
+        };
+
+        // Objects that were already loaded are not visited again, which
+        // also ends cycles
+        let mut queue = VecDeque::from(vec![index]);
+        while let Some(index) = queue.pop_front() {
+            for name in self.objects[index].needed() {
+                if let GetResult::Fresh(dep) = self.get_object(&name, Some(index))? {
+                    queue.push_back(dep);
+                }
+            }
         }
 
         Ok(index)
     }
-    */
 
-    /*
-    /// Find an object in the search path, and return its location
-    pub fn object_path(&self, name: &str) -> Result<PathBuf, LoadError> {
-        self.search_path
-            .iter()
-            .filter_map(|prefix| prefix.join(name).canonicalize().ok())
-            .find(|path| path.exists())
+    /// Find an object in the ramdisk, and return its path
+    ///
+    /// Names containing a slash are paths already. Otherwise, the object is
+    /// looked for in the search path of the object that needs it (the one at
+    /// index `needed_by`), then in the process' search path.
+    pub fn object_path(&self, name: &str, needed_by: Option<usize>) -> Result<String, LoadError> {
+        if name.contains('/') {
+            return Some(ramdisk::normalize(name))
+                .filter(|path| self.ramdisk.contains(path))
+                .ok_or_else(|| LoadError::NotFound(name.into()));
+        }
+
+        let own = needed_by
+            .map(|index| self.objects[index].search_path())
+            .unwrap_or_default();
+        own.iter()
+            .chain(&self.search_path)
+            .map(|dir| ramdisk::normalize(&format!("{}/{}", dir, name)))
+            .find(|path| self.ramdisk.contains(path))
             .ok_or_else(|| LoadError::NotFound(name.into()))
     }
-    */
 
-    /*
-    /// Retrieve an object by name
+    /// Retrieve an object by name, for the object at index `needed_by`
     ///
     /// This method gives a `Cached(obj)` if the object was
     /// already loaded, or a `Fresh(obj)` if it wasn't, and
     /// a lookup was necessary.
-    pub fn get_object(&mut self, name: &str) -> Result<GetResult, LoadError> {
-        let path = self.object_path(name)?;
-        self.objects_by_path
+    pub fn get_object(
+        &mut self,
+        name: &str,
+        needed_by: Option<usize>,
+    ) -> Result<GetResult, LoadError> {
+        let path = self.object_path(name, needed_by)?;
+        if let Some(&index) = self.objects_by_path.get(&path) {
+            return Ok(GetResult::Cached(index));
+        }
+
+        let input = self
+            .ramdisk
             .get(&path)
-            .map(|&index| Ok(GetResult::Cached(index)))
-            .unwrap_or_else(|| self.load_object(path).map(GetResult::Fresh))
+            .ok_or_else(|| LoadError::NotFound(name.into()))?;
+        let index = self.load_object(input)?;
+        self.objects[index].path = Some(path.clone());
+        self.objects_by_path.insert(path, index);
+        Ok(GetResult::Fresh(index))
     }
-    */
 
-    /// Lookup a symbol from the ones defined in the process
-    pub fn lookup_symbol(&self, name: &str) -> Option<(&Object, Sym)> {
-        for obj in self.objects.iter().rev() {
-            let symtab_index = obj.file.section_with_type(SectionType::SymTab)?;
-            let symtab = obj.file.symtab(symtab_index)?;
-            let symtab = Box::leak(Box::new(symtab));
+    /// Where to load an object spanning `mem_range` (before relocation)
+    ///
+    /// Executables must be loaded at the addresses they were linked at,
+    /// while position-independent and relocatable objects get a random
+    /// base, so that their layout can't be predicted.
+    fn choose_base(&self, typ: ElfType, mem_range: &Range<Addr>) -> Result<Addr, LoadError> {
+        match typ {
+            ElfType::Exec if self.is_free(mem_range.start.0, mem_range.end.0) => Ok(Addr(0)),
+            ElfType::Exec => Err(LoadError::Overlap),
+            ElfType::Dyn | ElfType::Rel => (0..DYN_BASE_ATTEMPTS)
+                .map(|_| DYN_BASE + random::below(DYN_BASE_PAGES) * PAGE_SIZE as u64)
+                .find(|&base| {
+                    let start = base.checked_add(mem_range.start.0);
+                    let end = base.checked_add(mem_range.end.0);
+                    matches!((start, end), (Some(start), Some(end)) if self.is_free(start, end))
+                })
+                .map(Addr)
+                .ok_or(LoadError::NoAddressSpace),
+            typ => Err(LoadError::UnsupportedType(typ)),
+        }
+    }
 
-            for sym in symtab.syms() {
-                if sym.name == name {
-                    return Some((obj, sym.clone()));
-                }
-            }
+    /// Whether every page between `start` and `end` can be given to user code
+    fn is_free(&self, start: u64, end: u64) -> bool {
+        let start = start & !(PAGE_SIZE as u64 - 1);
+        // The first page is left unmapped, to catch null pointers
+        if start == 0 || end > STACK_TOP - STACK_SIZE as u64 {
+            return false;
         }
-        None
+        // Objects that were already loaded are mapped too
+        let pager = kernel_state().pager.lock();
+        (start..end)
+            .step_by(PAGE_SIZE)
+            .all(|page| pager.translate(VirtAddr(page)).is_none())
     }
 
-    /// Apply all the relocations of this process
-    pub fn apply_relocations(&self) -> Result<(), RelocationError> {
-        let rels: Vec<_> = self
-            .objects
+    /// Run the process in user mode, starting at the entry point of the first
+    /// object loaded, until it exits or faults
+    ///
+    /// The program gets `args` and `env` on its [initial stack](stack),
+    /// along with an auxiliary vector describing the first object.
+    /// Relocations must have been applied already, since the segments get
+    /// their [final protections](Self::adjust_protections) here. The
+    /// process is consumed, so its memory is unmapped once it stops running.
+    pub fn start(mut self, args: &[&str], env: &[&str]) -> Result<UserExit, LoadError> {
+        use stack::auxv::*;
+
+        self.adjust_protections()?;
+
+        let main = self.objects.first().ok_or(LoadError::NothingLoaded)?;
+        let header = &main.file.elf_header;
+        let entry = main.base + header.entry_point;
+
+        let mut auxv = vec![
+            (AT_PHENT, header.ph_entsize as u64),
+            (AT_PHNUM, header.ph_count as u64),
+            (AT_PAGESZ, PAGE_SIZE as u64),
+            // The kernel loads every object itself, there's no interpreter
+            (AT_BASE, 0),
+            (AT_ENTRY, entry.0),
+            (AT_HWCAP, u64::from(cpu::info().hwcap)),
+        ];
+        if let Some(phdr) = main.program_headers_addr() {
+            auxv.push((AT_PHDR, phdr.0));
+        }
+
+        let stack_bottom = STACK_TOP - STACK_SIZE as u64;
+        for page in (stack_bottom..STACK_TOP).step_by(PAGE_SIZE) {
+            self.address_space.map(VirtAddr(page), USER_RW)?;
+        }
+        let mut bytes = [0; 16];
+        random::fill(&mut bytes);
+        let memory = unsafe { slice::from_raw_parts_mut(stack_bottom as *mut u8, STACK_SIZE) };
+        let sp = stack::build(memory, STACK_TOP, args, env, &auxv, bytes)
+            .ok_or(LoadError::ArgumentsTooLong)?;
+
+        // Everything the user code can reach was mapped for it, and belongs
+        // to this process
+        let exit = unsafe {
+            user::enter_user(x86_64::VirtAddr::new(entry.0), x86_64::VirtAddr::new(sp))
+        };
+        Ok(exit)
+    }
+
+    /// Lookup a symbol from the ones defined in the process
+    ///
+    /// Objects are searched in the order they were loaded, so the executable
+    /// comes first, then its dependencies breadth-first.
+    pub fn lookup_symbol(&self, name: &str) -> Option<(&Object, &Sym<'static>)> {
+        self.find_symbol(name, None)
+    }
+
+    /// Lookup a symbol, like [`lookup_symbol`](Self::lookup_symbol), but
+    /// not in `except`
+    fn find_symbol(&self, name: &str, except: Option<&Object>) -> Option<(&Object, &Sym<'static>)> {
+        self.objects
             .iter()
-            .rev()
-            .flat_map(|obj| {
-                let rela_table_index = obj.file.section_with_type(SectionType::Rela)?;
-
-                let rela_table = obj.file.rela(rela_table_index)?;
-                let rela_table = Box::leak(Box::new(rela_table));
-                Some(rela_table.iter().map(move |rel| ObjectRel {
-                    relobj: &obj,
-                    rel: rel.clone(),
-                }))
-            })
-            .flatten()
-            .collect();
+            .filter(|obj| !except.is_some_and(|except| core::ptr::eq(*obj, except)))
+            .find_map(|obj| Some((obj, obj.symbol(name)?)))
+    }
 
-        for rel in rels {
-            self.apply_relocation(rel)?;
+    /// Apply all the relocations of this process
+    ///
+    /// Indirect relocations come last, since their resolvers are user code:
+    /// they run once every other relocation was applied, and the segments
+    /// got their [final protections](Self::adjust_protections).
+    pub fn apply_relocations(&mut self) -> Result<(), RelocationError> {
+        let mut indirect = Vec::new();
+        for obj in self.objects.iter().rev() {
+            for rel in &obj.relocations {
+                let place = obj.base + rel.offset;
+                // The place was mapped for this process, and nothing runs
+                // there yet
+                match self.compute_relocation(obj, rel)? {
+                    Value::U64(value) => unsafe { place.write(&value.to_le_bytes()) },
+                    Value::U32(value) => unsafe { place.write(&value.to_le_bytes()) },
+                    Value::Copy { src, len } => unsafe {
+                        place.write(Addr(src).as_slice(len as usize))
+                    },
+                    Value::Indirect { resolver } => indirect.push((place, resolver)),
+                }
+            }
+        }
+        if indirect.is_empty() {
+            return Ok(());
+        }
+
+        self.adjust_protections()?;
+        let resolvers: Vec<_> = indirect.iter().map(|&(_, resolver)| resolver).collect();
+        let values = self.run_resolvers(&resolvers)?;
+        for (&(place, _), value) in indirect.iter().zip(values) {
+            unsafe { place.write(&value.to_le_bytes()) };
         }
         Ok(())
     }
 
-    /// Apply a single relocation
-    fn apply_relocation(&self, objrel: ObjectRel) -> Result<(), RelocationError> {
-        use RelocationType as RT;
+    /// What the relocation `rel` of `obj` computes to
+    fn compute_relocation(&self, obj: &Object, rel: &Rela) -> Result<Value, RelocationError> {
+        let name = &rel.sym.name;
+        let mut inputs = relocation::Inputs {
+            addend: rel.addend.0 as i64,
+            base: obj.base.0,
+            place: (obj.base + rel.offset).0,
+            ..Default::default()
+        };
 
-        let rel = &objrel.rel;
-        let reltype = rel.typ;
-        let addend = rel.addend;
-
-        // Perform symbol lookup early
-        let (obj, found) = self
-            .lookup_symbol(rel.sym.name.as_ref())
-            .ok_or_else(|| RelocationError::UndefinedSymbol(rel.sym.name.to_string()))?;
-
-        match reltype {
-            RT::_64 => unsafe {
-                objrel.addr().set(found.value + obj.base + addend);
-            },
-            RT::Relative => unsafe {
-                objrel.addr().set(obj.base + addend);
-            },
-            RT::Copy => unsafe {
-                objrel
-                    .addr()
-                    .write((found.value + obj.base).as_slice(found.size as usize));
-            },
-            _ => return Err(RelocationError::UnimplementedRelocation(reltype)),
+        // Relocatable objects use the symbols they define, even the local
+        // ones, which may have no name
+        if obj.file.elf_header.typ == ElfType::Rel && !rel.sym.shndx.is_undef() {
+            inputs.symbol = obj.symbol_addr(&rel.sym).0;
+            inputs.symbol_size = rel.sym.size;
+        // Relocations like `Relative` have no symbol
+        } else if !name.is_empty() {
+            // A copy relocation fills the object's own copy of a symbol with
+            // the contents of the definition in another object
+            let copy = rel.typ == RelocationType::Copy;
+            match self.find_symbol(name, if copy { Some(obj) } else { None }) {
+                Some((def, sym)) => {
+                    inputs.symbol = def.symbol_addr(sym).0;
+                    inputs.symbol_size = sym.size;
+                }
+                // Undefined weak symbols are null
+                None if matches!(rel.sym.bind, SymBind::Weak) => {}
+                None => return Err(RelocationError::UndefinedSymbol(name.to_string())),
+            }
         }
-        Ok(())
+        let entry = |entries: &BTreeMap<String, Addr>| {
+            entries.get(&**name).map(|&offset| (obj.base + offset).0)
+        };
+        inputs.got_entry = entry(&obj.got);
+        inputs.plt_entry = entry(&obj.plt);
+
+        relocation::compute(rel.typ, &inputs)
     }
 
-    /*
-    /// Set the correct protection for the segments of this process
-    pub fn adjust_protections(&self) -> Result<(), region::Error> {
-        use region::{protect, Protection};
+    /// Call each of the indirect function `resolvers` in user mode, and
+    /// return what they returned
+    ///
+    /// They return to a stub that saves their result right where the return
+    /// address was, then exits. The stub and the stack are mapped where the
+    /// process' stack will be, and are gone when this returns.
+    fn run_resolvers(&mut self, resolvers: &[u64]) -> Result<Vec<u64>, RelocationError> {
+        const STUB: [u8; 10] = [
+            0x50, // push %rax
+            0x31, 0xFF, // xor %edi, %edi
+            0xB8, 0x3C, 0x00, 0x00, 0x00, // mov $60, %eax (exit)
+            0x0F, 0x05, // syscall
+        ];
+        let stub = STACK_TOP - STACK_SIZE as u64;
+        let stack = stub + PAGE_SIZE as u64;
+        self.address_space.map(VirtAddr(stub), USER_RW)?;
+        self.address_space.map(VirtAddr(stack), USER_RW)?;
+        unsafe { (stub as *mut u8).copy_from_nonoverlapping(STUB.as_ptr(), STUB.len()) };
+        let code = MapFlags {
+            writable: false,
+            user: true,
+            executable: true,
+        };
+        self.address_space.protect(VirtAddr(stub), code)?;
 
-        for obj in &self.objects {
-            for seg in &obj.segments {
-                let mut protection = Protection::NONE;
-                for flag in seg.flags.iter() {
-                    protection |= match flag {
-                        SegmentFlag::Read => Protection::READ,
-                        SegmentFlag::Write => Protection::WRITE,
-                        SegmentFlag::Execute => Protection::EXECUTE,
+        // Functions start with the stack 16-byte aligned, plus the return
+        // address
+        let sp = stack + PAGE_SIZE as u64 - 8;
+        let values = resolvers
+            .iter()
+            .map(|&resolver| {
+                // Both pages were mapped for this, and resolvers are part of
+                // the process
+                unsafe {
+                    (sp as *mut u64).write(stub);
+                    let exit = user::enter_user(
+                        x86_64::VirtAddr::new(resolver),
+                        x86_64::VirtAddr::new(sp),
+                    );
+                    match exit {
+                        UserExit::Exited(0) => Ok((sp as *const u64).read()),
+                        exit => Err(RelocationError::ResolverFailed(exit)),
                     }
                 }
-                unsafe {
-                    protect(seg.map.data(), seg.map.len(), protection)?;
+            })
+            .collect();
+
+        self.address_space.unmap(VirtAddr(stub))?;
+        self.address_space.unmap(VirtAddr(stack))?;
+        values
+    }
+
+    /// Set the correct protection for the segments of this process
+    ///
+    /// Until then, every segment is writable and not executable. A page
+    /// shared by several segments gets the permissions of all of them.
+    pub fn adjust_protections(&mut self) -> Result<(), MapError> {
+        let mut pages = BTreeMap::new();
+        for seg in self.objects.iter().flat_map(|obj| &obj.segments) {
+            let start = seg.mem_range.start.0 & !(PAGE_SIZE as u64 - 1);
+            for page in (start..seg.mem_range.end.0).step_by(PAGE_SIZE) {
+                let flags = pages.entry(page).or_insert(MapFlags {
+                    writable: false,
+                    user: true,
+                    executable: false,
+                });
+                for flag in seg.flags.iter() {
+                    match flag {
+                        // Pages are always readable
+                        SegmentFlag::Read => {}
+                        SegmentFlag::Write => flags.writable = true,
+                        SegmentFlag::Execute => flags.executable = true,
+                    }
                 }
             }
         }
+
+        for (page, flags) in pages {
+            self.address_space.protect(VirtAddr(page), flags)?;
+        }
         Ok(())
     }
-    */
 }
 
+/// Segments are writable by user code until their protections are applied
+const USER_RW: MapFlags = MapFlags {
+    writable: true,
+    user: true,
+    executable: false,
+};
+
 /// An ELF object
 #[derive(CustomDebug)]
 pub struct Object {
+    /// Where the object was read from, if it came from the ramdisk
+    pub path: Option<String>,
     pub base: Addr,
     pub mem_range: Range<Addr>,
 
     #[debug(skip)]
     pub file: ParsedElf<'static>,
+    /// The dynamic entries, if the object has any
+    #[debug(skip)]
+    pub dynamic: Option<DynamicSection<'static>>,
     #[debug(skip)]
     pub segments: Vec<Segment>,
+    /// The dynamic symbols, or the `.symtab` of relocatable objects, parsed
+    /// once
+    #[debug(skip)]
+    pub syms: Vec<Sym<'static>>,
+    /// The table to find dynamic symbols with, if the object has one
+    #[debug(skip)]
+    pub hash: Option<HashTable<'static>>,
+    /// The relocations to apply once every object is loaded
+    #[debug(skip)]
+    pub relocations: Vec<Rela<'static>>,
+    /// Where the GOT entry of each symbol is, relative to the base
+    #[debug(skip)]
+    pub got: BTreeMap<String, Addr>,
+    /// Where the PLT entry of each function is, relative to the base, for
+    /// the objects the loader made a PLT for
+    #[debug(skip)]
+    pub plt: BTreeMap<String, Addr>,
+}
+
+impl Object {
+    /// Where the program headers are in memory, if they were loaded
+    pub fn program_headers_addr(&self) -> Option<Addr> {
+        let headers = &self.file.program_headers;
+        if let Some(phdr) = headers.iter().find(|ph| ph.typ == SegmentType::PHdr) {
+            return Some(self.base + phdr.vaddr);
+        }
+
+        // Otherwise, they may still be part of a loaded segment
+        let offset = self.file.elf_header.ph_offset;
+        headers
+            .iter()
+            .filter(|ph| ph.typ == SegmentType::Load)
+            .find(|ph| ph.offset <= offset && offset < ph.offset + ph.filesz)
+            .map(|ph| self.base + ph.vaddr + (offset - ph.offset))
+    }
+
+    /// The global symbol called `name` that this object defines
+    pub fn symbol(&self, name: &str) -> Option<&Sym<'static>> {
+        let is_match = |sym: &Sym| {
+            sym.name == name && !sym.shndx.is_undef() && !matches!(sym.bind, SymBind::Local)
+        };
+        match &self.hash {
+            Some(hash) => {
+                let index = hash.lookup(name, |index| {
+                    self.syms.get(index as usize).is_some_and(is_match)
+                })?;
+                self.syms.get(index as usize)
+            }
+            None => self.syms.iter().find(|sym| is_match(sym)),
+        }
+    }
+
+    /// Where the symbol `sym` of this object is, once loaded
+    pub fn symbol_addr(&self, sym: &Sym) -> Addr {
+        // Absolute symbols don't move with the object
+        if sym.shndx.is_abs() {
+            sym.value
+        } else {
+            self.base + sym.value
+        }
+    }
+
+    /// The names of the objects this one needs, in order
+    pub fn needed(&self) -> Vec<String> {
+        self.dynamic_strings(DynamicTag::Needed)
+    }
+
+    /// The directories to search for the objects this one needs
+    ///
+    /// They come from `DT_RUNPATH` or, if there is none, from the older
+    /// `DT_RPATH`. `$ORIGIN` stands for the directory of the object, so
+    /// directories using it are skipped if that isn't known.
+    pub fn search_path(&self) -> Vec<String> {
+        let mut paths = self.dynamic_strings(DynamicTag::Runpath);
+        if paths.is_empty() {
+            paths = self.dynamic_strings(DynamicTag::RPath);
+        }
+
+        let origin = self
+            .path
+            .as_deref()
+            .map(|path| &path[..path.rfind('/').unwrap_or(0)]);
+        paths
+            .iter()
+            .flat_map(|path| path.split(':'))
+            .filter(|dir| !dir.is_empty())
+            .filter_map(|dir| {
+                if !dir.contains("$ORIGIN") && !dir.contains("${ORIGIN}") {
+                    return Some(dir.into());
+                }
+                origin.map(|origin| dir.replace("${ORIGIN}", origin).replace("$ORIGIN", origin))
+            })
+            .collect()
+    }
+
+    /// The strings of the dynamic entries with the given tag
+    fn dynamic_strings(&self, tag: DynamicTag) -> Vec<String> {
+        let dynamic = match &self.dynamic {
+            Some(dynamic) => dynamic,
+            None => return vec![],
+        };
+        dynamic
+            .entries_with_tag(tag)
+            .filter_map(|entry| match &entry.addr {
+                AddrOrString::String(string) => Some(string.to_string()),
+                AddrOrString::Address(_) => None,
+            })
+            .collect()
+    }
 }
 
 /// A segment for an [`Object`]
@@ -298,24 +874,55 @@ pub struct Object {
 pub struct Segment {
     pub padding: Addr,
     pub flags: BitFlags<SegmentFlag>,
+    /// Where the segment was loaded
+    pub mem_range: Range<Addr>,
 }
 
-#[derive(Debug, Clone)]
-pub struct ObjectSym<'a> {
-    obj: &'a Object,
-    sym: &'a Sym<'a>,
+/// A part of the memory of a relocatable object
+#[derive(Clone, Copy)]
+enum Chunk {
+    /// One of its allocated sections
+    Section(usize),
+    /// The GOT the loader made for it
+    Got,
+    /// The PLT the loader made for it
+    Plt,
 }
 
-#[derive(Debug)]
-pub struct ObjectRel<'a> {
-    relobj: &'a Object,
-    rel: Rela<'a>,
-}
+/// The size of the PLT entries the loader makes
+const PLT_ENTRY_SIZE: u64 = 16;
+
+/// Map the pages of `mem_range` that aren't yet, then fill it with `data`
+/// followed by zeroes
+///
+/// Ranges may share their first or last page. Only the range's own bytes
+/// are written, so that a shared page keeps the other range's contents.
+fn fill(
+    address_space: &mut AddressSpace,
+    mem_range: &Range<Addr>,
+    data: &[u8],
+) -> Result<(), MapError> {
+    let start = mem_range.start.0 & !(PAGE_SIZE as u64 - 1);
+    for page in (start..mem_range.end.0).step_by(PAGE_SIZE) {
+        if address_space.flags(VirtAddr(page)).is_none() {
+            address_space.map(VirtAddr(page), USER_RW)?;
+        }
+    }
 
-impl ObjectRel<'_> {
-    fn addr(&self) -> Addr {
-        self.relobj.base + self.rel.offset
+    // The range was just mapped for the object, and nothing runs there yet
+    unsafe {
+        let mut start = mem_range.start;
+        let memory = start.as_mut_slice::<u8>((mem_range.end - mem_range.start).into());
+        let (filled, zeroed) = memory.split_at_mut(data.len());
+        filled.copy_from_slice(data);
+        zeroed.fill(0);
     }
+    Ok(())
+}
+
+/// Round `value` up to a multiple of `align`
+fn align_up(value: u64, align: u64) -> Option<u64> {
+    Some(value.checked_add(align - 1)? / align * align)
 }
 
 /// Get a range that contains both `a` and `b`
@@ -339,36 +946,361 @@ pub fn dump_maps(msg: &str) {
 
 #[derive(displaydoc::Display, Debug)]
 pub enum LoadError {
-    // /// ELF object not found: {0}
-    // NotFound(String),
+    /// ELF object not found: {0}
+    NotFound(String),
     // /// An invalid or unsupported path was encountered
     // InvalidPath(PathBuf),
     // /// I/O Error: {0}
     // IO(PathBuf, alloc::io::Error),
     /// ELF object could not be parsed
-    // /// ELF object could not be mapped to memory: {0}
-    // MapError(mmap::MapError),
     ParseError,
+    /// ELF object could not be mapped to memory: {0}
+    MapError(MapError),
+    /// No ELF object has been loaded
+    NothingLoaded,
+    /// Arguments and environment do not fit on the stack
+    ArgumentsTooLong,
     /// ELF object has no load segments
     NoLoadSegments,
+    /// ELF object has a segment that does not fit in the file, or in memory
+    InvalidSegment,
+    /// ELF object has a section that does not fit in the file, or in memory
+    InvalidSection,
+    /// ELF object has a common symbol, which only linkers allocate: {0}
+    CommonSymbol(String),
+    /// Unsupported ELF object type: {0:?}
+    UnsupportedType(ElfType),
+    /// ELF object overlaps memory that is already in use
+    Overlap,
+    /// No free address space left for ELF object
+    NoAddressSpace,
     /// Could not read symbols from ELF object: {0}
     ReadSymsError(ReadSymsError),
     /// Could not read relocations from ELF object: {0}
     ReadRelaError(ReadRelaError),
 }
 
+impl From<ReadSymsError> for LoadError {
+    fn from(err: ReadSymsError) -> Self {
+        LoadError::ReadSymsError(err)
+    }
+}
+
+impl From<ReadRelaError> for LoadError {
+    fn from(err: ReadRelaError) -> Self {
+        LoadError::ReadRelaError(err)
+    }
+}
+
+impl From<MapError> for LoadError {
+    fn from(err: MapError) -> Self {
+        LoadError::MapError(err)
+    }
+}
+
 #[derive(displaydoc::Display, Debug)]
 pub enum RelocationError {
-    /// unimplemented relocation: {0:?}
-    UnimplementedRelocation(RelocationType),
-    #[allow(dead_code)]
-    /// unknown symbol number: {0}
-    UnknownSymbolNumber(u32),
     /// undefined symbol: {0}
     UndefinedSymbol(String),
+    /// relocation {0:?} overflows with {1:#x}
+    Overflow(RelocationType, u64),
+    /// GOT-relative relocation against a symbol without a GOT entry
+    NoGotEntry,
+    /// indirect function resolver did not return: {0:?}
+    ResolverFailed(UserExit),
+    /// could not map memory for indirect function resolvers: {0}
+    MapError(MapError),
+}
+
+impl From<MapError> for RelocationError {
+    fn from(err: MapError) -> Self {
+        RelocationError::MapError(err)
+    }
 }
 
+/// An object retrieved with [`Process::get_object`]
 pub enum GetResult {
+    /// It had already been loaded
     Cached(usize),
+    /// It was loaded now
     Fresh(usize),
 }
+
+#[cfg(test)]
+mod test {
+    use super::{
+        DynamicTag, LoadError, ParsedElf, Process, ReadRelaError, ReadSymsError, RelocationType,
+        SegmentType, Sym, SymBind, DYN_BASE, DYN_BASE_PAGES,
+    };
+    use crate::{delf::components::sym::SymType, memory::PAGE_SIZE, ramdisk::Ramdisk};
+    use alloc::{boxed::Box, sync::Arc, vec::Vec};
+
+    static HELLO: &[u8] = include_bytes!("../../tests/fixtures/hello");
+    static HELLO_PIE: &[u8] = include_bytes!("../../tests/fixtures/hello-pie");
+
+    /// The objects built from `fixtures/lib.S`, with `libleaf.so` at `leaf`
+    fn ramdisk(leaf: &str) -> Ramdisk {
+        let mut ramdisk = Ramdisk::new();
+        ramdisk.insert(
+            "/bin/hello-deps",
+            include_bytes!("../../tests/fixtures/hello-deps"),
+        );
+        ramdisk.insert(
+            "/lib/libmid.so",
+            include_bytes!("../../tests/fixtures/libmid.so"),
+        );
+        ramdisk.insert(leaf, include_bytes!("../../tests/fixtures/libleaf.so"));
+        ramdisk.insert(
+            "/usr/lib/libloop-a.so",
+            include_bytes!("../../tests/fixtures/libloop-a.so"),
+        );
+        ramdisk.insert(
+            "/usr/lib/libloop-b.so",
+            include_bytes!("../../tests/fixtures/libloop-b.so"),
+        );
+        ramdisk
+    }
+
+    #[test_case]
+    fn executables_keep_their_addresses() {
+        let mut process = Process::new();
+        let index = process.load_object(HELLO).unwrap();
+        assert_eq!(process.objects[index].base.0, 0);
+        assert!(matches!(
+            process.load_object(HELLO),
+            Err(LoadError::Overlap)
+        ));
+    }
+
+    #[test_case]
+    fn position_independent_objects_get_random_bases() {
+        let mut process = Process::new();
+        let first = process.load_object(HELLO_PIE).unwrap();
+        let second = process.load_object(HELLO_PIE).unwrap();
+        let bases = [process.objects[first].base.0, process.objects[second].base.0];
+        assert_ne!(bases[0], bases[1]);
+        for &base in &bases {
+            assert_eq!(base % PAGE_SIZE as u64, 0);
+            assert!(base >= DYN_BASE && base < DYN_BASE + DYN_BASE_PAGES * PAGE_SIZE as u64);
+        }
+    }
+
+    #[test_case]
+    fn dependencies_are_loaded_breadth_first_and_once() {
+        let mut process = Process::with_ramdisk(Arc::new(ramdisk("/opt/libleaf.so")));
+        let main = process.load_obj_and_deps("/bin/hello-deps").unwrap();
+        assert_eq!(main, 0);
+
+        let paths: Vec<_> = process
+            .objects
+            .iter()
+            .map(|obj| obj.path.as_deref().unwrap())
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                "/bin/hello-deps",
+                "/lib/libmid.so",
+                "/usr/lib/libloop-a.so",
+                "/opt/libleaf.so",
+                "/usr/lib/libloop-b.so",
+            ]
+        );
+        assert_eq!(process.objects_by_path.len(), paths.len());
+        assert_eq!(process.objects_by_path["/opt/libleaf.so"], 3);
+    }
+
+    #[test_case]
+    fn missing_dependencies_are_reported() {
+        let mut process = Process::new();
+        assert!(matches!(
+            process.load_obj_and_deps("/bin/hello-deps"),
+            Err(LoadError::NotFound(name)) if name == "/bin/hello-deps"
+        ));
+
+        // Neither the DT_RUNPATH of `libmid.so` nor the default search path
+        // lead there
+        let mut process = Process::with_ramdisk(Arc::new(ramdisk("/srv/libleaf.so")));
+        assert!(matches!(
+            process.load_obj_and_deps("/bin/hello-deps"),
+            Err(LoadError::NotFound(name)) if name == "libleaf.so"
+        ));
+    }
+
+    #[test_case]
+    fn symbols_are_found_in_the_objects_defining_them() {
+        let mut process = Process::new();
+        process.load_object(HELLO_PIE).unwrap();
+        let lib = process
+            .load_object(include_bytes!("../../tests/fixtures/libsyms.so"))
+            .unwrap();
+
+        let (obj, sym) = process.lookup_symbol("gamma").unwrap();
+        assert_eq!(obj.base, process.objects[lib].base);
+        assert_eq!(sym.value.0, 0x1100);
+        // It's referenced, but not defined, by `libsyms.so`
+        assert!(process.lookup_symbol("missing").is_none());
+    }
+
+    #[test_case]
+    fn stripped_objects_are_linked_through_their_dynamic_entries() {
+        let mut process = Process::new();
+        let index = process
+            .load_object(include_bytes!("../../tests/fixtures/librel.so"))
+            .unwrap();
+        let obj = &process.objects[index];
+        assert!(obj.file.section_headers.is_empty());
+
+        assert_eq!(obj.symbol("func").unwrap().value.0, 0x1020);
+        assert_eq!(obj.symbol("value").unwrap().value.0, 0x2148);
+        let relocations: Vec<_> = obj
+            .relocations
+            .iter()
+            .map(|rel| (rel.typ, rel.offset.0, &*rel.sym.name))
+            .collect();
+        assert_eq!(
+            relocations,
+            [
+                (RelocationType::Relative, 0x2150, ""),
+                (RelocationType::GlobDat, 0x2120, "value"),
+                (RelocationType::_64, 0x2158, "value"),
+                (RelocationType::IRelative, 0x2168, ""),
+                (RelocationType::JumpSlot, 0x2140, "func"),
+            ]
+        );
+    }
+
+    /// `librel.so`, with its dynamic entries changed by `patch`
+    fn patched_librel(patch: impl FnOnce(&mut [u8])) -> &'static [u8] {
+        let mut librel = include_bytes!("../../tests/fixtures/librel.so").to_vec();
+        let file = ParsedElf::parse_or_print_error(&librel).unwrap();
+        let dynamic = file
+            .program_headers
+            .iter()
+            .find(|ph| ph.typ == SegmentType::Dynamic)
+            .unwrap();
+        let range = dynamic.file_range();
+        patch(&mut librel[range.start.0 as usize..range.end.0 as usize]);
+        Box::leak(librel.into_boxed_slice())
+    }
+
+    #[test_case]
+    fn malformed_dynamic_entries_are_reported() {
+        // A tag that doesn't exist
+        let librel = patched_librel(|dynamic| {
+            dynamic[..8].copy_from_slice(&0x7000_1234u64.to_le_bytes());
+        });
+        assert!(matches!(
+            Process::new().load_object(librel),
+            Err(LoadError::ReadSymsError(ReadSymsError::ReadDynamicError(_)))
+        ));
+
+        // PLT relocations without addends
+        let librel = patched_librel(|dynamic| {
+            let pltrel = dynamic
+                .chunks_mut(16)
+                .find(|entry| entry[..8] == (DynamicTag::PltRel as u64).to_le_bytes())
+                .unwrap();
+            pltrel[8..].copy_from_slice(&(DynamicTag::Rel as u64).to_le_bytes());
+        });
+        assert!(matches!(
+            Process::new().load_object(librel),
+            Err(LoadError::ReadRelaError(ReadRelaError::UnsupportedPltRel(
+                17
+            )))
+        ));
+    }
+
+    #[test_case]
+    fn symbol_names_past_the_string_table_are_reported() {
+        let mut librel = include_bytes!("../../tests/fixtures/librel.so").to_vec();
+        let file = ParsedElf::parse_or_print_error(&librel).unwrap();
+        let dynamic = file.dynamic_section().unwrap().unwrap();
+        let entry = dynamic.entry_with_tag(DynamicTag::SymTab).unwrap();
+        let symtab = file.data_at_addr(entry.addr.unwrap_address()).unwrap();
+        // The name of the first symbol after the null one
+        let name = symtab.as_ptr() as usize - librel.as_ptr() as usize + Sym::SIZE;
+        librel[name..name + 4].copy_from_slice(&0xffff_fff0u32.to_le_bytes());
+
+        assert!(matches!(
+            Process::new().load_object(Box::leak(librel.into_boxed_slice())),
+            Err(LoadError::ReadSymsError(ReadSymsError::ParsingError(_)))
+        ));
+    }
+
+    #[test_case]
+    fn thread_local_and_unique_symbols_are_read() {
+        let mut process = Process::new();
+        let index = process
+            .load_object(include_bytes!("../../tests/fixtures/libtls.so"))
+            .unwrap();
+        let obj = &process.objects[index];
+
+        let counter = obj.symbol("counter").unwrap();
+        assert!(matches!(counter.r#type, SymType::Tls));
+        assert!(matches!(counter.bind, SymBind::Global));
+        let instance = obj.symbol("instance").unwrap();
+        assert!(matches!(instance.r#type, SymType::Object));
+        assert!(matches!(instance.bind, SymBind::GnuUnique));
+    }
+
+    #[test_case]
+    fn relocations_are_applied() {
+        let mut process = Process::new();
+        let index = process
+            .load_object(include_bytes!("../../tests/fixtures/librel.so"))
+            .unwrap();
+        process.apply_relocations().unwrap();
+
+        let base = process.objects[index].base;
+        let word = |offset: u64| unsafe { ((base.0 + offset) as *const u64).read() };
+        // The GOT entry of `value`, the pointer to its address plus 8, the
+        // one to the address of the pointer itself, the PLT slot of `func`,
+        // and the function `pick` chose
+        assert_eq!(word(0x2120), base.0 + 0x2148);
+        assert_eq!(word(0x2158), base.0 + 0x2150);
+        assert_eq!(word(0x2150), base.0 + 0x2160);
+        assert_eq!(word(0x2140), base.0 + 0x1020);
+        assert_eq!(word(0x2168), base.0 + 0x1033);
+    }
+
+    #[test_case]
+    fn relocatable_objects_are_linked_against_loaded_ones() {
+        let mut process = Process::new();
+        let lib = process
+            .load_object(include_bytes!("../../tests/fixtures/librel.so"))
+            .unwrap();
+        let index = process
+            .load_object(include_bytes!("../../tests/fixtures/reloc.o"))
+            .unwrap();
+        process.apply_relocations().unwrap();
+
+        let lib = process.objects[lib].base.0;
+        let obj = &process.objects[index];
+        let addr = |name: &str| obj.symbol_addr(obj.symbol(name).unwrap()).0;
+        let word = |addr: u64| unsafe { (addr as *const u64).read() };
+        let half = |addr: u64| unsafe { (addr as *const u32).read() };
+        // Where the 32-bit displacement at `place`, which ends an
+        // instruction, points to
+        let target = |place: u64| (place + 4).wrapping_add(half(place) as i32 as u64);
+
+        // The GOT entry of `value`, and the PLT entry of `func`, which jumps
+        // through the GOT entry of `func`
+        assert_eq!(word(target(addr("get_value") + 3)), lib + 0x2148);
+        let plt = target(addr("call_func") + 1);
+        assert_eq!(unsafe { (plt as *const [u8; 2]).read() }, [0xFF, 0x25]);
+        assert_eq!(word(target(plt + 2)), lib + 0x1020);
+        // The object's own data, which it only refers to by section
+        assert_eq!(word(target(addr("get_local") + 3)), 5);
+        // The absolute symbol, with and without an addend
+        assert_eq!(addr("limit"), 0x7fff_0000);
+        assert_eq!(half(addr("get_limit") + 1), 0x7fff_0008);
+        assert_eq!(half(addr("get_limit") + 8), 0x7fff_0000);
+
+        // Local symbols are the object's own
+        assert!(obj.symbol("local").is_none());
+        let (def, _) = process.lookup_symbol("counter").unwrap();
+        assert_eq!(def.base, obj.base);
+        assert_eq!(word(addr("counter")), 0);
+    }
+}
@@ -0,0 +1,213 @@
+//! What relocations compute
+//!
+//! Every relocation type of the x86_64 System V ABI (table 4.10) is a
+//! formula over a few values, named after the ABI.
+
+use core::convert::TryFrom;
+
+use crate::delf::{components::rela::RelocationType, process::RelocationError};
+
+/// What a relocation is computed from
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Inputs {
+    /// S: the value of the symbol, once loaded
+    pub symbol: u64,
+    /// Z: the size of the symbol
+    pub symbol_size: u64,
+    /// A: the addend
+    pub addend: i64,
+    /// B: where the object being relocated was loaded
+    pub base: u64,
+    /// P: the address of the place being relocated
+    pub place: u64,
+    /// G + GOT: the address of the GOT entry for the symbol, if it has one
+    pub got_entry: Option<u64>,
+    /// L: the address of the PLT entry for the symbol, if it has one
+    pub plt_entry: Option<u64>,
+}
+
+/// What to do at the place being relocated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value {
+    /// Write a 64-bit word
+    U64(u64),
+    /// Write a 32-bit word
+    U32(u32),
+    /// Copy `len` bytes from `src`
+    Copy { src: u64, len: u64 },
+    /// Call the function at `resolver`, and write the 64-bit word it returns
+    Indirect { resolver: u64 },
+}
+
+/// Compute the relocation `typ` from `inputs`
+///
+/// The loader only makes PLT entries for relocatable objects, so a symbol
+/// without one (L) is called directly.
+pub fn compute(typ: RelocationType, inputs: &Inputs) -> Result<Value, RelocationError> {
+    use RelocationType as RT;
+
+    let Inputs {
+        symbol: s,
+        symbol_size,
+        addend: a,
+        base: b,
+        place: p,
+        got_entry,
+        plt_entry,
+    } = *inputs;
+    let plus = |value: u64| value.wrapping_add(a as u64);
+    let signed = |value: u64| {
+        i32::try_from(value as i64)
+            .map(|value| Value::U32(value as u32))
+            .map_err(|_| RelocationError::Overflow(typ, value))
+    };
+
+    Ok(match typ {
+        RT::_64 => Value::U64(plus(s)),
+        RT::Pc32 => signed(plus(s).wrapping_sub(p))?,
+        RT::Plt32 => signed(plus(plt_entry.unwrap_or(s)).wrapping_sub(p))?,
+        RT::Copy => Value::Copy {
+            src: s,
+            len: symbol_size,
+        },
+        RT::GlobDat | RT::JumpSlot => Value::U64(s),
+        RT::Relative => Value::U64(plus(b)),
+        RT::GotPcRel | RT::GotPcRelX | RT::RexGotPcRelX => {
+            let got_entry = got_entry.ok_or(RelocationError::NoGotEntry)?;
+            signed(plus(got_entry).wrapping_sub(p))?
+        }
+        RT::_32 => {
+            let value = plus(s);
+            Value::U32(u32::try_from(value).map_err(|_| RelocationError::Overflow(typ, value))?)
+        }
+        RT::_32S => signed(plus(s))?,
+        RT::IRelative => Value::Indirect { resolver: plus(b) },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compute, Inputs, Value};
+    use crate::delf::{components::rela::RelocationType as RT, process::RelocationError};
+
+    const INPUTS: Inputs = Inputs {
+        symbol: 0x2000_0000_1000,
+        symbol_size: 16,
+        addend: 8,
+        base: 0x2000_0000_0000,
+        place: 0x2000_0000_3000,
+        got_entry: Some(0x2000_0000_2000),
+        plt_entry: None,
+    };
+
+    #[test_case]
+    fn every_type() {
+        let cases = [
+            (RT::_64, Value::U64(0x2000_0000_1008)),
+            (RT::Pc32, Value::U32(-0x1ff8i32 as u32)),
+            (RT::Plt32, Value::U32(-0x1ff8i32 as u32)),
+            (
+                RT::Copy,
+                Value::Copy {
+                    src: 0x2000_0000_1000,
+                    len: 16,
+                },
+            ),
+            (RT::GlobDat, Value::U64(0x2000_0000_1000)),
+            (RT::JumpSlot, Value::U64(0x2000_0000_1000)),
+            (RT::Relative, Value::U64(0x2000_0000_0008)),
+            (RT::GotPcRel, Value::U32(-0xff8i32 as u32)),
+            (RT::GotPcRelX, Value::U32(-0xff8i32 as u32)),
+            (RT::RexGotPcRelX, Value::U32(-0xff8i32 as u32)),
+            (
+                RT::IRelative,
+                Value::Indirect {
+                    resolver: 0x2000_0000_0008,
+                },
+            ),
+        ];
+        for &(typ, expected) in cases.iter() {
+            assert_eq!(compute(typ, &INPUTS).unwrap(), expected, "{:?}", typ);
+        }
+    }
+
+    #[test_case]
+    fn absolute_32_bit_types() {
+        let low = Inputs {
+            symbol: 0x7FFF_FFF0,
+            addend: -0x10,
+            ..INPUTS
+        };
+        let cases = [
+            (RT::_32, low, Some(Value::U32(0x7FFF_FFE0))),
+            (RT::_32S, low, Some(Value::U32(0x7FFF_FFE0))),
+            (
+                RT::_32,
+                Inputs {
+                    addend: 0x20,
+                    ..low
+                },
+                Some(Value::U32(0x8000_0010)),
+            ),
+            (
+                RT::_32S,
+                Inputs {
+                    addend: 0x20,
+                    ..low
+                },
+                None,
+            ),
+            (
+                RT::_32S,
+                Inputs {
+                    symbol: -0x10i64 as u64,
+                    addend: 0,
+                    ..low
+                },
+                Some(Value::U32(-0x10i32 as u32)),
+            ),
+            (RT::_32, INPUTS, None),
+        ];
+        for &(typ, inputs, expected) in cases.iter() {
+            match (compute(typ, &inputs), expected) {
+                (Ok(value), Some(expected)) => assert_eq!(value, expected, "{:?}", typ),
+                (Err(RelocationError::Overflow(..)), None) => {}
+                (result, _) => panic!("{:?} of {:?} gave {:?}", typ, inputs, result),
+            }
+        }
+    }
+
+    #[test_case]
+    fn pc_relative_overflow() {
+        let far = Inputs {
+            place: 0x1000,
+            ..INPUTS
+        };
+        assert!(matches!(
+            compute(RT::Pc32, &far),
+            Err(RelocationError::Overflow(RT::Pc32, _))
+        ));
+        let no_got = Inputs {
+            got_entry: None,
+            ..INPUTS
+        };
+        assert!(matches!(
+            compute(RT::GotPcRel, &no_got),
+            Err(RelocationError::NoGotEntry)
+        ));
+    }
+
+    #[test_case]
+    fn calls_go_through_plt_entries() {
+        let plt = Inputs {
+            plt_entry: Some(0x2000_0000_4000),
+            ..INPUTS
+        };
+        assert_eq!(compute(RT::Plt32, &plt).unwrap(), Value::U32(0x1008));
+        // Only calls do
+        assert_eq!(
+            compute(RT::Pc32, &plt).unwrap(),
+            Value::U32(-0x1ff8i32 as u32)
+        );
+    }
+}
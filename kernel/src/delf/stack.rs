@@ -0,0 +1,174 @@
+//! The initial stack of a program
+//!
+//! Programs start with the layout described by the System V ABI (AMD64
+//! supplement, section 3.4.1). From the stack pointer up:
+//! - `argc`;
+//! - `argv`, then a null pointer;
+//! - `envp`, then a null pointer;
+//! - the auxiliary vector: (type, value) pairs, ending with [`auxv::AT_NULL`];
+//! - the strings and bytes the pointers above point to.
+
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+/// Types of auxiliary vector entries
+pub mod auxv {
+    /// The end of the vector
+    pub const AT_NULL: u64 = 0;
+    /// The address of the program headers of the executable
+    pub const AT_PHDR: u64 = 3;
+    /// The size of a program header
+    pub const AT_PHENT: u64 = 4;
+    /// The number of program headers
+    pub const AT_PHNUM: u64 = 5;
+    /// The size of a page
+    pub const AT_PAGESZ: u64 = 6;
+    /// The base address of the interpreter
+    pub const AT_BASE: u64 = 7;
+    /// The entry point of the executable
+    pub const AT_ENTRY: u64 = 9;
+    /// The features of the CPU
+    pub const AT_HWCAP: u64 = 16;
+    /// The address of 16 random bytes
+    pub const AT_RANDOM: u64 = 25;
+}
+
+/// Lay out the initial stack in `stack`, the memory right below the user
+/// address `top`, and return the initial stack pointer
+///
+/// [`auxv::AT_RANDOM`] is added to `auxv`, pointing to a copy of `random`.
+/// Returns `None` if everything doesn't fit.
+pub fn build(
+    stack: &mut [u8],
+    top: u64,
+    args: &[&str],
+    env: &[&str],
+    auxv: &[(u64, u64)],
+    random: [u8; 16],
+) -> Option<u64> {
+    let mut stack = Stack {
+        bottom: top.checked_sub(stack.len() as u64)?,
+        sp: top,
+        memory: stack,
+    };
+
+    let random = stack.push_bytes(&random)?;
+    let mut push_strings = |strings: &[&str]| {
+        let mut pointers = Vec::with_capacity(strings.len());
+        for string in strings.iter().rev() {
+            stack.push_bytes(&[0])?;
+            pointers.push(stack.push_bytes(string.as_bytes())?);
+        }
+        pointers.reverse();
+        Some(pointers)
+    };
+    let env = push_strings(env)?;
+    let args = push_strings(args)?;
+
+    // Everything below is made of words, and `argc` must end up 16-byte
+    // aligned
+    let words = 1 + (args.len() + 1) + (env.len() + 1) + 2 * (auxv.len() + 2);
+    let sp = stack.sp.checked_sub((words * size_of::<u64>()) as u64)? & !0xF;
+    if sp < stack.bottom {
+        return None;
+    }
+    stack.sp = sp;
+
+    let mut at = sp;
+    let mut write = |value: u64| {
+        stack.write(at, &value.to_le_bytes());
+        at += size_of::<u64>() as u64;
+    };
+    write(args.len() as u64);
+    args.iter().chain(&[0]).for_each(|&arg| write(arg));
+    env.iter().chain(&[0]).for_each(|&var| write(var));
+    for &(typ, value) in auxv {
+        write(typ);
+        write(value);
+    }
+    write(auxv::AT_RANDOM);
+    write(random);
+    write(auxv::AT_NULL);
+    write(0);
+
+    Some(sp)
+}
+
+/// A view of the stack memory, addressed with user addresses
+struct Stack<'a> {
+    memory: &'a mut [u8],
+    /// The user address of `memory[0]`
+    bottom: u64,
+    sp: u64,
+}
+
+impl Stack<'_> {
+    /// Push `bytes`, returning their address
+    fn push_bytes(&mut self, bytes: &[u8]) -> Option<u64> {
+        let sp = self.sp.checked_sub(bytes.len() as u64)?;
+        if sp < self.bottom {
+            return None;
+        }
+        self.sp = sp;
+        self.write(sp, bytes);
+        Some(sp)
+    }
+
+    fn write(&mut self, addr: u64, bytes: &[u8]) {
+        let start = (addr - self.bottom) as usize;
+        self.memory[start..start + bytes.len()].copy_from_slice(bytes);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{auxv::*, build};
+    use alloc::vec;
+    use core::convert::TryInto;
+
+    const TOP: u64 = 0x10_0000;
+
+    #[test_case]
+    fn layout() {
+        let mut memory = vec![0xFF; 512];
+        let bottom = TOP - memory.len() as u64;
+        let sp = build(
+            &mut memory,
+            TOP,
+            &["prog", "-v"],
+            &["HOME=/"],
+            &[(AT_PAGESZ, 4096)],
+            [7; 16],
+        )
+        .unwrap();
+        assert_eq!(sp % 16, 0);
+
+        let word = |addr: u64| {
+            let start = (addr - bottom) as usize;
+            u64::from_le_bytes(memory[start..start + 8].try_into().unwrap())
+        };
+        let string = |addr: u64| {
+            let start = (addr - bottom) as usize;
+            let len = memory[start..].iter().position(|&b| b == 0).unwrap();
+            core::str::from_utf8(&memory[start..start + len]).unwrap()
+        };
+
+        assert_eq!(word(sp), 2);
+        assert_eq!(string(word(sp + 8)), "prog");
+        assert_eq!(string(word(sp + 16)), "-v");
+        assert_eq!(word(sp + 24), 0);
+        assert_eq!(string(word(sp + 32)), "HOME=/");
+        assert_eq!(word(sp + 40), 0);
+        assert_eq!((word(sp + 48), word(sp + 56)), (AT_PAGESZ, 4096));
+        assert_eq!(word(sp + 64), AT_RANDOM);
+        let random = (word(sp + 72) - bottom) as usize;
+        assert_eq!(memory[random..random + 16], [7; 16]);
+        assert_eq!((word(sp + 80), word(sp + 88)), (AT_NULL, 0));
+    }
+
+    #[test_case]
+    fn too_small() {
+        let mut memory = vec![0; 64];
+        assert_eq!(build(&mut memory, TOP, &["prog"], &[], &[], [0; 16]), None);
+    }
+}
@@ -0,0 +1,240 @@
+//! A cooperative executor for `async` code
+//!
+//! This is a lighter alternative to spawning a [task](crate::sched::Task)
+//! for work that mostly waits for events: any number of futures can share a
+//! single kernel task. Futures are polled again only once their [`Waker`]
+//! is woken, which only pushes to a lock-free queue, and so can be done
+//! from interrupt handlers. When no future is ready, the executor halts the
+//! CPU until the next interrupt.
+
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, task::Wake};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use crossbeam_queue::ArrayQueue;
+use x86_64::instructions::interrupts;
+
+use crate::{deferred, sched};
+
+/// The maximum number of futures that can be woken at the same time
+pub const QUEUE_SIZE: usize = 128;
+
+/// A unique identifier of an [`AsyncTask`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct AsyncTaskId(u64);
+
+impl AsyncTaskId {
+    fn new() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        AsyncTaskId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// A future spawned on an [`Executor`]
+pub struct AsyncTask {
+    id: AsyncTaskId,
+    future: Pin<Box<dyn Future<Output = ()> + Send>>,
+}
+
+impl AsyncTask {
+    pub fn new(future: impl Future<Output = ()> + Send + 'static) -> Self {
+        Self {
+            id: AsyncTaskId::new(),
+            future: Box::pin(future),
+        }
+    }
+
+    fn poll(&mut self, context: &mut Context) -> Poll<()> {
+        self.future.as_mut().poll(context)
+    }
+}
+
+/// Runs futures until they complete
+pub struct Executor {
+    tasks: BTreeMap<AsyncTaskId, AsyncTask>,
+    /// The tasks that have been woken, and must be polled again
+    ready: Arc<ArrayQueue<AsyncTaskId>>,
+    wakers: BTreeMap<AsyncTaskId, Waker>,
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Self {
+            tasks: BTreeMap::new(),
+            ready: Arc::new(ArrayQueue::new(QUEUE_SIZE)),
+            wakers: BTreeMap::new(),
+        }
+    }
+
+    /// Add a future to the executor, which will poll it at least once
+    pub fn spawn(&mut self, task: AsyncTask) {
+        let id = task.id;
+        assert!(
+            self.tasks.insert(id, task).is_none(),
+            "A task with the same ID was already spawned"
+        );
+        self.ready.push(id).expect("The ready queue is full");
+    }
+
+    /// Whether all futures have completed
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Poll every future that has been woken, until none is left
+    pub fn run_ready_tasks(&mut self) {
+        while let Some(id) = self.ready.pop() {
+            let task = match self.tasks.get_mut(&id) {
+                Some(task) => task,
+                // The task already completed
+                None => continue,
+            };
+
+            let ready = &self.ready;
+            let waker = self
+                .wakers
+                .entry(id)
+                .or_insert_with(|| Waker::from(Arc::new(AsyncTaskWaker::new(id, ready.clone()))));
+            let mut context = Context::from_waker(waker);
+            if let Poll::Ready(()) = task.poll(&mut context) {
+                self.tasks.remove(&id);
+                self.wakers.remove(&id);
+            }
+        }
+    }
+
+    /// Run the futures forever, sleeping while none of them is ready
+    pub fn run(&mut self) -> ! {
+        loop {
+            self.run_ready_tasks();
+            self.sleep_if_idle();
+        }
+    }
+
+    /// Like [`crate::idle`], but also wake up when a future is woken
+    fn sleep_if_idle(&self) {
+        deferred::run_pending();
+        sched::yield_now();
+
+        // A future woken by an interrupt between the check and `hlt` would
+        // wait for the next interrupt to be polled
+        interrupts::disable();
+        if self.ready.is_empty() && !deferred::has_pending() {
+            interrupts::enable_and_hlt();
+        } else {
+            interrupts::enable();
+        }
+    }
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct AsyncTaskWaker {
+    id: AsyncTaskId,
+    ready: Arc<ArrayQueue<AsyncTaskId>>,
+}
+
+impl AsyncTaskWaker {
+    fn new(id: AsyncTaskId, ready: Arc<ArrayQueue<AsyncTaskId>>) -> Self {
+        Self { id, ready }
+    }
+
+    fn wake_task(&self) {
+        // If the queue is full, the task is polled after some other task
+        // wakes up and frees a slot: just like a missed interrupt, this
+        // only delays it
+        let _ = self.ready.push(self.id);
+    }
+}
+
+impl Wake for AsyncTaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_task();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_task();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AsyncTask, Executor};
+    use crate::interrupts::irq::{line, register_irq, unregister_irq, IrqReturn};
+    use core::{
+        future::Future,
+        pin::Pin,
+        sync::atomic::{AtomicBool, Ordering},
+        task::{Context, Poll},
+    };
+    use futures_util::task::AtomicWaker;
+
+    #[test_case]
+    fn run_to_completion() {
+        static DONE: AtomicBool = AtomicBool::new(false);
+
+        async fn answer() -> u32 {
+            42
+        }
+
+        let mut executor = Executor::new();
+        executor.spawn(AsyncTask::new(async {
+            assert_eq!(answer().await, 42);
+            DONE.store(true, Ordering::SeqCst);
+        }));
+        executor.run_ready_tasks();
+
+        assert!(DONE.load(Ordering::SeqCst));
+        assert!(executor.is_empty());
+    }
+
+    static FIRED: AtomicBool = AtomicBool::new(false);
+    static WAKER: AtomicWaker = AtomicWaker::new();
+
+    fn wake_from_irq(_: u8) -> IrqReturn {
+        FIRED.store(true, Ordering::SeqCst);
+        WAKER.wake();
+        IrqReturn::Handled
+    }
+
+    struct Interrupt;
+
+    impl Future for Interrupt {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+            WAKER.register(cx.waker());
+            if FIRED.load(Ordering::SeqCst) {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        }
+    }
+
+    #[test_case]
+    fn woken_by_interrupt() {
+        let mut executor = Executor::new();
+        executor.spawn(AsyncTask::new(Interrupt));
+        executor.run_ready_tasks();
+        assert!(!executor.is_empty());
+
+        // Wait for the next timer interrupt to wake the future
+        register_irq(line::TIMER, wake_from_irq, "executor test").unwrap();
+        while !FIRED.load(Ordering::SeqCst) {
+            x86_64::instructions::hlt();
+        }
+        unregister_irq(line::TIMER, wake_from_irq).unwrap();
+
+        executor.run_ready_tasks();
+        assert!(executor.is_empty());
+    }
+}
@@ -0,0 +1,257 @@
+//! Floating point, SSE and AVX state
+//!
+//! The kernel itself is built with soft-float and never touches the FPU or
+//! the vector registers, but user code does. Each task owns an [`FpuState`],
+//! which is saved when the task is switched out and restored right before
+//! it runs again. Switching is eager: saving on every switch
+//! costs a bit more than trapping the first use through `CR0.TS`, but it's
+//! much simpler, and it never leaks state between contexts.
+//!
+//! The state is saved with `xsave` when available, which also covers AVX,
+//! and with `fxsave` (x87 and SSE only) otherwise.
+
+use alloc::alloc::{alloc, dealloc, handle_alloc_error, Layout};
+use core::{arch::asm, ptr};
+
+use spin::Once;
+use x86_64::registers::{
+    control::{Cr0, Cr0Flags, Cr4, Cr4Flags},
+    xcontrol::{XCr0, XCr0Flags},
+};
+
+use crate::cpu;
+
+/// The alignment required by `xsave` (`fxsave` only needs 16 bytes)
+const ALIGN: usize = 64;
+/// The size of the area used by `fxsave`
+const FXSAVE_SIZE: usize = 512;
+/// The default value of `MXCSR`: all exceptions masked
+const MXCSR_DEFAULT: u32 = 0x1F80;
+
+/// How extended state is saved on this CPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveMethod {
+    Fxsave,
+    Xsave { features: XCr0Flags },
+}
+
+struct Config {
+    method: SaveMethod,
+    size: usize,
+    /// The state after a reset, which new contexts start from
+    initial: FpuState,
+}
+
+static CONFIG: Once<Config> = Once::new();
+
+/// Enable the FPU, SSE and (if present) AVX, and find out how much space
+/// their state takes
+///
+/// Must be called after the heap has been initialized
+pub fn init() {
+    let features = cpu::features();
+    assert!(features.fxsr && features.sse, "SSE is required");
+
+    unsafe {
+        Cr0::update(|flags| {
+            flags.remove(Cr0Flags::EMULATE_COPROCESSOR | Cr0Flags::TASK_SWITCHED);
+            flags.insert(Cr0Flags::MONITOR_COPROCESSOR | Cr0Flags::NUMERIC_ERROR);
+        });
+        Cr4::update(|flags| flags.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE));
+    }
+
+    let method = if features.xsave {
+        let mut enabled = XCr0Flags::X87 | XCr0Flags::SSE;
+        if features.avx {
+            enabled |= XCr0Flags::AVX;
+        }
+        unsafe {
+            Cr4::update(|flags| flags.insert(Cr4Flags::OSXSAVE));
+            XCr0::write(enabled);
+        }
+        SaveMethod::Xsave { features: enabled }
+    } else {
+        SaveMethod::Fxsave
+    };
+
+    // With xsave, the size depends on the features enabled in XCR0
+    let size = match method {
+        SaveMethod::Xsave { .. } => cpu::cpuid(0xD, 0).ebx as usize,
+        SaveMethod::Fxsave => FXSAVE_SIZE,
+    };
+
+    CONFIG.call_once(|| {
+        let mut initial = FpuState::alloc(size);
+        unsafe {
+            asm!("fninit");
+            asm!("ldmxcsr [{}]", in(reg) &MXCSR_DEFAULT, options(nostack, readonly));
+            initial.save_with(method);
+        }
+        Config {
+            method,
+            size,
+            initial,
+        }
+    });
+}
+
+fn config() -> &'static Config {
+    CONFIG.get().expect("The FPU has not been initialized yet")
+}
+
+/// How extended state is saved
+pub fn save_method() -> SaveMethod {
+    config().method
+}
+
+/// The size in bytes of an [`FpuState`]
+pub fn state_size() -> usize {
+    config().size
+}
+
+/// Put the FPU, SSE and AVX registers in their initial state, so that the
+/// next context doesn't see the registers of the previous one
+pub fn reset() {
+    unsafe { config().initial.restore() };
+}
+
+/// A saved copy of the FPU, SSE and AVX registers
+pub struct FpuState {
+    area: *mut u8,
+    size: usize,
+}
+
+unsafe impl Send for FpuState {}
+unsafe impl Sync for FpuState {}
+
+impl FpuState {
+    /// A state with every register in its initial state
+    pub fn new() -> Self {
+        let config = config();
+        let state = Self::alloc(config.size);
+        unsafe { ptr::copy_nonoverlapping(config.initial.area, state.area, config.size) };
+        state
+    }
+
+    fn alloc(size: usize) -> Self {
+        let layout = Self::layout(size);
+        let area = unsafe { alloc(layout) };
+        if area.is_null() {
+            handle_alloc_error(layout);
+        }
+        // The xsave header must be valid, and zero is valid
+        unsafe { ptr::write_bytes(area, 0, size) };
+        Self { area, size }
+    }
+
+    fn layout(size: usize) -> Layout {
+        Layout::from_size_align(size, ALIGN).unwrap()
+    }
+
+    /// Save the registers of the CPU into this state
+    ///
+    /// # Safety
+    ///
+    /// [`init`] must have been called.
+    pub unsafe fn save(&mut self) {
+        self.save_with(config().method);
+    }
+
+    unsafe fn save_with(&mut self, method: SaveMethod) {
+        match method {
+            SaveMethod::Xsave { .. } => asm!(
+                "xsave64 [{}]",
+                in(reg) self.area,
+                in("eax") u32::MAX,
+                in("edx") u32::MAX,
+                options(nostack),
+            ),
+            SaveMethod::Fxsave => asm!("fxsave64 [{}]", in(reg) self.area, options(nostack)),
+        }
+    }
+
+    /// Load this state into the registers of the CPU
+    ///
+    /// # Safety
+    ///
+    /// [`init`] must have been called.
+    pub unsafe fn restore(&self) {
+        match config().method {
+            SaveMethod::Xsave { .. } => asm!(
+                "xrstor64 [{}]",
+                in(reg) self.area,
+                in("eax") u32::MAX,
+                in("edx") u32::MAX,
+                options(nostack, readonly),
+            ),
+            SaveMethod::Fxsave => {
+                asm!("fxrstor64 [{}]", in(reg) self.area, options(nostack, readonly))
+            }
+        }
+    }
+}
+
+impl Default for FpuState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clone for FpuState {
+    fn clone(&self) -> Self {
+        let state = Self::alloc(self.size);
+        unsafe { ptr::copy_nonoverlapping(self.area, state.area, self.size) };
+        state
+    }
+}
+
+impl Drop for FpuState {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.area, Self::layout(self.size)) };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::FpuState;
+    use crate::user::{test_util::run_user_code, UserExit};
+    use core::arch::asm;
+
+    fn set_xmm0(value: u64) {
+        unsafe { asm!("movq xmm0, {}", in(reg) value) };
+    }
+
+    fn get_xmm0() -> u64 {
+        let value;
+        unsafe { asm!("movq {}, xmm0", out(reg) value) };
+        value
+    }
+
+    #[test_case]
+    fn save_and_restore() {
+        let mut state = FpuState::new();
+        set_xmm0(0x1234_5678);
+        unsafe { state.save() };
+
+        set_xmm0(0);
+        unsafe { state.restore() };
+        assert_eq!(get_xmm0(), 0x1234_5678);
+
+        // New states start from scratch
+        unsafe { FpuState::new().restore() };
+        assert_eq!(get_xmm0(), 0);
+    }
+
+    #[test_case]
+    fn user_code_can_use_sse() {
+        // xorps xmm0, xmm0; exit(0)
+        let code = [
+            0x0f, 0x57, 0xc0, 0xbf, 0x00, 0x00, 0x00, 0x00, 0xb8, 0x3c, 0x00, 0x00, 0x00, 0x0f,
+            0x05,
+        ];
+        assert_eq!(
+            run_user_code(0x0000_1000_0030_0000, &code),
+            UserExit::Exited(0)
+        );
+    }
+}
@@ -1,4 +1,7 @@
+use core::ptr::{addr_of, addr_of_mut};
+
 use lazy_static::lazy_static;
+use x86_64::instructions::segmentation::{Segment, CS, SS};
 use x86_64::structures::{
     gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector},
     tss::TaskStateSegment,
@@ -6,43 +9,108 @@ use x86_64::structures::{
 use x86_64::VirtAddr;
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub const NMI_IST_INDEX: u16 = 1;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
+
+/// How many NMIs can be nested, each one running on its own slot of the
+/// NMI stack (see [`set_nmi_stack_slot`])
+pub const NMI_STACK_SLOTS: usize = 3;
+const NMI_SLOT_SIZE: usize = 4096 * 4;
+
+/// A stack for the IST, aligned like the CPU aligns the stack pointer
+#[repr(align(16))]
+struct Stack<const SIZE: usize>([u8; SIZE]);
+
+static mut DOUBLE_FAULT_STACK: Stack<{ 4096 * 5 }> = Stack([0; 4096 * 5]);
+static mut NMI_STACK: Stack<{ NMI_SLOT_SIZE * NMI_STACK_SLOTS }> =
+    Stack([0; NMI_SLOT_SIZE * NMI_STACK_SLOTS]);
+static mut MACHINE_CHECK_STACK: Stack<{ 4096 * 4 }> = Stack([0; 4096 * 4]);
+
+/// The TSS is mutable, since the NMI handler moves its own IST entry around
+///
+/// The `syscall` entry point reads `rsp0` from here.
+pub(crate) static mut TSS: TaskStateSegment = TaskStateSegment::new();
 
 lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
-            stack_start + STACK_SIZE
-        };
-        tss
-    };
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
+        // The order of these segments matters: `sysret` expects the user
+        // data segment right before the user code segment
         let mut gdt = GlobalDescriptorTable::new();
-        let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        let kernel_code = gdt.add_entry(Descriptor::kernel_code_segment());
+        let kernel_data = gdt.add_entry(Descriptor::kernel_data_segment());
+        let user_data = gdt.add_entry(Descriptor::user_data_segment());
+        let user_code = gdt.add_entry(Descriptor::user_code_segment());
+        let tss = gdt.add_entry(Descriptor::tss_segment(unsafe { &*addr_of!(TSS) }));
         (
             gdt,
             Selectors {
-                code_selector,
-                tss_selector,
+                kernel_code,
+                kernel_data,
+                user_data,
+                user_code,
+                tss,
             },
         )
     };
 }
 
-struct Selectors {
-    code_selector: SegmentSelector,
-    tss_selector: SegmentSelector,
+/// The selectors of every segment in the GDT
+///
+/// User selectors already have their RPL set to 3.
+pub struct Selectors {
+    pub kernel_code: SegmentSelector,
+    pub kernel_data: SegmentSelector,
+    pub user_data: SegmentSelector,
+    pub user_code: SegmentSelector,
+    pub tss: SegmentSelector,
+}
+
+pub fn selectors() -> &'static Selectors {
+    &GDT.1
+}
+
+/// The address right after the end of a stack
+fn stack_top<const SIZE: usize>(stack: *const Stack<SIZE>) -> VirtAddr {
+    VirtAddr::from_ptr(stack) + SIZE
 }
 
 pub fn init() {
+    unsafe {
+        let tss = &mut *addr_of_mut!(TSS);
+        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+            stack_top(addr_of!(DOUBLE_FAULT_STACK));
+        tss.interrupt_stack_table[MACHINE_CHECK_IST_INDEX as usize] =
+            stack_top(addr_of!(MACHINE_CHECK_STACK));
+        set_nmi_stack_slot(0);
+    }
+
     GDT.0.load();
 
     unsafe {
-        x86_64::instructions::segmentation::set_cs(GDT.1.code_selector);
-        x86_64::instructions::tables::load_tss(GDT.1.tss_selector);
+        CS::set_reg(GDT.1.kernel_code);
+        SS::set_reg(GDT.1.kernel_data);
+        x86_64::instructions::tables::load_tss(GDT.1.tss);
     }
 }
+
+/// A pointer to the stack used when entering ring 0 from ring 3 (`rsp0`)
+///
+/// It's a raw pointer since the TSS is packed, so the field isn't aligned.
+pub fn kernel_stack_ptr() -> *mut u64 {
+    unsafe { addr_of_mut!((*addr_of_mut!(TSS)).privilege_stack_table[0]) as *mut u64 }
+}
+
+/// Make the next NMI start at the given slot of the NMI stack
+///
+/// Slot 0 is at the top of the stack, and each following slot lies right
+/// below the previous one. Slots past the last one reuse the last slot.
+///
+/// # Safety
+///
+/// The code currently running on the NMI stack must only be using the
+/// slots before `slot`, or its stack will be overwritten by the next NMI.
+pub unsafe fn set_nmi_stack_slot(slot: usize) {
+    let slot = slot.min(NMI_STACK_SLOTS - 1);
+    let top = stack_top(addr_of!(NMI_STACK)) - (slot * NMI_SLOT_SIZE) as u64;
+    (*addr_of_mut!(TSS)).interrupt_stack_table[NMI_IST_INDEX as usize] = top;
+}
@@ -0,0 +1,279 @@
+//! Handlers for the architecturally defined CPU exceptions
+//!
+//! Every exception enters through the stubs in [`trap`](super::trap) and
+//! ends up in [`dispatch`], which decodes the error code, dumps the state
+//! of the CPU and then decides what to do about it (see [`FaultAction`]).
+
+use core::fmt;
+
+use x86_64::{registers::control::Cr2, structures::idt::PageFaultErrorCode};
+
+use super::trap::TrapFrame;
+use crate::{backtrace::Backtrace, println, user::UserExit};
+
+/// What to do after an exception has been handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultAction {
+    /// The exception is harmless, so execution can just continue
+    Resume,
+    /// The exception was caused by a task, which must be terminated
+    KillTask,
+    /// The kernel itself is in an inconsistent state
+    Panic,
+}
+
+/// Exception vectors, as defined by the Intel SDM (Vol. 3A, 6.3.1)
+pub mod vector {
+    pub const DIVIDE_ERROR: u8 = 0;
+    pub const DEBUG: u8 = 1;
+    pub const NMI: u8 = 2;
+    pub const BREAKPOINT: u8 = 3;
+    pub const OVERFLOW: u8 = 4;
+    pub const BOUND_RANGE: u8 = 5;
+    pub const INVALID_OPCODE: u8 = 6;
+    pub const DEVICE_NOT_AVAILABLE: u8 = 7;
+    pub const DOUBLE_FAULT: u8 = 8;
+    pub const INVALID_TSS: u8 = 10;
+    pub const SEGMENT_NOT_PRESENT: u8 = 11;
+    pub const STACK_SEGMENT: u8 = 12;
+    pub const GENERAL_PROTECTION: u8 = 13;
+    pub const PAGE_FAULT: u8 = 14;
+    pub const X87_FLOATING_POINT: u8 = 16;
+    pub const ALIGNMENT_CHECK: u8 = 17;
+    pub const MACHINE_CHECK: u8 = 18;
+    pub const SIMD_FLOATING_POINT: u8 = 19;
+    pub const VIRTUALIZATION: u8 = 20;
+    pub const CONTROL_PROTECTION: u8 = 21;
+    pub const HV_INJECTION: u8 = 28;
+    pub const VMM_COMMUNICATION: u8 = 29;
+    pub const SECURITY: u8 = 30;
+}
+
+/// Get a human-readable name for an exception vector
+pub fn name(vector: u8) -> &'static str {
+    match vector {
+        vector::DIVIDE_ERROR => "DIVIDE ERROR",
+        vector::DEBUG => "DEBUG",
+        vector::NMI => "NON-MASKABLE INTERRUPT",
+        vector::BREAKPOINT => "BREAKPOINT",
+        vector::OVERFLOW => "OVERFLOW",
+        vector::BOUND_RANGE => "BOUND RANGE EXCEEDED",
+        vector::INVALID_OPCODE => "INVALID OPCODE",
+        vector::DEVICE_NOT_AVAILABLE => "DEVICE NOT AVAILABLE",
+        vector::DOUBLE_FAULT => "DOUBLE FAULT",
+        vector::INVALID_TSS => "INVALID TSS",
+        vector::SEGMENT_NOT_PRESENT => "SEGMENT NOT PRESENT",
+        vector::STACK_SEGMENT => "STACK-SEGMENT FAULT",
+        vector::GENERAL_PROTECTION => "GENERAL PROTECTION FAULT",
+        vector::PAGE_FAULT => "PAGE FAULT",
+        vector::X87_FLOATING_POINT => "X87 FLOATING POINT",
+        vector::ALIGNMENT_CHECK => "ALIGNMENT CHECK",
+        vector::MACHINE_CHECK => "MACHINE CHECK",
+        vector::SIMD_FLOATING_POINT => "SIMD FLOATING POINT",
+        vector::VIRTUALIZATION => "VIRTUALIZATION",
+        vector::CONTROL_PROTECTION => "CONTROL PROTECTION",
+        vector::HV_INJECTION => "HYPERVISOR INJECTION",
+        vector::VMM_COMMUNICATION => "VMM COMMUNICATION",
+        vector::SECURITY => "SECURITY",
+        _ => "RESERVED",
+    }
+}
+
+/// The error code pushed by `#TS`, `#NP`, `#SS` and `#GP`
+///
+/// It references the segment selector (or IDT vector) related to the fault.
+#[derive(Clone, Copy)]
+pub struct SelectorErrorCode(pub u64);
+
+/// The descriptor table a [`SelectorErrorCode`] refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescriptorTable {
+    Gdt,
+    Idt,
+    Ldt,
+}
+
+impl SelectorErrorCode {
+    /// The exception originated externally to the processor
+    pub fn external(&self) -> bool {
+        self.0 & 1 != 0
+    }
+
+    /// The table that contains the offending descriptor
+    pub fn table(&self) -> DescriptorTable {
+        match (self.0 >> 1) & 0b11 {
+            0b00 => DescriptorTable::Gdt,
+            0b10 => DescriptorTable::Ldt,
+            _ => DescriptorTable::Idt,
+        }
+    }
+
+    /// The index of the descriptor inside of its table
+    pub fn index(&self) -> u64 {
+        (self.0 >> 3) & 0x1FFF
+    }
+
+    /// A null error code means the fault isn't related to a segment at all
+    pub fn is_null(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl fmt::Debug for SelectorErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_null() {
+            return write!(f, "not segment related");
+        }
+        write!(
+            f,
+            "{:?}[{:#x}]{}",
+            self.table(),
+            self.index(),
+            if self.external() { " (external)" } else { "" }
+        )
+    }
+}
+
+/// The error code pushed by `#CP`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlProtectionError {
+    NearRet,
+    FarRetOrIret,
+    EndBranch,
+    RstorSsp,
+    SetSsBsy,
+    Unknown(u64),
+}
+
+impl From<u64> for ControlProtectionError {
+    fn from(code: u64) -> Self {
+        match code & 0x7FFF {
+            1 => Self::NearRet,
+            2 => Self::FarRetOrIret,
+            3 => Self::EndBranch,
+            4 => Self::RstorSsp,
+            5 => Self::SetSsBsy,
+            other => Self::Unknown(other),
+        }
+    }
+}
+
+/// The entry point for all exceptions, called by `trap_common`
+pub(super) extern "C" fn dispatch(frame: &mut TrapFrame) {
+    let vector = frame.vector as u8;
+    let action = handle(vector, frame);
+    if action != FaultAction::Resume {
+        println!("{}", Backtrace::from_trap(frame));
+    }
+
+    match action {
+        FaultAction::Resume => {}
+        FaultAction::KillTask => unsafe {
+            crate::user::exit_to_kernel(UserExit::Fault {
+                vector,
+                error_code: frame.error_code,
+                rip: frame.rip,
+            })
+        },
+        FaultAction::Panic => panic!("EXCEPTION: {}", name(vector)),
+    }
+}
+
+/// Decode and report an exception, and decide what to do about it
+fn handle(vector: u8, frame: &TrapFrame) -> FaultAction {
+    use vector::*;
+
+    // Faults caused by a task only take down that task, while the same
+    // fault in kernel code means the kernel is broken
+    let kill_or_panic = if frame.from_user() {
+        FaultAction::KillTask
+    } else {
+        FaultAction::Panic
+    };
+
+    match vector {
+        BREAKPOINT => {
+            println!("EXCEPTION: BREAKPOINT at {:#x}", frame.rip);
+            FaultAction::Resume
+        }
+        DEBUG => {
+            println!("EXCEPTION: DEBUG at {:#x}", frame.rip);
+            FaultAction::Resume
+        }
+        NMI => super::nmi::handle(frame),
+        MACHINE_CHECK => super::mce::handle(frame),
+        DOUBLE_FAULT => {
+            report(vector, frame, None);
+            FaultAction::Panic
+        }
+        PAGE_FAULT => {
+            let err_code = PageFaultErrorCode::from_bits_truncate(frame.error_code);
+            report(
+                vector,
+                frame,
+                Some(format_args!(
+                    "Accessed address: {:?}\nError code: {:?}",
+                    Cr2::read(),
+                    err_code
+                )),
+            );
+            kill_or_panic
+        }
+        INVALID_TSS | SEGMENT_NOT_PRESENT | STACK_SEGMENT | GENERAL_PROTECTION => {
+            let err_code = SelectorErrorCode(frame.error_code);
+            report(vector, frame, Some(format_args!("Selector: {:?}", err_code)));
+            kill_or_panic
+        }
+        ALIGNMENT_CHECK => {
+            report(vector, frame, Some(format_args!("Error code: {:#x}", frame.error_code)));
+            kill_or_panic
+        }
+        CONTROL_PROTECTION => {
+            let err_code = ControlProtectionError::from(frame.error_code);
+            report(vector, frame, Some(format_args!("Cause: {:?}", err_code)));
+            kill_or_panic
+        }
+        VMM_COMMUNICATION | SECURITY => {
+            report(vector, frame, Some(format_args!("Error code: {:#x}", frame.error_code)));
+            kill_or_panic
+        }
+        _ => {
+            report(vector, frame, None);
+            kill_or_panic
+        }
+    }
+}
+
+/// Print an exception, some additional details, and the full register state
+fn report(vector: u8, frame: &TrapFrame, details: Option<fmt::Arguments>) {
+    println!(
+        "EXCEPTION: {} (vector {}) in {} mode",
+        name(vector),
+        vector,
+        if frame.from_user() { "user" } else { "kernel" }
+    );
+    if let Some(details) = details {
+        println!("{}", details);
+    }
+    println!("{}", frame);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DescriptorTable, SelectorErrorCode};
+
+    #[test_case]
+    fn selector_error_code() {
+        // Index 5 of the GDT
+        let code = SelectorErrorCode(5 << 3);
+        assert!(!code.external());
+        assert_eq!(code.table(), DescriptorTable::Gdt);
+        assert_eq!(code.index(), 5);
+
+        // Vector 0x80 of the IDT, raised by an external event
+        let code = SelectorErrorCode(0x80 << 3 | 0b011);
+        assert!(code.external());
+        assert_eq!(code.table(), DescriptorTable::Idt);
+        assert_eq!(code.index(), 0x80);
+    }
+}
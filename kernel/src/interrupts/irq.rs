@@ -0,0 +1,294 @@
+//! Registration of handlers for hardware interrupt lines (IRQs)
+//!
+//! Drivers don't install IDT entries themselves: every line of the two
+//! chained PICs gets a generic entry point, which counts the interrupt, runs
+//! all handlers registered for that line and then sends the end of
+//! interrupt. Lines with no handlers are kept masked.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use types::IrqSpinlock;
+use x86_64::{
+    instructions::port::Port,
+    structures::idt::{HandlerFunc, InterruptStackFrame},
+};
+
+use super::{PICS, PIC_1_OFFSET};
+
+/// The number of IRQ lines provided by the two chained PICs
+pub const IRQ_COUNT: usize = 16;
+
+/// The legacy ISA IRQ lines
+pub mod line {
+    pub const TIMER: u8 = 0;
+    pub const KEYBOARD: u8 = 1;
+    pub const CASCADE: u8 = 2;
+    pub const COM2: u8 = 3;
+    pub const COM1: u8 = 4;
+    pub const LPT1: u8 = 7;
+    pub const RTC: u8 = 8;
+    pub const MOUSE: u8 = 12;
+    pub const ATA_PRIMARY: u8 = 14;
+    pub const ATA_SECONDARY: u8 = 15;
+}
+
+/// Whether a handler took care of an interrupt
+///
+/// Lines can be shared between devices, so each handler must check whether
+/// its own device actually raised the interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqReturn {
+    Handled,
+    NotMine,
+}
+
+/// A function handling an interrupt on the given line
+///
+/// Handlers run with interrupts disabled, and must not register or
+/// unregister handlers for their own line. Anything that isn't strictly
+/// necessary to acknowledge the device should be [deferred](crate::deferred).
+pub type IrqHandler = fn(irq: u8) -> IrqReturn;
+
+#[derive(displaydoc::Display, Debug)]
+pub enum IrqError {
+    /// IRQ {0} does not exist
+    InvalidLine(u8),
+    /// IRQ {0} is reserved for the PIC cascade
+    Reserved(u8),
+    /// the handler is not registered on IRQ {0}
+    NotRegistered(u8),
+}
+
+/// Statistics about an IRQ line
+#[derive(Debug, Clone)]
+pub struct IrqStats {
+    pub irq: u8,
+    /// How many times the interrupt was raised
+    pub count: u64,
+    /// How many times no handler recognized the interrupt
+    pub unhandled: u64,
+    /// The names of the registered handlers
+    pub handlers: Vec<&'static str>,
+}
+
+struct IrqAction {
+    handler: IrqHandler,
+    name: &'static str,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_ACTIONS: IrqSpinlock<Vec<IrqAction>> = IrqSpinlock::new(Vec::new());
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU64 = AtomicU64::new(0);
+
+static ACTIONS: [IrqSpinlock<Vec<IrqAction>>; IRQ_COUNT] = [NO_ACTIONS; IRQ_COUNT];
+static COUNTS: [AtomicU64; IRQ_COUNT] = [ZERO; IRQ_COUNT];
+static UNHANDLED: [AtomicU64; IRQ_COUNT] = [ZERO; IRQ_COUNT];
+/// The number of IRQ handlers currently running
+static DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// Mask every line, except for the cascade
+///
+/// Must be called after the PICs have been initialized
+pub fn init() {
+    let _pics = PICS.lock();
+    unsafe {
+        Port::<u8>::new(PIC1_DATA).write(!(1 << line::CASCADE));
+        Port::<u8>::new(PIC2_DATA).write(0xFF);
+    }
+}
+
+/// Register a handler on an IRQ line, unmasking it if necessary
+pub fn register_irq(irq: u8, handler: IrqHandler, name: &'static str) -> Result<(), IrqError> {
+    check_line(irq)?;
+
+    let mut actions = ACTIONS[irq as usize].lock();
+    actions.push(IrqAction { handler, name });
+    if actions.len() == 1 {
+        unmask(irq);
+    }
+    Ok(())
+}
+
+/// Remove a handler from an IRQ line, masking it if no handlers are left
+pub fn unregister_irq(irq: u8, handler: IrqHandler) -> Result<(), IrqError> {
+    check_line(irq)?;
+
+    let mut actions = ACTIONS[irq as usize].lock();
+    let index = actions
+        .iter()
+        .position(|action| action.handler as usize == handler as usize)
+        .ok_or(IrqError::NotRegistered(irq))?;
+
+    actions.remove(index);
+    if actions.is_empty() {
+        mask(irq);
+    }
+    Ok(())
+}
+
+/// Get the statistics of an IRQ line
+pub fn stats(irq: u8) -> Option<IrqStats> {
+    if irq as usize >= IRQ_COUNT {
+        return None;
+    }
+
+    let handlers = ACTIONS[irq as usize]
+        .lock()
+        .iter()
+        .map(|action| action.name)
+        .collect();
+    Some(IrqStats {
+        irq,
+        count: COUNTS[irq as usize].load(Ordering::Relaxed),
+        unhandled: UNHANDLED[irq as usize].load(Ordering::Relaxed),
+        handlers,
+    })
+}
+
+/// Whether this code is running in an IRQ handler
+pub fn in_irq() -> bool {
+    DEPTH.load(Ordering::Relaxed) > 0
+}
+
+fn check_line(irq: u8) -> Result<(), IrqError> {
+    match irq {
+        line::CASCADE => Err(IrqError::Reserved(irq)),
+        _ if irq as usize >= IRQ_COUNT => Err(IrqError::InvalidLine(irq)),
+        _ => Ok(()),
+    }
+}
+
+const PIC1_COMMAND: u16 = 0x20;
+const PIC1_DATA: u16 = 0x21;
+const PIC2_COMMAND: u16 = 0xA0;
+const PIC2_DATA: u16 = 0xA1;
+
+/// Read the In-Service Register of both PICs
+fn read_isr() -> u16 {
+    const OCW3_READ_ISR: u8 = 0x0B;
+    unsafe {
+        Port::<u8>::new(PIC1_COMMAND).write(OCW3_READ_ISR);
+        Port::<u8>::new(PIC2_COMMAND).write(OCW3_READ_ISR);
+        let low = Port::<u8>::new(PIC1_COMMAND).read();
+        let high = Port::<u8>::new(PIC2_COMMAND).read();
+        u16::from(high) << 8 | u16::from(low)
+    }
+}
+
+/// Update the mask bit of an IRQ line
+fn set_masked(irq: u8, masked: bool) {
+    let (port, bit) = if irq < 8 {
+        (PIC1_DATA, irq)
+    } else {
+        (PIC2_DATA, irq - 8)
+    };
+
+    let _pics = PICS.lock();
+    let mut port = Port::<u8>::new(port);
+    unsafe {
+        let mask = port.read();
+        if masked {
+            port.write(mask | 1 << bit);
+        } else {
+            port.write(mask & !(1 << bit));
+        }
+    }
+}
+
+/// Stop the PIC from delivering interrupts on this line
+pub fn mask(irq: u8) {
+    set_masked(irq, true);
+}
+
+/// Allow the PIC to deliver interrupts on this line
+pub fn unmask(irq: u8) {
+    set_masked(irq, false);
+}
+
+/// Run the handlers of an IRQ line, and acknowledge the interrupt
+///
+/// `from_user` tells whether the interrupted code was running in user mode.
+fn dispatch(irq: u8, from_user: bool) {
+    // The PICs raise IRQ 7 and 15 when an interrupt goes away before being
+    // acknowledged. These must not receive an EOI (although the master must
+    // still get one for the cascade, if the slave raised it).
+    if (irq == 7 || irq == 15) && read_isr() & (1 << irq) == 0 {
+        if irq == 15 {
+            unsafe { PICS.lock().notify_end_of_interrupt(PIC_1_OFFSET + line::CASCADE) };
+        }
+        return;
+    }
+
+    COUNTS[irq as usize].fetch_add(1, Ordering::Relaxed);
+    DEPTH.fetch_add(1, Ordering::Relaxed);
+
+    // Every handler on a shared line runs, even after one claimed the IRQ
+    let mut handled = false;
+    for action in ACTIONS[irq as usize].lock().iter() {
+        handled |= (action.handler)(irq) == IrqReturn::Handled;
+    }
+    if !handled {
+        UNHANDLED[irq as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    unsafe {
+        PICS.lock().notify_end_of_interrupt(PIC_1_OFFSET + irq);
+    }
+    DEPTH.fetch_sub(1, Ordering::Relaxed);
+
+    crate::deferred::irq_exit();
+    crate::sched::irq_exit();
+    if from_user {
+        unsafe { crate::user::exit_if_killed() };
+    }
+}
+
+macro_rules! irq_entries {
+    ($($irq: literal),*) => {
+        [$({
+            extern "x86-interrupt" fn entry(frame: InterruptStackFrame) {
+                dispatch($irq, frame.code_segment & 3 == 3);
+            }
+            entry as HandlerFunc
+        }),*]
+    };
+}
+
+/// The IDT entries of every IRQ line, to be installed starting at
+/// [`PIC_1_OFFSET`]
+pub(super) static ENTRIES: [HandlerFunc; IRQ_COUNT] =
+    irq_entries!(0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15);
+
+#[cfg(test)]
+mod test {
+    use super::{register_irq, stats, unregister_irq, IrqReturn};
+    use core::{
+        arch::asm,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    fn test_handler(_: u8) -> IrqReturn {
+        CALLS.fetch_add(1, Ordering::SeqCst);
+        IrqReturn::Handled
+    }
+
+    #[test_case]
+    fn register_and_unregister() {
+        // IRQ 5 is not used by anything, and is raised by software here
+        register_irq(5, test_handler, "test").unwrap();
+        assert_eq!(stats(5).unwrap().handlers, ["test"]);
+
+        unsafe { asm!("int 0x25") };
+        assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+        assert_eq!(stats(5).unwrap().count, 1);
+
+        unregister_irq(5, test_handler).unwrap();
+        unsafe { asm!("int 0x25") };
+        assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+        assert!(stats(5).unwrap().handlers.is_empty());
+    }
+}
@@ -0,0 +1,425 @@
+//! Machine-check exceptions
+//!
+//! The CPU reports hardware errors (memory, cache, bus...) through the
+//! machine-check architecture: a set of banks, each one with a status,
+//! address and miscellaneous register. When an error can't be corrected,
+//! `#MC` is raised, and the handler reads every bank to find out what
+//! happened (Intel SDM Vol. 3B, chapter 16).
+//!
+//! `#MC` runs on its own IST stack, since it can interrupt anything.
+
+use core::fmt;
+
+use x86_64::registers::control::{Cr4, Cr4Flags};
+
+use super::{FaultAction, TrapFrame};
+use crate::{
+    cpu::{
+        self,
+        msr::{self, Ia32McgCap, Ia32McgCtl, Ia32McgStatus, McBank, McgStatus, Register},
+    },
+    println,
+};
+
+/// Whether the CPU supports the machine-check architecture
+fn has_mca() -> bool {
+    let features = cpu::features();
+    features.mce && features.mca
+}
+
+fn banks() -> impl Iterator<Item = McBank> {
+    let count = unsafe { Ia32McgCap::read() }.bank_count();
+    (0..count).map(McBank)
+}
+
+/// Enable reporting of every error, and enable `#MC`
+///
+/// Errors logged before boot (for example, the one that caused the last
+/// reset) are reported and cleared.
+pub fn init() {
+    if !has_mca() {
+        return;
+    }
+
+    unsafe {
+        if Ia32McgCap::read().ctl_present() {
+            Ia32McgCtl::write(u64::MAX);
+        }
+
+        for bank in banks() {
+            let status = BankStatus(msr::read(bank.status()));
+            if status.valid() {
+                println!("MCE: bank {} has an error logged before boot", bank.0);
+                report_bank(bank, status);
+            }
+            msr::write(bank.ctl(), u64::MAX);
+            msr::write(bank.status(), 0);
+        }
+
+        Cr4::update(|flags| flags.insert(Cr4Flags::MACHINE_CHECK_EXCEPTION));
+    }
+}
+
+pub(super) fn handle(frame: &TrapFrame) -> FaultAction {
+    println!(
+        "EXCEPTION: MACHINE CHECK at {:#x} in {} mode",
+        frame.rip,
+        if frame.from_user() { "user" } else { "kernel" }
+    );
+    println!("{}", frame);
+
+    if !has_mca() {
+        return FaultAction::Panic;
+    }
+
+    let mcg_status = unsafe { Ia32McgStatus::read() };
+    let restartable = mcg_status.restart_ip_valid();
+    println!(
+        "Restartable: {}, caused by the interrupted instruction: {}",
+        restartable,
+        mcg_status.error_ip_valid()
+    );
+
+    let mut uncorrected = false;
+    let mut context_corrupt = false;
+    for bank in banks() {
+        let status = BankStatus(unsafe { msr::read(bank.status()) });
+        if !status.valid() {
+            continue;
+        }
+        report_bank(bank, status);
+        uncorrected |= status.uncorrected();
+        context_corrupt |= status.context_corrupt();
+        unsafe { msr::write(bank.status(), 0) };
+    }
+
+    // Clearing MCIP allows another #MC to be delivered, instead of
+    // shutting down the CPU
+    unsafe { Ia32McgStatus::write(McgStatus(0)) };
+
+    if !restartable || context_corrupt {
+        FaultAction::Panic
+    } else if !uncorrected {
+        FaultAction::Resume
+    } else if frame.from_user() {
+        FaultAction::KillTask
+    } else {
+        FaultAction::Panic
+    }
+}
+
+fn report_bank(bank: McBank, status: BankStatus) {
+    println!("Bank {}: {:?}", bank.0, status);
+    if status.addr_valid() {
+        println!("  Address: {:#x}", unsafe { msr::read(bank.addr()) });
+    }
+    if status.misc_valid() {
+        println!("  Misc: {:#x}", unsafe { msr::read(bank.misc()) });
+    }
+}
+
+/// The contents of an `IA32_MCi_STATUS` register
+#[derive(Clone, Copy)]
+pub struct BankStatus(pub u64);
+
+impl BankStatus {
+    /// The register contains an error
+    pub fn valid(&self) -> bool {
+        self.0 & 1 << 63 != 0
+    }
+
+    /// Another error happened while this one was still logged
+    pub fn overflow(&self) -> bool {
+        self.0 & 1 << 62 != 0
+    }
+
+    /// The error was not corrected by the hardware
+    pub fn uncorrected(&self) -> bool {
+        self.0 & 1 << 61 != 0
+    }
+
+    /// Reporting of this error was enabled
+    pub fn enabled(&self) -> bool {
+        self.0 & 1 << 60 != 0
+    }
+
+    /// The `IA32_MCi_MISC` register contains more information
+    pub fn misc_valid(&self) -> bool {
+        self.0 & 1 << 59 != 0
+    }
+
+    /// The `IA32_MCi_ADDR` register contains the address of the error
+    pub fn addr_valid(&self) -> bool {
+        self.0 & 1 << 58 != 0
+    }
+
+    /// The state of the processor might have been corrupted, so it can't
+    /// safely continue
+    pub fn context_corrupt(&self) -> bool {
+        self.0 & 1 << 57 != 0
+    }
+
+    /// The model-specific error code
+    pub fn model_code(&self) -> u16 {
+        (self.0 >> 16) as u16
+    }
+
+    /// The architecturally defined error code
+    pub fn error(&self) -> McaError {
+        McaError::from(self.0 as u16)
+    }
+}
+
+impl fmt::Debug for BankStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (model code {:#x})", self.error(), self.model_code())?;
+        for (set, name) in [
+            (self.uncorrected(), "uncorrected"),
+            (self.context_corrupt(), "context corrupt"),
+            (self.overflow(), "overflow"),
+            (!self.enabled(), "not enabled"),
+        ] {
+            if set {
+                write!(f, ", {}", name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A decoded MCA error code (Intel SDM Vol. 3B, 16.9)
+///
+/// The fields of compound errors are kept as raw values, and only
+/// translated to text when printing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum McaError {
+    NoError,
+    Unclassified,
+    MicrocodeRomParity,
+    External,
+    Frc,
+    InternalParity,
+    SmmHandlerCode,
+    InternalTimer,
+    InternalUnclassified,
+    GenericCache {
+        level: u8,
+    },
+    Tlb {
+        level: u8,
+        kind: u8,
+    },
+    MemoryController {
+        access: u8,
+        channel: u8,
+    },
+    Cache {
+        level: u8,
+        kind: u8,
+        request: u8,
+    },
+    Bus {
+        level: u8,
+        participation: u8,
+        timeout: bool,
+        request: u8,
+        space: u8,
+    },
+    Unknown(u16),
+}
+
+impl From<u16> for McaError {
+    fn from(code: u16) -> Self {
+        match code {
+            0 => return Self::NoError,
+            1 => return Self::Unclassified,
+            2 => return Self::MicrocodeRomParity,
+            3 => return Self::External,
+            4 => return Self::Frc,
+            5 => return Self::InternalParity,
+            6 => return Self::SmmHandlerCode,
+            0x0400 => return Self::InternalTimer,
+            _ if code & 0xFC00 == 0x0400 => return Self::InternalUnclassified,
+            _ => {}
+        }
+
+        // Bit 12 only says whether the error was filtered
+        let code = code & !0x1000;
+        let level = (code & 0b11) as u8;
+        let kind = ((code >> 2) & 0b11) as u8;
+        let request = ((code >> 4) & 0xF) as u8;
+
+        if code & 0xE800 == 0x0800 {
+            Self::Bus {
+                level,
+                space: kind,
+                request,
+                timeout: code & 1 << 8 != 0,
+                participation: ((code >> 9) & 0b11) as u8,
+            }
+        } else if code & 0xFF00 == 0x0100 {
+            Self::Cache {
+                level,
+                kind,
+                request,
+            }
+        } else if code & 0xFF80 == 0x0080 {
+            Self::MemoryController {
+                access: ((code >> 4) & 0b111) as u8,
+                channel: (code & 0xF) as u8,
+            }
+        } else if code & 0xFFF0 == 0x0010 {
+            Self::Tlb { level, kind }
+        } else if code & 0xFFFC == 0x000C {
+            Self::GenericCache { level }
+        } else {
+            Self::Unknown(code)
+        }
+    }
+}
+
+fn level_name(level: u8) -> &'static str {
+    ["L0", "L1", "L2", "generic level"][level as usize & 0b11]
+}
+
+fn kind_name(kind: u8) -> &'static str {
+    ["instruction", "data", "generic", "unknown"][kind as usize & 0b11]
+}
+
+fn request_name(request: u8) -> &'static str {
+    match request {
+        0 => "generic error",
+        1 => "read",
+        2 => "write",
+        3 => "data read",
+        4 => "data write",
+        5 => "instruction fetch",
+        6 => "prefetch",
+        7 => "eviction",
+        8 => "snoop",
+        _ => "unknown request",
+    }
+}
+
+impl fmt::Display for McaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::NoError => write!(f, "no error"),
+            Self::Unclassified => write!(f, "unclassified error"),
+            Self::MicrocodeRomParity => write!(f, "microcode ROM parity error"),
+            Self::External => write!(f, "external error"),
+            Self::Frc => write!(f, "FRC error"),
+            Self::InternalParity => write!(f, "internal parity error"),
+            Self::SmmHandlerCode => write!(f, "SMM handler code access violation"),
+            Self::InternalTimer => write!(f, "internal timer error"),
+            Self::InternalUnclassified => write!(f, "internal unclassified error"),
+            Self::GenericCache { level } => {
+                write!(f, "{} cache hierarchy error", level_name(level))
+            }
+            Self::Tlb { level, kind } => {
+                write!(f, "{} {} TLB error", level_name(level), kind_name(kind))
+            }
+            Self::MemoryController { access, channel } => {
+                let access = match access {
+                    0 => "generic error",
+                    1 => "read",
+                    2 => "write",
+                    3 => "address/command",
+                    4 => "memory scrubbing",
+                    _ => "unknown access",
+                };
+                write!(f, "memory controller error ({}", access)?;
+                match channel {
+                    0xF => write!(f, ")"),
+                    channel => write!(f, ", channel {})", channel),
+                }
+            }
+            Self::Cache {
+                level,
+                kind,
+                request,
+            } => write!(
+                f,
+                "{} {} cache error ({})",
+                level_name(level),
+                kind_name(kind),
+                request_name(request)
+            ),
+            Self::Bus {
+                level,
+                participation,
+                timeout,
+                request,
+                space,
+            } => {
+                let participation = [
+                    "originated by this processor",
+                    "responded to by this processor",
+                    "observed by this processor",
+                    "generic",
+                ][participation as usize & 0b11];
+                let space = ["memory", "reserved", "I/O", "other"][space as usize & 0b11];
+                write!(
+                    f,
+                    "{} bus error ({}, {} {}{})",
+                    level_name(level),
+                    participation,
+                    space,
+                    request_name(request),
+                    if timeout { ", timed out" } else { "" }
+                )
+            }
+            Self::Unknown(code) => write!(f, "unknown error {:#06x}", code),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BankStatus, McaError};
+
+    #[test_case]
+    fn decode_error_codes() {
+        assert_eq!(McaError::from(0x0000), McaError::NoError);
+        assert_eq!(McaError::from(0x0400), McaError::InternalTimer);
+        assert_eq!(McaError::from(0x0405), McaError::InternalUnclassified);
+        // L1 data cache, data read; bit 12 (filtering) is ignored
+        assert_eq!(
+            McaError::from(0x1135),
+            McaError::Cache {
+                level: 1,
+                kind: 1,
+                request: 3
+            }
+        );
+        // Memory write on channel 2
+        assert_eq!(
+            McaError::from(0x00A2),
+            McaError::MemoryController {
+                access: 2,
+                channel: 2
+            }
+        );
+        assert_eq!(McaError::from(0x0014), McaError::Tlb { level: 0, kind: 1 });
+        assert_eq!(McaError::from(0x000F), McaError::GenericCache { level: 3 });
+    }
+
+    #[test_case]
+    fn bank_status() {
+        let status = BankStatus(1 << 63 | 1 << 61 | 1 << 58 | 0x1234 << 16 | 0x0150);
+        assert!(status.valid());
+        assert!(status.uncorrected());
+        assert!(status.addr_valid());
+        assert!(!status.misc_valid());
+        assert!(!status.context_corrupt());
+        assert_eq!(status.model_code(), 0x1234);
+        assert_eq!(
+            status.error(),
+            McaError::Cache {
+                level: 0,
+                kind: 0,
+                request: 5
+            }
+        );
+    }
+}
@@ -0,0 +1,89 @@
+pub mod exceptions;
+pub mod irq;
+pub mod mce;
+pub mod nmi;
+#[path = "../arch/x86_64/trap.rs"]
+mod trap;
+
+pub use exceptions::FaultAction;
+pub use trap::TrapFrame;
+
+use lazy_static::lazy_static;
+use pic8259::ChainedPics;
+use types::IrqSpinlock;
+use x86_64::{structures::idt::InterruptDescriptorTable, PrivilegeLevel};
+
+use crate::{gdt, syscall};
+use exceptions::vector;
+
+pub const PIC_1_OFFSET: u8 = 32;
+pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+
+pub static PICS: IrqSpinlock<ChainedPics> =
+    IrqSpinlock::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+
+lazy_static! {
+    static ref IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+
+        // Every exception goes through the common entry stubs, so that
+        // handlers get to see the full register state
+        macro_rules! set_exception {
+            ($entry: ident, $vector: expr) => {
+                idt.$entry.set_handler_addr(trap::stub_addr($vector))
+            };
+        }
+        unsafe {
+            set_exception!(divide_error, vector::DIVIDE_ERROR);
+            set_exception!(debug, vector::DEBUG);
+            // User code may use `int3`
+            set_exception!(breakpoint, vector::BREAKPOINT)
+                .set_privilege_level(PrivilegeLevel::Ring3);
+            set_exception!(overflow, vector::OVERFLOW);
+            set_exception!(bound_range_exceeded, vector::BOUND_RANGE);
+            set_exception!(invalid_opcode, vector::INVALID_OPCODE);
+            set_exception!(device_not_available, vector::DEVICE_NOT_AVAILABLE);
+            set_exception!(invalid_tss, vector::INVALID_TSS);
+            set_exception!(segment_not_present, vector::SEGMENT_NOT_PRESENT);
+            set_exception!(stack_segment_fault, vector::STACK_SEGMENT);
+            set_exception!(general_protection_fault, vector::GENERAL_PROTECTION);
+            set_exception!(page_fault, vector::PAGE_FAULT);
+            set_exception!(x87_floating_point, vector::X87_FLOATING_POINT);
+            set_exception!(alignment_check, vector::ALIGNMENT_CHECK);
+            set_exception!(simd_floating_point, vector::SIMD_FLOATING_POINT);
+            set_exception!(virtualization, vector::VIRTUALIZATION);
+            set_exception!(cp_protection_exception, vector::CONTROL_PROTECTION);
+            set_exception!(hv_injection_exception, vector::HV_INJECTION);
+            set_exception!(vmm_communication_exception, vector::VMM_COMMUNICATION);
+            set_exception!(security_exception, vector::SECURITY);
+            set_exception!(double_fault, vector::DOUBLE_FAULT)
+                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+            // These can arrive at any time, even while the kernel stack
+            // is being switched or is about to overflow
+            set_exception!(non_maskable_interrupt, vector::NMI)
+                .set_stack_index(gdt::NMI_IST_INDEX);
+            set_exception!(machine_check, vector::MACHINE_CHECK)
+                .set_stack_index(gdt::MACHINE_CHECK_IST_INDEX);
+        }
+
+        unsafe {
+            idt[usize::from(syscall::INT80_VECTOR)]
+                .set_handler_addr(syscall::int80_handler_addr())
+                .set_privilege_level(PrivilegeLevel::Ring3);
+        }
+
+        for (line, &entry) in irq::ENTRIES.iter().enumerate() {
+            idt[usize::from(PIC_1_OFFSET) + line].set_handler_fn(entry);
+        }
+        idt
+    };
+}
+
+pub fn init_idt() {
+    IDT.load();
+}
+
+#[test_case]
+fn breakpoint_handling() {
+    x86_64::instructions::interrupts::int3();
+}
@@ -0,0 +1,137 @@
+//! Non-maskable interrupts
+//!
+//! NMIs can arrive at any time, even with interrupts disabled and while the
+//! kernel holds a lock, so the handler only ever uses `try_lock` and never
+//! prints to the VGA buffer. They run on a dedicated IST stack, since the
+//! interrupted stack might not be usable.
+//!
+//! The CPU doesn't deliver another NMI until the handler returns with
+//! `iretq`, but any exception raised by the handler also returns with
+//! `iretq`. A second NMI could then arrive, and would start again from the
+//! top of the IST stack, overwriting the first one. To avoid this, every
+//! nested NMI moves the IST entry down to the next slot of the stack (see
+//! [`gdt::set_nmi_stack_slot`]).
+//!
+//! NMIs are also used when panicking, to stop all other CPUs (see
+//! [`stop_other_cpus`]).
+
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+use types::PhysAddr;
+use x86_64::instructions::{hlt, interrupts, port::Port};
+
+use super::{FaultAction, TrapFrame};
+use crate::{
+    cpu::{
+        self, apic_id,
+        msr::{Ia32ApicBase, Register, X2ApicIcr},
+    },
+    gdt, memory,
+};
+
+/// How many NMIs are being handled, counting the nested ones
+static DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// How many NMIs were received since boot
+static COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Set by the first CPU that panics
+static PANICKING: AtomicBool = AtomicBool::new(false);
+/// The APIC ID of the CPU that is panicking
+static PANIC_CPU: AtomicU32 = AtomicU32::new(0);
+
+/// System control port B, which reports the source of legacy NMIs
+const SYSTEM_CONTROL_B: u16 = 0x61;
+const IOCHK: u8 = 1 << 6;
+const SERR: u8 = 1 << 7;
+
+/// The interrupt command register in xAPIC mode, as a pair of 32-bit
+/// registers
+const XAPIC_ICR_LOW: u64 = 0x300;
+const XAPIC_ICR_HIGH: u64 = 0x310;
+
+const ICR_DELIVERY_NMI: u64 = 0b100 << 8;
+const ICR_LEVEL_ASSERT: u64 = 1 << 14;
+const ICR_ALL_EXCLUDING_SELF: u64 = 0b11 << 18;
+
+/// Print to serial, if that can be done without waiting
+macro_rules! nmi_println {
+    ($($arg:tt)*) => {
+        $crate::serial::_try_print(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+/// The number of NMIs received since boot
+pub fn count() -> u64 {
+    COUNT.load(Ordering::Relaxed)
+}
+
+pub(super) fn handle(frame: &TrapFrame) -> FaultAction {
+    // This must come first: anything after it can fault, and thus let
+    // another NMI in
+    let depth = DEPTH.fetch_add(1, Ordering::SeqCst);
+    unsafe { gdt::set_nmi_stack_slot(depth + 1) };
+
+    if PANICKING.load(Ordering::SeqCst) && apic_id() != PANIC_CPU.load(Ordering::SeqCst) {
+        halt_forever();
+    }
+
+    COUNT.fetch_add(1, Ordering::Relaxed);
+
+    let reason = unsafe { Port::<u8>::new(SYSTEM_CONTROL_B).read() };
+    if reason & SERR != 0 {
+        nmi_println!("NMI: memory parity / system error at {:#x}", frame.rip);
+    }
+    if reason & IOCHK != 0 {
+        nmi_println!("NMI: I/O channel check at {:#x}", frame.rip);
+    }
+    if reason & (SERR | IOCHK) == 0 {
+        nmi_println!("NMI: unknown reason at {:#x} (depth {})", frame.rip, depth);
+    }
+
+    // If this handler faulted, an NMI could still arrive between here and
+    // `iretq`, and overwrite this frame. There is no way to close that
+    // window without a much more complex entry sequence.
+    unsafe { gdt::set_nmi_stack_slot(depth) };
+    DEPTH.fetch_sub(1, Ordering::SeqCst);
+    FaultAction::Resume
+}
+
+/// Stop every other CPU, by sending them an NMI
+///
+/// Called when panicking, so that other CPUs don't keep running on top of
+/// a kernel in an inconsistent state. Only the first call sends the NMI:
+/// other CPUs panicking at the same time get halted by it.
+pub fn stop_other_cpus() {
+    if PANICKING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    PANIC_CPU.store(apic_id(), Ordering::SeqCst);
+
+    // Without a local APIC there can't be any other CPU
+    if !cpu::features().apic {
+        return;
+    }
+    let apic_base = unsafe { Ia32ApicBase::read() };
+    if !apic_base.enabled() {
+        return;
+    }
+
+    let icr = ICR_ALL_EXCLUDING_SELF | ICR_LEVEL_ASSERT | ICR_DELIVERY_NMI;
+    if apic_base.x2apic() {
+        unsafe { X2ApicIcr::write(icr) };
+    } else if let Some(base) = memory::phys_to_virt(PhysAddr(apic_base.addr())) {
+        // Writing the low half is what sends the IPI
+        unsafe {
+            core::ptr::write_volatile((base.0 + XAPIC_ICR_HIGH) as *mut u32, 0);
+            core::ptr::write_volatile((base.0 + XAPIC_ICR_LOW) as *mut u32, icr as u32);
+        }
+    }
+}
+
+fn halt_forever() -> ! {
+    interrupts::disable();
+    loop {
+        hlt()
+    }
+}
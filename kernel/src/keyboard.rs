@@ -0,0 +1,124 @@
+//! A PS/2 keyboard driver
+//!
+//! The interrupt handler only reads the scancode and queues it. Decoding
+//! is done by whoever reads the [`ScancodeStream`], like [`print_keypresses`].
+
+use core::{
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    task::{Context, Poll},
+};
+
+use crossbeam_queue::ArrayQueue;
+use futures_util::{
+    stream::{Stream, StreamExt},
+    task::AtomicWaker,
+};
+use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
+use spin::Once;
+use x86_64::instructions::port::Port;
+
+use crate::{
+    interrupts::irq::{self, IrqReturn},
+    print,
+};
+
+/// The maximum number of scancodes waiting to be read
+pub const QUEUE_SIZE: usize = 100;
+
+static SCANCODES: Once<ArrayQueue<u8>> = Once::new();
+static WAKER: AtomicWaker = AtomicWaker::new();
+/// Set once a [`ScancodeStream`] has been created
+static TAKEN: AtomicBool = AtomicBool::new(false);
+/// The number of scancodes lost because the queue was full
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Start handling keyboard interrupts
+///
+/// Must be called after the heap has been initialized
+pub fn init() {
+    SCANCODES.call_once(|| ArrayQueue::new(QUEUE_SIZE));
+    irq::register_irq(irq::line::KEYBOARD, keyboard_interrupt_handler, "keyboard")
+        .expect("Failed to register the keyboard interrupt handler");
+}
+
+/// The number of scancodes dropped because nobody was reading them
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+fn keyboard_interrupt_handler(_: u8) -> IrqReturn {
+    let mut port = Port::new(0x60);
+    let scancode: u8 = unsafe { port.read() };
+
+    // If the queue is full the key press is lost, just like it would be
+    // if the keyboard's own buffer overflowed
+    match SCANCODES.get().map(|queue| queue.push(scancode)) {
+        Some(Ok(())) => WAKER.wake(),
+        _ => {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    IrqReturn::Handled
+}
+
+/// The scancodes received from the keyboard
+///
+/// There can only be one, since every scancode is only read once.
+pub struct ScancodeStream {
+    queue: &'static ArrayQueue<u8>,
+}
+
+impl ScancodeStream {
+    /// Panics if a stream was already created, or if the keyboard hasn't
+    /// been initialized
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        assert!(
+            !TAKEN.swap(true, Ordering::AcqRel),
+            "There can only be one ScancodeStream"
+        );
+        let queue = SCANCODES
+            .get()
+            .expect("The keyboard has not been initialized yet");
+        Self { queue }
+    }
+}
+
+impl Stream for ScancodeStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        // Check before registering, to avoid the cost of registering when
+        // a scancode is already there
+        if let Some(scancode) = self.queue.pop() {
+            return Poll::Ready(Some(scancode));
+        }
+
+        WAKER.register(cx.waker());
+        match self.queue.pop() {
+            Some(scancode) => {
+                WAKER.take();
+                Poll::Ready(Some(scancode))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Decode scancodes, and print the keys that are pressed
+pub async fn print_keypresses() {
+    let mut scancodes = ScancodeStream::new();
+    let mut keyboard = Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore);
+
+    while let Some(scancode) = scancodes.next().await {
+        if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
+            if let Some(key) = keyboard.process_keyevent(key_event) {
+                match key {
+                    DecodedKey::Unicode(character) => print!("{}", character),
+                    DecodedKey::RawKey(key) => print!("{:?}", key),
+                }
+            }
+        }
+    }
+}
@@ -11,16 +11,34 @@
 
 extern crate alloc;
 
+pub mod backtrace;
+pub mod cpu;
+pub mod deferred;
+pub mod delf;
+pub mod executor;
+pub mod fpu;
 pub mod gdt;
 pub mod interrupts;
+pub mod keyboard;
+#[cfg(feature = "lockdep")]
+pub mod lockdep;
 pub mod memory;
+pub mod process;
+pub mod ramdisk;
+pub mod random;
+pub mod sched;
 pub mod serial;
+pub mod sync;
+pub mod syscall;
+pub mod thread;
+pub mod time;
+pub mod user;
 pub mod vga_buffer;
 
-use types::{KernelState, VirtAddr};
+use types::{IrqSpinlock, KernelState, VirtAddr};
 
 use bootloader::BootInfo;
-use spin::{Mutex, Once};
+use spin::Once;
 use x86_64::instructions::hlt;
 
 pub static KERNEL_STATE: Once<KernelState<memory::PagerImpl, memory::FrameAllocImpl, ()>> = Once::new();
@@ -33,10 +51,12 @@ pub fn kernel_state() -> &'static KernelState<memory::PagerImpl, memory::FrameAl
 /// Initialize all of the kernel's subsystems (such as 
 /// interrupt handling, memory management, serial, vga)
 pub fn init(info: &'static BootInfo) {
+    cpu::init();
     gdt::init();
     interrupts::init_idt();
+    syscall::init();
     unsafe { interrupts::PICS.lock().initialize() };
-    x86_64::instructions::interrupts::enable();
+    interrupts::irq::init();
 
     let (pager, frame_alloc) = unsafe {
         memory::init(
@@ -45,8 +65,8 @@ pub fn init(info: &'static BootInfo) {
         )
     };
 
-    let frame_alloc = Mutex::new(frame_alloc);
-    let pager = Mutex::new(pager);
+    let frame_alloc = IrqSpinlock::new(frame_alloc);
+    let pager = IrqSpinlock::new(pager);
 
     KERNEL_STATE.call_once(|| KernelState {
         pager,
@@ -55,6 +75,36 @@ pub fn init(info: &'static BootInfo) {
     });
 
     memory::init_heap().expect("Heap creation failed");
+    #[cfg(feature = "lockdep")]
+    lockdep::init();
+    interrupts::mce::init();
+    fpu::init();
+    sched::init();
+    backtrace::init(&info.memory_map);
+    deferred::init();
+
+    time::init();
+    keyboard::init();
+    serial::init();
+    x86_64::instructions::interrupts::enable();
+}
+
+/// Run pending deferred work and let other tasks run, then sleep until the
+/// next interrupt
+pub fn idle() {
+    use x86_64::instructions::interrupts;
+
+    deferred::run_pending();
+    sched::yield_now();
+
+    // Interrupts must be disabled between checking for work and halting,
+    // or an interrupt scheduling work in between would go unnoticed
+    interrupts::disable();
+    if deferred::has_pending() {
+        interrupts::enable();
+    } else {
+        interrupts::enable_and_hlt();
+    }
 }
 
 /// A test runner for the kernel
@@ -93,8 +143,11 @@ fn test_start(info: &'static bootloader::BootInfo) -> ! {
 
 #[cfg_attr(test, panic_handler)]
 pub fn test_panic_handler(info: &core::panic::PanicInfo) -> ! {
+    thread::handle_panic(info);
+    interrupts::nmi::stop_other_cpus();
     serial_println!("[failed]\n");
     serial_println!("Error: {}\n", info);
+    serial_println!("{}", backtrace::Backtrace::capture());
     exit_qemu(0x11);
 }
 
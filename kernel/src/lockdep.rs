@@ -0,0 +1,275 @@
+//! Lock dependency validator ("lockdep"), enabled by the `lockdep` feature
+//!
+//! Every lock is its own class, identified by its address. Whenever a lock
+//! is acquired, lockdep records that it comes after each lock the same
+//! context (a task, or IRQ handlers) is already holding, along with where
+//! both were acquired. A report is printed the first time:
+//! - a new ordering closes a cycle, so that two contexts could each end up
+//!   waiting for a lock the other one holds;
+//! - a lock is taken both in an IRQ handler and elsewhere with interrupts
+//!   enabled, so the handler could interrupt its holder;
+//! - a context acquires a lock it's already holding.
+//!
+//! Locks report to lockdep through [`types::lockdep`].
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    vec::Vec,
+};
+use core::{
+    panic::Location,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use spin::Mutex;
+use types::lockdep::{self, Hooks};
+use x86_64::instructions::interrupts;
+
+use crate::{
+    interrupts::irq,
+    println,
+    sched::{self, TaskId},
+};
+
+type Site = &'static Location<'static>;
+
+/// Which locks are held is tracked separately for each context
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Context {
+    Task(TaskId),
+    Irq,
+}
+
+impl Context {
+    fn current() -> Self {
+        if irq::in_irq() {
+            Context::Irq
+        } else {
+            Context::Task(sched::current_id())
+        }
+    }
+}
+
+struct Held {
+    class: usize,
+    site: Site,
+    shared: bool,
+}
+
+/// Where an ordering between two locks was first seen
+#[derive(Clone, Copy)]
+struct Edge {
+    before: usize,
+    held_at: Site,
+    after: usize,
+    acquired_at: Site,
+}
+
+#[derive(Default)]
+struct Usage {
+    in_irq: Option<Site>,
+    irqs_enabled: Option<Site>,
+}
+
+struct Graph {
+    held: BTreeMap<Context, Vec<Held>>,
+    /// `edges[a][b]` exists once `b` was acquired while holding `a`
+    edges: BTreeMap<usize, BTreeMap<usize, Edge>>,
+    usage: BTreeMap<usize, Usage>,
+}
+
+/// A plain spinlock, since the locks lockdep watches report to it
+static GRAPH: Mutex<Graph> = Mutex::new(Graph {
+    held: BTreeMap::new(),
+    edges: BTreeMap::new(),
+    usage: BTreeMap::new(),
+});
+/// Set while lockdep is running, so that the locks it takes itself (for
+/// example to print a report) are ignored
+static BUSY: AtomicBool = AtomicBool::new(false);
+static REPORTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Start validating locks
+///
+/// Must be called after the heap has been initialized
+pub fn init() {
+    lockdep::set_hooks(Hooks {
+        acquire,
+        release,
+        forget,
+    });
+}
+
+/// The number of problems reported so far
+pub fn reports() -> usize {
+    REPORTS.load(Ordering::Relaxed)
+}
+
+fn with_graph(f: impl FnOnce(&mut Graph)) {
+    interrupts::without_interrupts(|| {
+        if BUSY.swap(true, Ordering::Acquire) {
+            return;
+        }
+        f(&mut GRAPH.lock());
+        BUSY.store(false, Ordering::Release);
+    });
+}
+
+fn acquire(class: usize, site: Site, shared: bool) {
+    let irqs_enabled = interrupts::are_enabled();
+    with_graph(|graph| graph.acquire(class, site, shared, irqs_enabled));
+}
+
+fn release(class: usize) {
+    with_graph(|graph| {
+        let context = Context::current();
+        if let Some(held) = graph.held.get_mut(&context) {
+            // Locks aren't necessarily released in order
+            if let Some(index) = held.iter().rposition(|held| held.class == class) {
+                held.remove(index);
+            }
+            if held.is_empty() {
+                graph.held.remove(&context);
+            }
+        }
+    });
+}
+
+fn forget(class: usize) {
+    with_graph(|graph| {
+        graph.edges.remove(&class);
+        for edges in graph.edges.values_mut() {
+            edges.remove(&class);
+        }
+        graph.usage.remove(&class);
+    });
+}
+
+impl Graph {
+    fn acquire(&mut self, class: usize, site: Site, shared: bool, irqs_enabled: bool) {
+        let context = Context::current();
+        let usage = self.usage.entry(class).or_default();
+        if context == Context::Irq && usage.in_irq.is_none() {
+            usage.in_irq = Some(site);
+            if let Some(enabled_at) = usage.irqs_enabled {
+                report_irq_unsafe(class, site, enabled_at);
+            }
+        } else if context != Context::Irq && irqs_enabled && usage.irqs_enabled.is_none() {
+            usage.irqs_enabled = Some(site);
+            if let Some(irq_at) = usage.in_irq {
+                report_irq_unsafe(class, irq_at, site);
+            }
+        }
+
+        let held = self.held.entry(context).or_default();
+        for lock in held.iter() {
+            if lock.class == class {
+                if !(shared && lock.shared) {
+                    report("recursive locking");
+                    println!("  acquiring lock {:#x} at {}", class, site);
+                    println!("  which was already acquired at {}", lock.site);
+                }
+                continue;
+            }
+
+            let known = self
+                .edges
+                .get(&lock.class)
+                .is_some_and(|edges| edges.contains_key(&class));
+            if known {
+                continue;
+            }
+            if let Some(path) = find_path(&self.edges, class, lock.class) {
+                report("possible circular locking dependency");
+                println!(
+                    "  acquiring lock {:#x} at {}, while holding lock {:#x} acquired at {}",
+                    class, site, lock.class, lock.site
+                );
+                println!("  but the opposite order was seen before:");
+                for edge in path {
+                    println!(
+                        "  lock {:#x} acquired at {}, while holding lock {:#x} acquired at {}",
+                        edge.after, edge.acquired_at, edge.before, edge.held_at
+                    );
+                }
+            }
+            self.edges.entry(lock.class).or_default().insert(
+                class,
+                Edge {
+                    before: lock.class,
+                    held_at: lock.site,
+                    after: class,
+                    acquired_at: site,
+                },
+            );
+        }
+        held.push(Held {
+            class,
+            site,
+            shared,
+        });
+    }
+}
+
+/// Find a chain of orderings leading from `from` to `to`
+fn find_path(
+    edges: &BTreeMap<usize, BTreeMap<usize, Edge>>,
+    from: usize,
+    to: usize,
+) -> Option<Vec<Edge>> {
+    let mut visited = BTreeSet::new();
+    let mut path = Vec::new();
+    // Depth-first, with the edge leading to each node on the stack
+    let mut stack: Vec<(usize, Option<Edge>, usize)> = alloc::vec![(from, None, 0)];
+    while let Some((node, edge, depth)) = stack.pop() {
+        path.truncate(depth);
+        if let Some(edge) = edge {
+            path.push(edge);
+        }
+        if node == to {
+            return Some(path);
+        }
+        if !visited.insert(node) {
+            continue;
+        }
+        for (&next, &edge) in edges.get(&node).into_iter().flatten() {
+            stack.push((next, Some(edge), path.len()));
+        }
+    }
+    None
+}
+
+fn report(problem: &str) {
+    REPORTS.fetch_add(1, Ordering::Relaxed);
+    println!("lockdep: {} in {:?}", problem, Context::current());
+}
+
+fn report_irq_unsafe(class: usize, irq_at: Site, enabled_at: Site) {
+    report("IRQ-unsafe lock usage");
+    println!(
+        "  lock {:#x} acquired in an IRQ handler at {}",
+        class, irq_at
+    );
+    println!("  and with interrupts enabled at {}", enabled_at);
+}
+
+#[cfg(test)]
+mod test {
+    use super::reports;
+    use types::IrqSpinlock;
+
+    #[test_case]
+    fn reports_inverted_order() {
+        let (first, second) = (IrqSpinlock::new(()), IrqSpinlock::new(()));
+        let before = reports();
+        {
+            let _first = first.lock();
+            let _second = second.lock();
+        }
+        assert_eq!(reports(), before);
+
+        let _second = second.lock();
+        let _first = first.lock();
+        assert_eq!(reports(), before + 1);
+    }
+}
@@ -6,7 +6,11 @@
 
 extern crate alloc;
 
-use kernel::println;
+use kernel::{
+    backtrace::Backtrace,
+    executor::{AsyncTask, Executor},
+    keyboard, println, serial,
+};
 
 use x86_64::instructions::hlt;
 use bootloader::{entry_point, BootInfo};
@@ -18,21 +22,21 @@ fn main(info: &'static BootInfo) -> ! {
 
     #[cfg(test)]
     test_main();
-    loop {
-        hlt()
-    }
+
+    let mut executor = Executor::new();
+    executor.spawn(AsyncTask::new(keyboard::print_keypresses()));
+    executor.spawn(AsyncTask::new(serial::print_input()));
+    executor.run()
 }
 
 #[panic_handler]
 fn panic(i: &core::panic::PanicInfo) -> ! {
+    kernel::thread::handle_panic(i);
+    kernel::interrupts::nmi::stop_other_cpus();
     println!("{}", i);
+    println!("{}", Backtrace::capture());
 
     loop {
         hlt()
     }
 }
-
-unsafe fn jmp(addr: *const u8) {
-    let addr: fn() = core::mem::transmute(addr);
-    addr();
-}
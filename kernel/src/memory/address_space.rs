@@ -0,0 +1,187 @@
+use alloc::collections::BTreeMap;
+
+use types::{MapFlags, Pager, VirtAddr};
+
+use super::{allocate_frame, deallocate_frame, phys_to_virt, PAGE_SIZE};
+use crate::{kernel_state, user::USER_END};
+
+#[derive(displaydoc::Display, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapError {
+    /// page {0:#x} is not in user space
+    NotUser(u64),
+    /// page {0:#x} is already mapped
+    AlreadyMapped(u64),
+    /// page {0:#x} is not mapped
+    NotMapped(u64),
+    /// out of physical memory
+    OutOfMemory,
+}
+
+/// The user pages owned by a process
+///
+/// Every task shares the same page table, so an address space is just the
+/// set of pages that were mapped on behalf of its owner. They are unmapped,
+/// and their frames freed, when it's dropped.
+#[derive(Debug, Default)]
+pub struct AddressSpace {
+    /// The start of every mapped page, with its permissions
+    pages: BTreeMap<u64, MapFlags>,
+}
+
+impl AddressSpace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Map a zeroed frame at the page containing `addr`
+    pub fn map(&mut self, addr: VirtAddr, flags: MapFlags) -> Result<(), MapError> {
+        let page = addr.0 & !(PAGE_SIZE as u64 - 1);
+        if page >= USER_END {
+            return Err(MapError::NotUser(page));
+        }
+
+        let mut pager = kernel_state().pager.lock();
+        if pager.translate(VirtAddr(page)).is_some() {
+            return Err(MapError::AlreadyMapped(page));
+        }
+        let frame = allocate_frame().ok_or(MapError::OutOfMemory)?;
+        assert_eq!(frame.0 % PAGE_SIZE as u64, 0);
+        let kernel_addr = phys_to_virt(frame).expect("Memory is not initialized").0;
+        unsafe {
+            (kernel_addr as *mut u8).write_bytes(0, PAGE_SIZE);
+            if pager.map_with_flags(VirtAddr(page), frame, flags).is_none() {
+                deallocate_frame(frame);
+                return Err(MapError::OutOfMemory);
+            }
+        }
+
+        self.pages.insert(page, flags);
+        Ok(())
+    }
+
+    /// Change the permissions of the page containing `addr`, which must
+    /// belong to this address space
+    pub fn protect(&mut self, addr: VirtAddr, flags: MapFlags) -> Result<(), MapError> {
+        let page = addr.0 & !(PAGE_SIZE as u64 - 1);
+        let current = self.pages.get_mut(&page).ok_or(MapError::NotMapped(page))?;
+        // Only the owner uses this page
+        unsafe {
+            kernel_state()
+                .pager
+                .lock()
+                .update_flags(VirtAddr(page), flags)
+        }
+        .ok_or(MapError::NotMapped(page))?;
+        *current = flags;
+        Ok(())
+    }
+
+    /// Unmap the page containing `addr`, which must belong to this address
+    /// space, and free its frame
+    pub fn unmap(&mut self, addr: VirtAddr) -> Result<(), MapError> {
+        let page = addr.0 & !(PAGE_SIZE as u64 - 1);
+        self.pages.remove(&page).ok_or(MapError::NotMapped(page))?;
+        // Only the owner uses this page
+        unsafe {
+            if let Some(frame) = kernel_state().pager.lock().unmap(VirtAddr(page)) {
+                deallocate_frame(frame);
+            }
+        }
+        Ok(())
+    }
+
+    /// The permissions of the page containing `addr`, if it belongs to
+    /// this address space
+    pub fn flags(&self, addr: VirtAddr) -> Option<MapFlags> {
+        let page = addr.0 & !(PAGE_SIZE as u64 - 1);
+        self.pages.get(&page).copied()
+    }
+
+    /// The number of mapped pages
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        let mut pager = kernel_state().pager.lock();
+        for &page in self.pages.keys() {
+            // Only the owner could use these pages, and it's gone
+            unsafe {
+                if let Some(frame) = pager.unmap(VirtAddr(page)) {
+                    deallocate_frame(frame);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AddressSpace, MapError};
+    use crate::kernel_state;
+    use types::{MapFlags, Pager, VirtAddr};
+
+    const PAGE: u64 = 0x0000_1000_0040_0000;
+
+    #[test_case]
+    fn pages_are_unmapped_on_drop() {
+        let flags = MapFlags {
+            writable: true,
+            user: true,
+            executable: false,
+        };
+        let mut space = AddressSpace::new();
+        space.map(VirtAddr(PAGE + 0x10), flags).unwrap();
+        assert_eq!(
+            space.map(VirtAddr(PAGE), flags),
+            Err(MapError::AlreadyMapped(PAGE))
+        );
+        assert_eq!(unsafe { *(PAGE as *const u64) }, 0);
+        assert_eq!(space.flags(VirtAddr(PAGE + 0xFFF)), Some(flags));
+
+        drop(space);
+        assert!(kernel_state()
+            .pager
+            .lock()
+            .translate(VirtAddr(PAGE))
+            .is_none());
+    }
+
+    #[test_case]
+    fn protect_updates_the_page_table() {
+        let code = MapFlags {
+            writable: false,
+            user: true,
+            executable: true,
+        };
+        let data = MapFlags {
+            user: true,
+            ..MapFlags::KERNEL
+        };
+        let mut space = AddressSpace::new();
+        space.map(VirtAddr(PAGE), data).unwrap();
+        space.protect(VirtAddr(PAGE), code).unwrap();
+        assert_eq!(space.flags(VirtAddr(PAGE)), Some(code));
+        assert_eq!(
+            kernel_state().pager.lock().flags(VirtAddr(PAGE)),
+            Some(code)
+        );
+
+        let next = PAGE + 0x1000;
+        assert_eq!(
+            space.protect(VirtAddr(next), code),
+            Err(MapError::NotMapped(next))
+        );
+
+        space.unmap(VirtAddr(PAGE)).unwrap();
+        assert!(space.is_empty());
+        assert_eq!(kernel_state().pager.lock().flags(VirtAddr(PAGE)), None);
+        assert_eq!(space.unmap(VirtAddr(PAGE)), Err(MapError::NotMapped(PAGE)));
+    }
+}
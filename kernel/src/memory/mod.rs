@@ -26,12 +26,17 @@
 
 #[path = "../arch/x86_64/memory.rs"]
 mod arch;
+mod address_space;
 
 use types::{VirtAddr, PhysAddr, Pager};
 
-use core::alloc::Layout;
+use core::alloc::{GlobalAlloc, Layout};
 use linked_list_allocator::LockedHeap;
-pub use arch::{allocate_frame, init, FrameAllocImpl, PagerImpl, PAGE_SIZE};
+use x86_64::instructions::interrupts::without_interrupts;
+pub use address_space::{AddressSpace, MapError};
+pub use arch::{
+    allocate_frame, deallocate_frame, init, phys_to_virt, FrameAllocImpl, PagerImpl, PAGE_SIZE,
+};
 
 use crate::kernel_state;
 
@@ -40,8 +45,25 @@ pub const HEAP_START: u64 = 0x_4444_4444_0000;
 /// The size in bytes of the heap
 pub const HEAP_SIZE: u64 = 100 * 1024; // 100 KiB
 
+/// The heap allocator, which holds its lock with interrupts disabled
+///
+/// Otherwise a task preempted while allocating would keep the heap locked,
+/// and any allocation made with interrupts disabled (like the scheduler's)
+/// would spin forever.
+struct Allocator(LockedHeap);
+
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        without_interrupts(|| self.0.alloc(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        without_interrupts(|| self.0.dealloc(ptr, layout))
+    }
+}
+
 #[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+static ALLOCATOR: Allocator = Allocator(LockedHeap::empty());
 
 #[alloc_error_handler]
 fn alloc_error_handler(layout: Layout) -> ! {
@@ -69,6 +91,7 @@ pub fn init_heap() -> Option<()> {
 
     unsafe {
         ALLOCATOR
+            .0
             .lock()
             .init(HEAP_START as usize, HEAP_SIZE as usize);
     }
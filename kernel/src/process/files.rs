@@ -0,0 +1,95 @@
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::convert::TryFrom;
+
+use crate::{print, syscall::Errno};
+
+/// Something a process can read from or write to through a file descriptor
+///
+/// Operations the file doesn't support fail with [`Errno::EBADF`].
+pub trait File: Send + Sync {
+    /// Read into `buf`, returning how many bytes were read
+    fn read(&self, _buf: &mut [u8]) -> Result<usize, Errno> {
+        Err(Errno::EBADF)
+    }
+
+    /// Write `buf`, returning how many bytes were written
+    fn write(&self, _buf: &[u8]) -> Result<usize, Errno> {
+        Err(Errno::EBADF)
+    }
+}
+
+/// The kernel console. Writes are printed, and there's nothing to read.
+#[derive(Debug, Clone, Copy)]
+pub struct Console;
+
+impl File for Console {
+    fn read(&self, _buf: &mut [u8]) -> Result<usize, Errno> {
+        Ok(0)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        print!("{}", String::from_utf8_lossy(buf));
+        Ok(buf.len())
+    }
+}
+
+/// The open files of a process, indexed by file descriptor
+#[derive(Default)]
+pub struct FileTable {
+    files: Vec<Option<Arc<dyn File>>>,
+}
+
+impl FileTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A table with standard input, output and error open on the console
+    pub fn with_console() -> Self {
+        let mut table = Self::new();
+        for _ in 0..3 {
+            table.open(Arc::new(Console));
+        }
+        table
+    }
+
+    pub fn get(&self, fd: i32) -> Option<Arc<dyn File>> {
+        let fd = usize::try_from(fd).ok()?;
+        self.files.get(fd)?.clone()
+    }
+
+    /// Add a file to the table, and return its descriptor: the lowest one
+    /// that isn't in use
+    pub fn open(&mut self, file: Arc<dyn File>) -> i32 {
+        let fd = match self.files.iter().position(Option::is_none) {
+            Some(fd) => fd,
+            None => {
+                self.files.push(None);
+                self.files.len() - 1
+            }
+        };
+        self.files[fd] = Some(file);
+        fd as i32
+    }
+
+    pub fn close(&mut self, fd: i32) -> Result<(), Errno> {
+        let slot = usize::try_from(fd)
+            .ok()
+            .and_then(|fd| self.files.get_mut(fd))
+            .ok_or(Errno::EBADF)?;
+        slot.take().map(drop).ok_or(Errno::EBADF)
+    }
+
+    pub fn close_all(&mut self) {
+        self.files.clear();
+    }
+
+    /// The number of open files
+    pub fn len(&self) -> usize {
+        self.files.iter().flatten().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
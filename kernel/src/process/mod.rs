@@ -0,0 +1,431 @@
+//! Processes
+//!
+//! A process is a [task](crate::sched::Task) together with the resources it
+//! owns: an [`AddressSpace`] and a [`FileTable`]. Every process has a
+//! [`Pid`] and a parent, which is the process that spawned it, or
+//! [`KERNEL`] if it was spawned by a kernel thread.
+//!
+//! When a process exits, its resources are freed right away, but it stays
+//! in the process table as a zombie until its parent collects its
+//! [`ExitStatus`] with [`wait`] or [`waitpid`]. The children of a process
+//! that exits are adopted by the kernel, and reaped as soon as they exit.
+
+mod files;
+
+pub use files::{Console, File, FileTable};
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{fmt, mem};
+
+use spin::Once;
+
+use crate::{
+    memory::AddressSpace,
+    sched::{self, State, Task},
+    sync::{Condvar, Mutex, MutexGuard},
+    user::{self, UserExit},
+};
+
+/// A process identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Pid(u32);
+
+impl Pid {
+    pub fn as_u32(&self) -> u32 {
+        self.0
+    }
+}
+
+impl fmt::Display for Pid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// The parent of processes spawned by kernel threads, which isn't a
+/// process itself
+pub const KERNEL: Pid = Pid(0);
+
+/// What a process is doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    /// Running or waiting to run
+    Running,
+    /// Blocked, waiting for something to happen
+    Sleeping,
+    /// Exited, and waiting for its parent to collect its exit status
+    Zombie,
+}
+
+/// How a process ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// The process exited with the given code
+    Exited(i32),
+    /// The process was [killed](kill)
+    Killed,
+    /// The process raised an exception, or its kernel code panicked
+    Crashed,
+}
+
+impl From<UserExit> for ExitStatus {
+    fn from(exit: UserExit) -> Self {
+        match exit {
+            UserExit::Exited(code) => ExitStatus::Exited(code),
+            UserExit::Killed => ExitStatus::Killed,
+            UserExit::Fault { .. } => ExitStatus::Crashed,
+        }
+    }
+}
+
+#[derive(displaydoc::Display, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitError {
+    /// the process has no children to wait for
+    NoChildren,
+    /// process {0} is not a child of the caller
+    NotAChild(Pid),
+}
+
+#[derive(displaydoc::Display, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KillError {
+    /// process {0} does not exist
+    NoSuchProcess(Pid),
+}
+
+/// A process, which may have exited already
+pub struct Process {
+    pid: Pid,
+    name: String,
+    /// Set right after spawning, before the process is added to the table
+    task: Once<Arc<Task>>,
+    exit_status: Once<ExitStatus>,
+    address_space: Mutex<AddressSpace>,
+    files: Mutex<FileTable>,
+}
+
+impl Process {
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The task running the process
+    pub fn task(&self) -> &Arc<Task> {
+        self.task.get().expect("Process has no task")
+    }
+
+    /// The parent of the process, or `None` if it has been reaped
+    pub fn parent(&self) -> Option<Pid> {
+        TABLE
+            .lock()
+            .processes
+            .get(&self.pid)
+            .map(|entry| entry.parent)
+    }
+
+    /// The processes this one spawned, which haven't been reaped yet
+    pub fn children(&self) -> Vec<Pid> {
+        TABLE
+            .lock()
+            .children(self.pid)
+            .map(|(&pid, _)| pid)
+            .collect()
+    }
+
+    pub fn state(&self) -> ProcessState {
+        if self.exit_status.is_completed() {
+            return ProcessState::Zombie;
+        }
+        match self.task().state() {
+            State::Blocked => ProcessState::Sleeping,
+            _ => ProcessState::Running,
+        }
+    }
+
+    /// How the process ended, or `None` if it's still running
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        self.exit_status.get().copied()
+    }
+
+    /// The user memory of the process, which is emptied when it exits
+    pub fn address_space(&self) -> MutexGuard<'_, AddressSpace> {
+        self.address_space.lock()
+    }
+
+    /// The open files of the process, which are closed when it exits
+    pub fn files(&self) -> MutexGuard<'_, FileTable> {
+        self.files.lock()
+    }
+}
+
+impl fmt::Debug for Process {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Process")
+            .field("pid", &self.pid)
+            .field("name", &self.name)
+            .field("state", &self.state())
+            .finish()
+    }
+}
+
+struct Entry {
+    process: Arc<Process>,
+    parent: Pid,
+    /// Set once the parent has exited, so that nobody will wait for it
+    orphan: bool,
+}
+
+struct Table {
+    processes: BTreeMap<Pid, Entry>,
+    next_pid: u32,
+}
+
+impl Table {
+    /// The children of `parent` that somebody may wait for
+    fn children(&self, parent: Pid) -> impl Iterator<Item = (&Pid, &Entry)> {
+        self.processes
+            .iter()
+            .filter(move |(_, entry)| entry.parent == parent && !entry.orphan)
+    }
+
+    fn allocate_pid(&mut self) -> Pid {
+        loop {
+            let pid = Pid(self.next_pid);
+            self.next_pid = self.next_pid.checked_add(1).unwrap_or(1);
+            if !self.processes.contains_key(&pid) {
+                return pid;
+            }
+        }
+    }
+}
+
+static TABLE: Mutex<Table> = Mutex::new(Table {
+    processes: BTreeMap::new(),
+    next_pid: 1,
+});
+/// Notified whenever a process exits
+static EXITED: Condvar = Condvar::new();
+
+/// Start a new process, whose task runs `f` and then exits with the status
+/// it returns
+///
+/// The process starts with an empty address space, and with its standard
+/// streams open on the [`Console`].
+pub fn spawn<F>(name: &str, f: F) -> Arc<Process>
+where
+    F: FnOnce() -> ExitStatus + Send + 'static,
+{
+    let parent = current().map_or(KERNEL, |process| process.pid);
+
+    // The table stays locked until the process is in it, so that the new
+    // task can't look itself up before then
+    let mut table = TABLE.lock();
+    let process = Arc::new(Process {
+        pid: table.allocate_pid(),
+        name: name.to_string(),
+        task: Once::new(),
+        exit_status: Once::new(),
+        address_space: Mutex::new(AddressSpace::new()),
+        files: Mutex::new(FileTable::with_console()),
+    });
+    let task = sched::spawn(name, move || exit(f()));
+    process.task.call_once(|| task);
+    table.processes.insert(
+        process.pid,
+        Entry {
+            process: process.clone(),
+            parent,
+            orphan: false,
+        },
+    );
+    process
+}
+
+/// The process the current task belongs to, if any
+pub fn current() -> Option<Arc<Process>> {
+    let id = sched::current_id();
+    TABLE
+        .lock()
+        .processes
+        .values()
+        .find(|entry| entry.process.task().id() == id)
+        .map(|entry| entry.process.clone())
+}
+
+/// Find a process that hasn't been reaped yet
+pub fn get(pid: Pid) -> Option<Arc<Process>> {
+    TABLE
+        .lock()
+        .processes
+        .get(&pid)
+        .map(|entry| entry.process.clone())
+}
+
+/// End the current process, freeing its resources
+///
+/// A process that was [killed](kill) always ends with
+/// [`ExitStatus::Killed`]. Panics if the current task isn't a process.
+pub fn exit(status: ExitStatus) -> ! {
+    let process = current().expect("exit called outside of a process");
+    let status = if process.task().user.is_killed() {
+        ExitStatus::Killed
+    } else {
+        status
+    };
+
+    drop(mem::take(&mut *process.address_space()));
+    process.files().close_all();
+
+    {
+        let mut table = TABLE.lock();
+        process.exit_status.call_once(|| status);
+        for entry in table.processes.values_mut() {
+            if entry.parent == process.pid {
+                entry.parent = KERNEL;
+                entry.orphan = true;
+            }
+        }
+        // Orphans are reaped right away, including this one if its parent
+        // is already gone
+        table
+            .processes
+            .retain(|_, entry| !(entry.orphan && entry.process.exit_status.is_completed()));
+        EXITED.notify_all();
+    }
+
+    drop(process);
+    sched::exit()
+}
+
+/// Wait for any child of the current process to exit, and reap it
+///
+/// Kernel threads wait for the children of [`KERNEL`], which they all
+/// share.
+pub fn wait() -> Result<(Pid, ExitStatus), WaitError> {
+    wait_for(None)
+}
+
+/// Wait for the child `pid` of the current process to exit, and reap it
+pub fn waitpid(pid: Pid) -> Result<ExitStatus, WaitError> {
+    wait_for(Some(pid)).map(|(_, status)| status)
+}
+
+fn wait_for(pid: Option<Pid>) -> Result<(Pid, ExitStatus), WaitError> {
+    let parent = current().map_or(KERNEL, |process| process.pid);
+    let mut table = TABLE.lock();
+    loop {
+        let mut children = table
+            .children(parent)
+            .filter(|(&child, _)| pid.is_none_or(|pid| pid == child))
+            .peekable();
+        if children.peek().is_none() {
+            return Err(pid.map_or(WaitError::NoChildren, WaitError::NotAChild));
+        }
+
+        let exited = children
+            .find_map(|(&child, entry)| entry.process.exit_status().map(|status| (child, status)));
+        drop(children);
+        if let Some((child, status)) = exited {
+            table.processes.remove(&child);
+            return Ok((child, status));
+        }
+        table = EXITED.wait(table);
+    }
+}
+
+/// Make a process exit with [`ExitStatus::Killed`]
+///
+/// This takes effect as soon as it would run user code, or return to it
+/// (see [`user::kill`]). Killing a zombie does nothing.
+pub fn kill(pid: Pid) -> Result<(), KillError> {
+    let process = get(pid).ok_or(KillError::NoSuchProcess(pid))?;
+    if process.state() != ProcessState::Zombie {
+        user::kill(process.task());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{current, get, kill, spawn, wait, waitpid, ExitStatus, Process, WaitError};
+    use crate::{kernel_state, memory::PAGE_SIZE, sched, user};
+    use alloc::sync::Arc;
+    use spin::Mutex;
+    use types::{MapFlags, Pager, VirtAddr};
+
+    const CODE: u64 = 0x0000_1000_0050_0000;
+
+    #[test_case]
+    fn exit_status_is_collected() {
+        let process = spawn("test", || {
+            assert!(current().is_some());
+            ExitStatus::Exited(3)
+        });
+        assert_eq!(waitpid(process.pid()), Ok(ExitStatus::Exited(3)));
+        assert!(get(process.pid()).is_none());
+        assert_eq!(
+            waitpid(process.pid()),
+            Err(WaitError::NotAChild(process.pid()))
+        );
+    }
+
+    #[test_case]
+    fn kill_stops_user_code() {
+        let process = spawn("spin", || {
+            let process = current().unwrap();
+            let flags = MapFlags {
+                writable: true,
+                user: true,
+                executable: true,
+            };
+            let mut space = process.address_space();
+            space.map(VirtAddr(CODE), flags).unwrap();
+            space.map(VirtAddr(CODE + PAGE_SIZE as u64), flags).unwrap();
+            drop(space);
+
+            // jmp $
+            unsafe { (CODE as *mut [u8; 2]).write([0xEB, 0xFE]) };
+            let stack = CODE + 2 * PAGE_SIZE as u64;
+            let exit = unsafe {
+                user::enter_user(x86_64::VirtAddr::new(CODE), x86_64::VirtAddr::new(stack))
+            };
+            exit.into()
+        });
+
+        kill(process.pid()).unwrap();
+        assert_eq!(waitpid(process.pid()), Ok(ExitStatus::Killed));
+        assert!(kernel_state()
+            .pager
+            .lock()
+            .translate(VirtAddr(CODE))
+            .is_none());
+    }
+
+    #[test_case]
+    fn orphans_are_reaped() {
+        let child: Arc<Mutex<Option<Arc<Process>>>> = Arc::default();
+        let parent = {
+            let child = child.clone();
+            spawn("parent", move || {
+                *child.lock() = Some(spawn("child", || ExitStatus::Exited(1)));
+                ExitStatus::Exited(0)
+            })
+        };
+
+        assert_eq!(wait(), Ok((parent.pid(), ExitStatus::Exited(0))));
+        // The child was adopted, so there's nothing left to wait for
+        assert_eq!(wait(), Err(WaitError::NoChildren));
+
+        let child = child.lock().take().unwrap();
+        sched::wait_for_exit(child.task());
+        assert!(get(child.pid()).is_none());
+    }
+}
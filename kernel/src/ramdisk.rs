@@ -0,0 +1,83 @@
+//! A read-only, in-memory file system
+//!
+//! Files are byte slices that live as long as the kernel, usually embedded in
+//! its image with `include_bytes!`, and are found by their absolute path.
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+/// A set of files, by path
+#[derive(Debug, Default, Clone)]
+pub struct Ramdisk {
+    files: BTreeMap<String, &'static [u8]>,
+}
+
+impl Ramdisk {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a file at `path`, replacing the one that was there
+    pub fn insert(&mut self, path: &str, contents: &'static [u8]) {
+        self.files.insert(normalize(path), contents);
+    }
+
+    /// The contents of the file at `path`
+    pub fn get(&self, path: &str) -> Option<&'static [u8]> {
+        self.files.get(&normalize(path)).copied()
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.get(path).is_some()
+    }
+
+    /// The path of every file
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.files.keys().map(String::as_str)
+    }
+}
+
+/// Turn `path` into an absolute path without `.`, `..` or repeated slashes
+///
+/// Relative paths are taken from the root, and so is `..` in it.
+pub fn normalize(path: &str) -> String {
+    let mut components = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+    if components.is_empty() {
+        return "/".to_string();
+    }
+    let mut normalized = String::new();
+    for component in components {
+        normalized.push('/');
+        normalized.push_str(component);
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod test {
+    use super::{normalize, Ramdisk};
+
+    #[test_case]
+    fn paths_are_normalized() {
+        assert_eq!(normalize("/usr//lib/./libc.so"), "/usr/lib/libc.so");
+        assert_eq!(normalize("/usr/lib/../../lib"), "/lib");
+        assert_eq!(normalize("/.."), "/");
+
+        let mut ramdisk = Ramdisk::new();
+        ramdisk.insert("/lib/libc.so", b"libc");
+        assert_eq!(ramdisk.get("/usr/../lib//libc.so"), Some(&b"libc"[..]));
+        assert!(!ramdisk.contains("/lib/libm.so"));
+    }
+}
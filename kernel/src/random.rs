@@ -0,0 +1,89 @@
+//! Random numbers
+//!
+//! Numbers come from RDRAND when the CPU has it. Otherwise, or if RDRAND
+//! keeps failing, they come from a SplitMix64 generator perturbed by the
+//! TSC, which is unpredictable enough for things like address space layout
+//! randomization, but not for cryptography.
+
+use core::{
+    arch::x86_64::{_rdrand64_step, _rdtsc},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::cpu;
+
+/// How many times to retry RDRAND, as recommended by Intel
+const RDRAND_RETRIES: usize = 10;
+
+/// The state of the fallback generator
+static STATE: AtomicU64 = AtomicU64::new(0x853C_49E6_748F_EA9B);
+
+/// A random `u64`
+pub fn u64() -> u64 {
+    if cpu::features().rdrand {
+        if let Some(value) = unsafe { rdrand() } {
+            return value;
+        }
+    }
+    fallback()
+}
+
+/// Fill `buf` with random bytes
+pub fn fill(buf: &mut [u8]) {
+    for chunk in buf.chunks_mut(8) {
+        chunk.copy_from_slice(&u64().to_le_bytes()[..chunk.len()]);
+    }
+}
+
+/// A random number in `0..bound`, which must not be 0
+pub fn below(bound: u64) -> u64 {
+    assert_ne!(bound, 0, "Empty range");
+    // Reject the values that would make the lowest numbers more likely
+    let limit = u64::MAX - u64::MAX % bound;
+    loop {
+        let value = u64();
+        if value < limit {
+            return value % bound;
+        }
+    }
+}
+
+#[target_feature(enable = "rdrand")]
+unsafe fn rdrand() -> Option<u64> {
+    let mut value = 0;
+    for _ in 0..RDRAND_RETRIES {
+        if _rdrand64_step(&mut value) == 1 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn fallback() -> u64 {
+    let tsc = unsafe { _rdtsc() };
+    let mut z = STATE
+        .fetch_add(0x9E37_79B9_7F4A_7C15, Ordering::Relaxed)
+        .wrapping_add(tsc);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{below, fallback, fill};
+
+    #[test_case]
+    fn fill_changes_every_chunk() {
+        let mut buf = [0u8; 20];
+        fill(&mut buf);
+        // Each check fails with probability 2^-64 (or 2^-32 for the tail)
+        assert!(buf.chunks(8).all(|chunk| chunk.iter().any(|&b| b != 0)));
+    }
+
+    #[test_case]
+    fn fallback_values_differ() {
+        assert_ne!(fallback(), fallback());
+        assert!((0..100).all(|_| below(3) < 3));
+    }
+}
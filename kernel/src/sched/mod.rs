@@ -0,0 +1,609 @@
+//! Tasks, and a preemptive scheduler
+//!
+//! A [`Task`] is a thread of execution with its own kernel stack. Kernel
+//! threads and user processes are both tasks: a task runs user code by
+//! calling [`enter_user`](crate::user::enter_user) on its own stack.
+//!
+//! Runnable tasks are queued by a scheduling [policy], which decides which
+//! one runs next. Every timer tick is charged to the current task, and once
+//! the policy decides it has run for long enough, the task is preempted
+//! when the interrupt handler returns (see [`irq_exit`]). Tasks can also
+//! give up the CPU early with [`yield_now`], or [`block`] until they are
+//! woken up or a deadline passes. When nothing is runnable, the idle task
+//! halts the CPU until the next interrupt.
+//!
+//! Most kernel code should use the [`thread`](crate::thread) API instead.
+//!
+//! The scheduler lock is only ever taken with interrupts disabled, and
+//! switching tasks always happens with interrupts disabled.
+
+#[path = "../arch/x86_64/sched.rs"]
+mod arch;
+pub mod policy;
+pub mod stack;
+
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    cell::UnsafeCell,
+    fmt, mem,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use spin::{Mutex, MutexGuard, Once};
+use types::held_locks;
+use x86_64::instructions::interrupts;
+
+use crate::{fpu::FpuState, gdt, thread::Locals, time, user::UserContext};
+use policy::{Class, Policy, SchedEntity, Schedulable};
+use stack::KernelStack;
+
+/// The policy deciding which task runs next
+#[cfg(not(feature = "sched-round-robin"))]
+type ActivePolicy = policy::RealTime<Arc<Task>, policy::Fair<Arc<Task>>>;
+#[cfg(feature = "sched-round-robin")]
+type ActivePolicy = policy::RealTime<Arc<Task>, policy::RoundRobin<Arc<Task>>>;
+
+/// The number of timer ticks a task runs for before being preempted, unless
+/// changed with [`set_time_slice`]
+pub const DEFAULT_TIME_SLICE: u64 = 5;
+
+static TIME_SLICE: AtomicU64 = AtomicU64::new(DEFAULT_TIME_SLICE);
+/// Set when the current task should be preempted as soon as possible
+static NEED_RESCHED: AtomicBool = AtomicBool::new(false);
+/// The id of the running task, which can be read without locking
+static CURRENT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A unique identifier of a task
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TaskId(u64);
+
+impl TaskId {
+    fn new() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        TaskId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// The scheduling state of a task
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum State {
+    /// Queued by the scheduling policy
+    Ready,
+    /// Running on the CPU
+    Running,
+    /// Waiting to be woken up, and not queued
+    Blocked,
+    /// Finished, and waiting to be freed
+    Dead,
+}
+
+/// The function run by a new task
+type Entry = Box<dyn FnOnce() + Send>;
+
+/// A thread of execution, with its own kernel stack
+pub struct Task {
+    id: TaskId,
+    name: String,
+    /// A [`State`], which is atomic so that it can be read without locking
+    state: AtomicU8,
+    /// The stack pointer saved by the last context switch
+    rsp: UnsafeCell<u64>,
+    /// `None` for the boot task, which runs on the bootloader's stack
+    stack: Option<KernelStack>,
+    fpu: UnsafeCell<FpuState>,
+    sched: SchedEntity,
+    /// Set when the last [`block`] ended because of its deadline
+    timed_out: AtomicBool,
+    /// The number of locks the task held when it was switched out
+    held_locks: AtomicUsize,
+    /// The tasks waiting for this one to exit
+    exit_waiters: Mutex<Vec<Arc<Task>>>,
+    pub(crate) user: UserContext,
+    pub(crate) locals: Locals,
+}
+
+// `rsp` and `fpu` are only accessed while switching to or from the task,
+// with interrupts disabled, and `locals` only by the task itself
+unsafe impl Send for Task {}
+unsafe impl Sync for Task {}
+
+impl Task {
+    /// The task that is already running, on the stack set up by the
+    /// bootloader
+    fn boot(name: &str) -> Self {
+        Self::new(name, State::Running, None)
+    }
+
+    /// A task that will start by running `entry`
+    fn with_entry(name: &str, entry: Entry) -> Self {
+        let stack = KernelStack::new().expect("No memory left for a kernel stack");
+        let top = stack.top();
+
+        let task = Self::new(name, State::Ready, Some(stack));
+        let entry = Box::into_raw(Box::new(entry));
+        unsafe { *task.rsp.get() = arch::init_stack(top, entry as u64) };
+        task
+    }
+
+    fn new(name: &str, state: State, stack: Option<KernelStack>) -> Self {
+        Self {
+            id: TaskId::new(),
+            name: name.to_string(),
+            state: AtomicU8::new(state as u8),
+            rsp: UnsafeCell::new(0),
+            stack,
+            fpu: UnsafeCell::new(FpuState::new()),
+            sched: SchedEntity::default(),
+            timed_out: AtomicBool::new(false),
+            held_locks: AtomicUsize::new(0),
+            exit_waiters: Mutex::new(Vec::new()),
+            user: UserContext::default(),
+            locals: Locals::default(),
+        }
+    }
+
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn state(&self) -> State {
+        match self.state.load(Ordering::Acquire) {
+            0 => State::Ready,
+            1 => State::Running,
+            2 => State::Blocked,
+            _ => State::Dead,
+        }
+    }
+
+    fn set_state(&self, state: State) {
+        self.state.store(state as u8, Ordering::Release);
+    }
+
+    pub fn class(&self) -> Class {
+        self.sched.class()
+    }
+
+    /// The time the task spent running, counted in timer ticks
+    pub fn cpu_time(&self) -> Duration {
+        time::ticks_to_duration(self.sched.runtime())
+    }
+}
+
+impl Schedulable for Arc<Task> {
+    fn entity(&self) -> &SchedEntity {
+        &self.sched
+    }
+}
+
+impl fmt::Debug for Task {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Task")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .field("state", &self.state())
+            .field("class", &self.class())
+            .finish()
+    }
+}
+
+struct Scheduler {
+    current: Arc<Task>,
+    /// Never queued in the policy, and only runs when nothing else can
+    idle: Arc<Task>,
+    policy: ActivePolicy,
+    /// Blocked tasks with a deadline, and the tick at which to wake them
+    sleepers: Vec<(u64, Arc<Task>)>,
+    /// Tasks that have exited, whose stacks can't be freed until another
+    /// task is running
+    dead: Vec<Arc<Task>>,
+}
+
+impl Scheduler {
+    /// Make a blocked task runnable again, returning whether it was blocked
+    fn wake(&mut self, task: &Arc<Task>) -> bool {
+        if task.state() != State::Blocked {
+            return false;
+        }
+        task.set_state(State::Ready);
+        self.enqueue(task.clone());
+        true
+    }
+
+    /// Queue a runnable task, preempting the current one if the policy
+    /// says so
+    fn enqueue(&mut self, task: Arc<Task>) {
+        let preempt = self.is_idle() || self.policy.preempts(&self.current, &task);
+        self.policy.enqueue(task);
+        if preempt {
+            NEED_RESCHED.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        Arc::ptr_eq(&self.current, &self.idle)
+    }
+}
+
+static SCHEDULER: Once<Mutex<Scheduler>> = Once::new();
+
+/// Turn the running code into the "main" task, and create the idle task
+///
+/// Must be called after the heap and the FPU have been initialized
+pub fn init() {
+    SCHEDULER.call_once(|| {
+        let boot = Arc::new(Task::boot("main"));
+        CURRENT_ID.store(boot.id.0, Ordering::Relaxed);
+        Mutex::new(Scheduler {
+            current: boot,
+            idle: Arc::new(Task::with_entry("idle", Box::new(idle_loop))),
+            policy: ActivePolicy::default(),
+            sleepers: Vec::new(),
+            dead: Vec::new(),
+        })
+    });
+}
+
+/// Lock the scheduler
+///
+/// Must be called with interrupts disabled.
+fn scheduler() -> MutexGuard<'static, Scheduler> {
+    debug_assert!(!interrupts::are_enabled());
+    SCHEDULER
+        .get()
+        .expect("The scheduler has not been initialized yet")
+        .lock()
+}
+
+/// The task running this code
+pub fn current() -> Arc<Task> {
+    try_current().expect("The scheduler has not been initialized yet")
+}
+
+/// The id of the task running this code, without locking the scheduler
+///
+/// Before [`init`], this is the id the boot task is going to get.
+pub fn current_id() -> TaskId {
+    TaskId(CURRENT_ID.load(Ordering::Relaxed))
+}
+
+/// The task running this code, or `None` if the scheduler hasn't been
+/// initialized yet
+pub fn try_current() -> Option<Arc<Task>> {
+    SCHEDULER.get()?;
+    Some(interrupts::without_interrupts(|| {
+        scheduler().current.clone()
+    }))
+}
+
+/// Whether a task can be stopped with [`exit`] at any point without
+/// affecting the rest of the kernel: the boot and idle tasks can't
+pub(crate) fn can_exit(task: &Arc<Task>) -> bool {
+    task.stack.is_some() && interrupts::without_interrupts(|| !Arc::ptr_eq(task, &scheduler().idle))
+}
+
+/// Create a new task running `f`, and make it runnable
+pub fn spawn<F>(name: &str, f: F) -> Arc<Task>
+where
+    F: FnOnce() + Send + 'static,
+{
+    spawn_with_class(name, Class::default(), f)
+}
+
+/// Like [`spawn`], but the task is scheduled according to `class`
+pub fn spawn_with_class<F>(name: &str, class: Class, f: F) -> Arc<Task>
+where
+    F: FnOnce() + Send + 'static,
+{
+    assert!(class.is_valid(), "Invalid scheduling class {:?}", class);
+    let task = Arc::new(Task::with_entry(name, Box::new(f)));
+    task.sched.set_class(class);
+    interrupts::without_interrupts(|| scheduler().enqueue(task.clone()));
+    task
+}
+
+/// Change how a task is scheduled
+pub fn set_class(task: &Arc<Task>, class: Class) {
+    assert!(class.is_valid(), "Invalid scheduling class {:?}", class);
+    interrupts::without_interrupts(|| {
+        let mut scheduler = scheduler();
+        // Queued tasks are requeued, since their position depends on it
+        let queued = scheduler.policy.remove(task);
+        task.sched.set_class(class);
+        if queued {
+            scheduler.enqueue(task.clone());
+        } else if Arc::ptr_eq(task, &scheduler.current) {
+            NEED_RESCHED.store(true, Ordering::Relaxed);
+        }
+    });
+}
+
+/// Let other runnable tasks run before continuing
+pub fn yield_now() {
+    interrupts::without_interrupts(|| {
+        let mut scheduler = scheduler();
+        let current = scheduler.current.clone();
+        scheduler.policy.yield_task(&current);
+        switch(scheduler);
+    });
+}
+
+/// Block the current task until [`wake`] is called on it, or until the
+/// timer reaches `deadline` (in ticks, see [`time::ticks`])
+///
+/// Returns `false` if the deadline passed. Must be called with interrupts
+/// disabled, after making sure that whoever is going to wake the task can
+/// find it. Wakeups can be spurious, so the condition being waited for must
+/// be checked again.
+pub fn block(deadline: Option<u64>) -> bool {
+    let mut locked = scheduler();
+    let current = locked.current.clone();
+    if let Some(deadline) = deadline {
+        if time::ticks() >= deadline {
+            return false;
+        }
+        locked.sleepers.push((deadline, current.clone()));
+    }
+
+    current.timed_out.store(false, Ordering::Relaxed);
+    current.set_state(State::Blocked);
+    switch(locked);
+
+    if deadline.is_some() {
+        scheduler()
+            .sleepers
+            .retain(|(_, task)| !Arc::ptr_eq(task, &current));
+    }
+    !current.timed_out.load(Ordering::Relaxed)
+}
+
+/// Make a blocked task runnable again, returning whether it was blocked
+pub fn wake(task: &Arc<Task>) -> bool {
+    interrupts::without_interrupts(|| scheduler().wake(task))
+}
+
+/// Block until the timer reaches `deadline` (in ticks)
+pub fn sleep_until(deadline: u64) {
+    interrupts::without_interrupts(|| while block(Some(deadline)) {});
+}
+
+/// Block until `task` has exited
+pub fn wait_for_exit(task: &Arc<Task>) {
+    interrupts::without_interrupts(|| {
+        while task.state() != State::Dead {
+            let current = current();
+            task.exit_waiters.lock().push(current);
+            block(None);
+        }
+    });
+}
+
+/// End the current task
+pub fn exit() -> ! {
+    interrupts::disable();
+    let mut scheduler = scheduler();
+    assert!(
+        !Arc::ptr_eq(&scheduler.current, &scheduler.idle),
+        "The idle task can't exit"
+    );
+
+    let current = scheduler.current.clone();
+    current.set_state(State::Dead);
+    for waiter in mem::take(&mut *current.exit_waiters.lock()) {
+        scheduler.wake(&waiter);
+    }
+    drop(current);
+
+    switch(scheduler);
+    unreachable!("A dead task was scheduled again");
+}
+
+/// The number of timer ticks in a time slice
+pub fn time_slice() -> u64 {
+    TIME_SLICE.load(Ordering::Relaxed)
+}
+
+/// Change the number of timer ticks in a time slice
+///
+/// Takes effect from the next time slice.
+pub fn set_time_slice(ticks: u64) {
+    assert!(ticks > 0, "The time slice can't be empty");
+    TIME_SLICE.store(ticks, Ordering::Relaxed);
+}
+
+/// Account a timer tick to the current task, and wake up the tasks whose
+/// deadline passed. Called by the timer interrupt, `now` being the new
+/// number of ticks.
+pub(crate) fn tick(now: u64) {
+    if SCHEDULER.get().is_none() {
+        return;
+    }
+
+    let mut scheduler = scheduler();
+    let mut i = 0;
+    while i < scheduler.sleepers.len() {
+        if scheduler.sleepers[i].0 > now {
+            i += 1;
+            continue;
+        }
+        let (_, task) = scheduler.sleepers.swap_remove(i);
+        if scheduler.wake(&task) {
+            task.timed_out.store(true, Ordering::Relaxed);
+        }
+    }
+
+    let current = scheduler.current.clone();
+    current.sched.charge_tick();
+    let preempt = if scheduler.is_idle() {
+        !scheduler.policy.is_empty()
+    } else {
+        scheduler.policy.tick(&current)
+    };
+    if preempt {
+        NEED_RESCHED.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Called by interrupt handlers right before returning
+///
+/// Preempts the current task if its time slice is over.
+pub(crate) fn irq_exit() {
+    if NEED_RESCHED.load(Ordering::Relaxed) && SCHEDULER.get().is_some() {
+        switch(scheduler());
+    }
+}
+
+/// Switch to the task chosen by the policy, which is the idle task if
+/// nothing else is runnable
+///
+/// The current task is queued again if it's still runnable, so it may be
+/// chosen again. Must be called with interrupts disabled, and returns when
+/// the current task is scheduled again.
+fn switch(mut scheduler: MutexGuard<'static, Scheduler>) {
+    NEED_RESCHED.store(false, Ordering::Relaxed);
+
+    let runnable = scheduler.current.state() == State::Running;
+    if runnable && !scheduler.is_idle() {
+        let current = scheduler.current.clone();
+        scheduler.policy.enqueue(current);
+    }
+    let next = match scheduler.policy.pick_next() {
+        Some(next) => next,
+        None => scheduler.idle.clone(),
+    };
+    if Arc::ptr_eq(&next, &scheduler.current) {
+        start_slice(&mut scheduler);
+        return;
+    }
+
+    let prev = mem::replace(&mut scheduler.current, next);
+    match prev.state() {
+        State::Running => prev.set_state(State::Ready),
+        State::Dead => scheduler.dead.push(prev.clone()),
+        // Whoever is going to wake the task up keeps it alive
+        State::Blocked | State::Ready => {}
+    }
+    scheduler.current.set_state(State::Running);
+    CURRENT_ID.store(scheduler.current.id.0, Ordering::Relaxed);
+    let held = held_locks::swap(scheduler.current.held_locks.load(Ordering::Relaxed));
+    prev.held_locks.store(held, Ordering::Relaxed);
+    start_slice(&mut scheduler);
+
+    // Both tasks are kept alive elsewhere, and an `Arc` left on this stack
+    // would never be dropped if the current task is dead
+    let (prev_ptr, next_ptr) = (Arc::as_ptr(&prev), Arc::as_ptr(&scheduler.current));
+    drop(prev);
+    drop(scheduler);
+
+    unsafe {
+        let (prev, next) = (&*prev_ptr, &*next_ptr);
+        (*prev.fpu.get()).save();
+        (*next.fpu.get()).restore();
+
+        // Interrupts from user mode must land on the stack of the task
+        // that was running user code
+        let rsp0 = next.user.return_rsp();
+        if rsp0 != 0 {
+            gdt::kernel_stack_ptr().write_unaligned(rsp0);
+        }
+
+        arch::context_switch(prev.rsp.get(), *next.rsp.get());
+    }
+
+    after_switch();
+}
+
+fn start_slice(scheduler: &mut Scheduler) {
+    // The idle task is preempted as soon as anything is runnable
+    if !scheduler.is_idle() {
+        let current = scheduler.current.clone();
+        scheduler.policy.start(&current);
+    }
+}
+
+/// Free the tasks that exited, now that none of their stacks are in use
+fn after_switch() {
+    let dead = mem::take(&mut scheduler().dead);
+    drop(dead);
+}
+
+/// Where new tasks start, called by `task_trampoline`
+extern "C" fn task_start(entry: *mut Entry) -> ! {
+    after_switch();
+    interrupts::enable();
+
+    let entry = unsafe { Box::from_raw(entry) };
+    entry();
+    exit()
+}
+
+fn idle_loop() {
+    loop {
+        interrupts::enable_and_hlt();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{current, spawn, yield_now, State};
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    #[test_case]
+    fn spawn_and_yield() {
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+        let task = spawn("test", || {
+            RUNS.fetch_add(1, Ordering::SeqCst);
+            yield_now();
+            RUNS.fetch_add(1, Ordering::SeqCst);
+        });
+        assert_eq!(current().name(), "main");
+
+        while task.state() != State::Dead {
+            yield_now();
+        }
+        assert_eq!(RUNS.load(Ordering::SeqCst), 2);
+    }
+
+    #[test_case]
+    fn timer_preempts_tasks() {
+        static STARTED: AtomicBool = AtomicBool::new(false);
+        static STOP: AtomicBool = AtomicBool::new(false);
+
+        // Neither task yields, so they only take turns through preemption
+        let task = spawn("spinner", || {
+            STARTED.store(true, Ordering::SeqCst);
+            while !STOP.load(Ordering::SeqCst) {
+                core::hint::spin_loop();
+            }
+        });
+        while !STARTED.load(Ordering::SeqCst) {
+            core::hint::spin_loop();
+        }
+        STOP.store(true, Ordering::SeqCst);
+
+        while task.state() != State::Dead {
+            yield_now();
+        }
+    }
+}
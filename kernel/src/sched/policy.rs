@@ -0,0 +1,506 @@
+//! Scheduling policies, deciding which runnable task runs next
+//!
+//! The scheduler keeps the current task and the blocked ones, and hands
+//! every runnable task to a [`Policy`]. Time is measured in timer ticks,
+//! which the scheduler reports through [`Policy::tick`], so policies can be
+//! tested by driving them with a simulated clock.
+//!
+//! Tasks belong to a [`Class`]: real-time tasks always run before normal
+//! ones (see [`RealTime`]), which are shared either fairly according to
+//! their nice level ([`Fair`]) or in turns ([`RoundRobin`]). The policy is
+//! chosen at build time, with the `sched-round-robin` feature.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use core::{
+    ptr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use spin::Mutex;
+
+use super::time_slice;
+
+/// The lowest (most favourable) nice level
+pub const MIN_NICE: i8 = -20;
+/// The highest nice level
+pub const MAX_NICE: i8 = 19;
+/// The highest real-time priority
+pub const MAX_RT_PRIORITY: u8 = 99;
+
+/// How a task is scheduled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    /// Shares the CPU with the other normal tasks. Tasks with a lower nice
+    /// level (from [`MIN_NICE`] to [`MAX_NICE`]) get a bigger share.
+    Normal { nice: i8 },
+    /// Runs before every normal task, and before real-time tasks with a
+    /// lower priority (from 1 to [`MAX_RT_PRIORITY`]). Tasks with the same
+    /// priority take turns.
+    RealTime { priority: u8 },
+}
+
+impl Class {
+    /// Check that the nice level or priority is in range
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            Class::Normal { nice } => (MIN_NICE..=MAX_NICE).contains(&nice),
+            Class::RealTime { priority } => (1..=MAX_RT_PRIORITY).contains(&priority),
+        }
+    }
+}
+
+impl Default for Class {
+    fn default() -> Self {
+        Class::Normal { nice: 0 }
+    }
+}
+
+/// The scheduling state of a task, shared by all policies
+#[derive(Debug, Default)]
+pub struct SchedEntity {
+    class: Mutex<Class>,
+    /// The number of ticks during which the task was running
+    runtime: AtomicU64,
+    /// The runtime weighted by the nice level, used by [`Fair`]
+    vruntime: AtomicU64,
+    /// The ticks left before the task should be preempted
+    slice_left: AtomicU64,
+}
+
+impl SchedEntity {
+    pub fn new(class: Class) -> Self {
+        Self {
+            class: Mutex::new(class),
+            ..Self::default()
+        }
+    }
+
+    pub fn class(&self) -> Class {
+        *self.class.lock()
+    }
+
+    /// Change the class. The task must not be queued in a policy.
+    pub(super) fn set_class(&self, class: Class) {
+        *self.class.lock() = class;
+    }
+
+    /// The number of ticks during which the task was running
+    pub fn runtime(&self) -> u64 {
+        self.runtime.load(Ordering::Relaxed)
+    }
+
+    /// Account a tick of running time
+    pub fn charge_tick(&self) {
+        self.runtime.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn vruntime(&self) -> u64 {
+        self.vruntime.load(Ordering::Relaxed)
+    }
+
+    fn start_slice(&self) {
+        self.slice_left.store(time_slice(), Ordering::Relaxed);
+    }
+
+    /// Use up a tick of the slice, returning whether it's over
+    fn slice_tick(&self) -> bool {
+        let left = self.slice_left.load(Ordering::Relaxed).saturating_sub(1);
+        self.slice_left.store(left, Ordering::Relaxed);
+        left == 0
+    }
+}
+
+/// Something that can be scheduled, like a task
+pub trait Schedulable: Clone {
+    fn entity(&self) -> &SchedEntity;
+
+    fn is(&self, other: &Self) -> bool {
+        ptr::eq(self.entity(), other.entity())
+    }
+}
+
+/// A way of choosing the next task to run
+pub trait Policy<T: Schedulable>: Default + Send {
+    /// Add a task that became runnable, or was preempted
+    fn enqueue(&mut self, task: T);
+
+    /// Remove a task from the queue, returning whether it was there
+    fn remove(&mut self, task: &T) -> bool;
+
+    /// Take the task that should run next off the queue
+    fn pick_next(&mut self) -> Option<T>;
+
+    /// `task` starts running, or keeps running after being picked again
+    fn start(&mut self, task: &T);
+
+    /// `current` was running during a timer tick. Returns whether it should
+    /// be preempted.
+    fn tick(&mut self, current: &T) -> bool;
+
+    /// `task` is going to give up the CPU, and should go after the tasks
+    /// that are already waiting
+    fn yield_task(&mut self, _task: &T) {}
+
+    /// Whether `woken`, which was just enqueued, should preempt `current`
+    fn preempts(&self, _current: &T, _woken: &T) -> bool {
+        false
+    }
+
+    fn is_empty(&self) -> bool;
+}
+
+/// Normal tasks take turns, running for a time slice each
+pub struct RoundRobin<T> {
+    queue: VecDeque<T>,
+}
+
+impl<T> Default for RoundRobin<T> {
+    fn default() -> Self {
+        Self {
+            queue: VecDeque::new(),
+        }
+    }
+}
+
+impl<T: Schedulable + Send> Policy<T> for RoundRobin<T> {
+    fn enqueue(&mut self, task: T) {
+        self.queue.push_back(task);
+    }
+
+    fn remove(&mut self, task: &T) -> bool {
+        let len = self.queue.len();
+        self.queue.retain(|queued| !queued.is(task));
+        self.queue.len() != len
+    }
+
+    fn pick_next(&mut self) -> Option<T> {
+        self.queue.pop_front()
+    }
+
+    fn start(&mut self, task: &T) {
+        task.entity().start_slice();
+    }
+
+    fn tick(&mut self, current: &T) -> bool {
+        current.entity().slice_tick()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+/// The weight of a task with nice level 0
+const NICE_0_WEIGHT: u64 = 1024;
+
+/// The weight of each nice level, starting from [`MIN_NICE`]. Every level
+/// gets about 10% less CPU time than the one before.
+const WEIGHTS: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87,
+    70, 56, 45, 36, 29, 23, 18, 15,
+];
+
+/// The vruntime a task gains by running a tick
+fn vruntime_delta(nice: i8) -> u64 {
+    let nice = nice.clamp(MIN_NICE, MAX_NICE);
+    NICE_0_WEIGHT * NICE_0_WEIGHT / WEIGHTS[(nice - MIN_NICE) as usize]
+}
+
+/// Normal tasks share the CPU in proportion to the weight of their nice
+/// level
+///
+/// Every task has a virtual runtime, which grows more slowly the lower its
+/// nice level is, and the task with the lowest one runs next. When it has
+/// used up its time slice, the running task is preempted if another one has
+/// a lower virtual runtime.
+pub struct Fair<T> {
+    /// Ordered by vruntime, and then by the order they were queued in
+    queue: BTreeMap<(u64, u64), T>,
+    next_seq: u64,
+    /// Never decreases, and is where tasks that were blocked start again
+    min_vruntime: u64,
+}
+
+/// How far behind the minimum vruntime a task that was blocked can be, in
+/// ticks at nice level 0: it gets to run sooner, but can't make up for all
+/// the time it spent blocked
+const SLEEPER_CREDIT: u64 = 2;
+
+impl<T> Default for Fair<T> {
+    fn default() -> Self {
+        Self {
+            queue: BTreeMap::new(),
+            next_seq: 0,
+            min_vruntime: 0,
+        }
+    }
+}
+
+impl<T: Schedulable> Fair<T> {
+    fn nice(task: &T) -> i8 {
+        match task.entity().class() {
+            Class::Normal { nice } => nice,
+            Class::RealTime { .. } => MIN_NICE,
+        }
+    }
+
+    fn leftmost_vruntime(&self) -> Option<u64> {
+        self.queue.keys().next().map(|&(vruntime, _)| vruntime)
+    }
+}
+
+impl<T: Schedulable + Send> Policy<T> for Fair<T> {
+    fn enqueue(&mut self, task: T) {
+        let floor = self
+            .min_vruntime
+            .saturating_sub(SLEEPER_CREDIT * NICE_0_WEIGHT);
+        let vruntime = task.entity().vruntime().max(floor);
+        task.entity().vruntime.store(vruntime, Ordering::Relaxed);
+
+        self.queue.insert((vruntime, self.next_seq), task);
+        self.next_seq += 1;
+    }
+
+    fn remove(&mut self, task: &T) -> bool {
+        let key = self
+            .queue
+            .iter()
+            .find(|(_, queued)| queued.is(task))
+            .map(|(&key, _)| key);
+        key.and_then(|key| self.queue.remove(&key)).is_some()
+    }
+
+    fn pick_next(&mut self) -> Option<T> {
+        let ((vruntime, _), task) = self.queue.pop_first()?;
+        self.min_vruntime = self.min_vruntime.max(vruntime);
+        Some(task)
+    }
+
+    fn start(&mut self, task: &T) {
+        task.entity().start_slice();
+    }
+
+    fn tick(&mut self, current: &T) -> bool {
+        let entity = current.entity();
+        let vruntime = entity.vruntime() + vruntime_delta(Self::nice(current));
+        entity.vruntime.store(vruntime, Ordering::Relaxed);
+
+        let slice_over = entity.slice_tick();
+        slice_over && self.leftmost_vruntime().is_some_and(|left| left < vruntime)
+    }
+
+    fn yield_task(&mut self, task: &T) {
+        // Go right after the last queued task
+        if let Some(&(last, _)) = self.queue.keys().next_back() {
+            let entity = task.entity();
+            entity
+                .vruntime
+                .store(entity.vruntime().max(last), Ordering::Relaxed);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+/// Real-time tasks run before the tasks scheduled by `P`, in order of
+/// priority
+///
+/// Real-time tasks with the same priority take turns every time slice, and
+/// a real-time task that becomes runnable preempts tasks with a lower
+/// priority right away.
+pub struct RealTime<T, P> {
+    /// Queues indexed by priority
+    queues: BTreeMap<u8, VecDeque<T>>,
+    normal: P,
+}
+
+impl<T, P: Default> Default for RealTime<T, P> {
+    fn default() -> Self {
+        Self {
+            queues: BTreeMap::new(),
+            normal: P::default(),
+        }
+    }
+}
+
+impl<T: Schedulable, P> RealTime<T, P> {
+    fn priority(task: &T) -> Option<u8> {
+        match task.entity().class() {
+            Class::RealTime { priority } => Some(priority),
+            Class::Normal { .. } => None,
+        }
+    }
+
+    /// The highest priority of the queued real-time tasks
+    fn highest_queued(&self) -> Option<u8> {
+        self.queues.keys().next_back().copied()
+    }
+}
+
+impl<T: Schedulable + Send, P: Policy<T>> Policy<T> for RealTime<T, P> {
+    fn enqueue(&mut self, task: T) {
+        match Self::priority(&task) {
+            Some(priority) => self.queues.entry(priority).or_default().push_back(task),
+            None => self.normal.enqueue(task),
+        }
+    }
+
+    fn remove(&mut self, task: &T) -> bool {
+        let priority = match Self::priority(task) {
+            Some(priority) => priority,
+            None => return self.normal.remove(task),
+        };
+        let queue = match self.queues.get_mut(&priority) {
+            Some(queue) => queue,
+            None => return false,
+        };
+
+        let len = queue.len();
+        queue.retain(|queued| !queued.is(task));
+        let removed = queue.len() != len;
+        if queue.is_empty() {
+            self.queues.remove(&priority);
+        }
+        removed
+    }
+
+    fn pick_next(&mut self) -> Option<T> {
+        let mut highest = match self.queues.last_entry() {
+            Some(highest) => highest,
+            None => return self.normal.pick_next(),
+        };
+        let task = highest.get_mut().pop_front();
+        if highest.get().is_empty() {
+            highest.remove();
+        }
+        task
+    }
+
+    fn start(&mut self, task: &T) {
+        match Self::priority(task) {
+            Some(_) => task.entity().start_slice(),
+            None => self.normal.start(task),
+        }
+    }
+
+    fn tick(&mut self, current: &T) -> bool {
+        match Self::priority(current) {
+            Some(priority) => {
+                let slice_over = current.entity().slice_tick();
+                let highest = self.highest_queued().unwrap_or(0);
+                highest > priority || (slice_over && highest == priority)
+            }
+            None => {
+                // Keep the accounting of the normal policy going
+                let preempt = self.normal.tick(current);
+                preempt || self.highest_queued().is_some()
+            }
+        }
+    }
+
+    fn yield_task(&mut self, task: &T) {
+        if Self::priority(task).is_none() {
+            self.normal.yield_task(task);
+        }
+    }
+
+    fn preempts(&self, current: &T, woken: &T) -> bool {
+        match (Self::priority(current), Self::priority(woken)) {
+            (_, None) => self.normal.preempts(current, woken),
+            (None, Some(_)) => true,
+            (Some(current), Some(woken)) => woken > current,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queues.is_empty() && self.normal.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Class, Fair, Policy, RealTime, RoundRobin, SchedEntity, Schedulable};
+    use crate::sched::time_slice;
+    use alloc::{sync::Arc, vec::Vec};
+
+    #[derive(Clone)]
+    struct FakeTask(Arc<SchedEntity>);
+
+    impl Schedulable for FakeTask {
+        fn entity(&self) -> &SchedEntity {
+            &self.0
+        }
+    }
+
+    /// Run the tasks under `P` for `ticks` timer ticks, like the scheduler
+    /// would, and return the ticks each of them got
+    fn simulate<P: Policy<FakeTask>>(classes: &[Class], ticks: u64) -> Vec<u64> {
+        let tasks: Vec<_> = classes
+            .iter()
+            .map(|&class| FakeTask(Arc::new(SchedEntity::new(class))))
+            .collect();
+        let mut policy = P::default();
+        for task in &tasks {
+            policy.enqueue(task.clone());
+        }
+
+        let mut current = policy.pick_next().unwrap();
+        policy.start(&current);
+        for _ in 0..ticks {
+            current.entity().charge_tick();
+            if policy.tick(&current) {
+                policy.enqueue(current);
+                current = policy.pick_next().unwrap();
+                policy.start(&current);
+            }
+        }
+        tasks.iter().map(|task| task.entity().runtime()).collect()
+    }
+
+    const NORMAL: Class = Class::Normal { nice: 0 };
+
+    #[test_case]
+    fn round_robin_takes_turns() {
+        let runtimes = simulate::<RoundRobin<_>>(&[NORMAL; 3], 30 * time_slice());
+        assert!(runtimes.iter().all(|&runtime| runtime == 10 * time_slice()));
+    }
+
+    #[test_case]
+    fn fair_follows_nice_levels() {
+        let classes = [NORMAL, NORMAL, Class::Normal { nice: 5 }];
+        let runtimes = simulate::<Fair<_>>(&classes, 3000);
+
+        // Equal tasks get the same share, within a time slice
+        assert!(runtimes[0].abs_diff(runtimes[1]) <= time_slice());
+        // Weights 1024 and 335 for nice 0 and 5, so about 3 times as much
+        let ratio = runtimes[0] * 10 / runtimes[2];
+        assert!((25..35).contains(&ratio), "Ratio was {}", ratio);
+    }
+
+    #[test_case]
+    fn real_time_runs_first() {
+        let classes = [
+            NORMAL,
+            Class::RealTime { priority: 10 },
+            Class::RealTime { priority: 20 },
+        ];
+        let runtimes = simulate::<RealTime<_, Fair<_>>>(&classes, 100);
+        assert_eq!(runtimes, [0, 0, 100]);
+    }
+
+    #[test_case]
+    fn real_time_preempts_on_wakeup() {
+        let mut policy = RealTime::<_, Fair<_>>::default();
+        let normal = FakeTask(Arc::new(SchedEntity::new(NORMAL)));
+        let rt = FakeTask(Arc::new(SchedEntity::new(Class::RealTime { priority: 1 })));
+
+        policy.start(&normal);
+        policy.enqueue(rt.clone());
+        assert!(policy.preempts(&normal, &rt));
+        assert!(policy.tick(&normal));
+        assert!(policy.pick_next().unwrap().is(&rt));
+    }
+}
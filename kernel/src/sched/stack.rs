@@ -0,0 +1,77 @@
+//! Kernel stacks, with a guard page below each one
+//!
+//! Stacks live in their own region of virtual memory, and the page below
+//! each stack is never mapped: overflowing a stack faults (and ends in the
+//! double fault handler) instead of silently overwriting whatever lies
+//! below it. The stacks of tasks that ended aren't unmapped: they are kept,
+//! and reused by new tasks, which saves mapping them again.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{kernel_state, memory};
+use types::{IrqSpinlock, Pager, VirtAddr};
+
+/// The number of pages in every stack
+pub const STACK_PAGES: u64 = 4;
+/// The size in bytes of every stack
+pub const STACK_SIZE: u64 = STACK_PAGES * PAGE_SIZE;
+
+const PAGE_SIZE: u64 = memory::PAGE_SIZE as u64;
+/// The start of the region holding the stacks
+const STACKS_START: u64 = 0x_5555_0000_0000;
+/// The space taken by each stack, including its guard page
+const SLOT_SIZE: u64 = STACK_SIZE + PAGE_SIZE;
+/// The maximum number of stacks that can exist at the same time
+pub const MAX_STACKS: u64 = 4096;
+
+/// The first slot that was never used
+static NEXT_SLOT: AtomicU64 = AtomicU64::new(0);
+/// The bottoms of the stacks that can be reused. Stacks are freed while
+/// switching tasks, with interrupts disabled.
+static FREE: IrqSpinlock<Vec<u64>> = IrqSpinlock::new(Vec::new());
+
+/// The stack of a task, which goes back to the pool when dropped
+#[derive(Debug)]
+pub struct KernelStack {
+    bottom: u64,
+}
+
+impl KernelStack {
+    /// Get an unused stack, returning `None` if there's no memory left for it
+    pub fn new() -> Option<Self> {
+        if let Some(bottom) = FREE.lock().pop() {
+            return Some(Self { bottom });
+        }
+
+        let slot = NEXT_SLOT.fetch_add(1, Ordering::Relaxed);
+        if slot >= MAX_STACKS {
+            return None;
+        }
+
+        // The guard page comes first, and is left unmapped
+        let bottom = STACKS_START + slot * SLOT_SIZE + PAGE_SIZE;
+        for page in 0..STACK_PAGES {
+            let frame = memory::allocate_frame()?;
+            let page = VirtAddr(bottom + page * PAGE_SIZE);
+            unsafe { kernel_state().pager.lock().map(page, frame)? };
+        }
+        Some(Self { bottom })
+    }
+
+    /// The lowest address of the stack
+    pub fn bottom(&self) -> u64 {
+        self.bottom
+    }
+
+    /// The address right after the end of the stack, where it starts
+    pub fn top(&self) -> u64 {
+        self.bottom + STACK_SIZE
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        FREE.lock().push(self.bottom);
+    }
+}
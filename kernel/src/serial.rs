@@ -1,26 +1,163 @@
+use core::{
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    task::{Context, Poll},
+};
+
+use crossbeam_queue::ArrayQueue;
+use futures_util::{
+    stream::{Stream, StreamExt},
+    task::AtomicWaker,
+};
 use lazy_static::lazy_static;
-use spin::Mutex;
+use spin::Once;
+use types::IrqSpinlock;
 use uart_16550::SerialPort;
+use x86_64::instructions::port::Port;
+
+use crate::{
+    interrupts::irq::{self, IrqReturn},
+    print,
+};
+
+/// The I/O port of COM1
+const COM1: u16 = 0x3F8;
+/// The line status register, relative to the base port
+const LINE_STATUS: u16 = 5;
+/// The line status bit set when a received byte can be read
+const DATA_READY: u8 = 1;
+
+/// The maximum number of received bytes waiting to be read
+pub const QUEUE_SIZE: usize = 256;
 
 lazy_static! {
-    pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial_port = unsafe { SerialPort::new(0x3F8) };
+    pub static ref SERIAL1: IrqSpinlock<SerialPort> = {
+        let mut serial_port = unsafe { SerialPort::new(COM1) };
         serial_port.init();
-        Mutex::new(serial_port)
+        IrqSpinlock::new(serial_port)
     };
 }
 
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments) {
     use core::fmt::Write;
-    use x86_64::instructions::interrupts;
-
-    interrupts::without_interrupts(|| {
-        SERIAL1
-            .lock()
-            .write_fmt(args)
-            .expect("Printing to serial failed")
-    });
+
+    SERIAL1
+        .lock()
+        .write_fmt(args)
+        .expect("Printing to serial failed");
+}
+
+/// Print to serial, unless the port is already in use
+///
+/// This is for handlers that can interrupt code holding the lock even
+/// with interrupts disabled (like the NMI handler), and so can't wait for
+/// it. Returns whether anything was printed.
+#[doc(hidden)]
+pub fn _try_print(args: ::core::fmt::Arguments) -> bool {
+    use core::fmt::Write;
+
+    match SERIAL1.try_lock() {
+        Some(mut serial) => serial.write_fmt(args).is_ok(),
+        None => false,
+    }
+}
+
+static RECEIVED: Once<ArrayQueue<u8>> = Once::new();
+static WAKER: AtomicWaker = AtomicWaker::new();
+/// Set once a [`SerialStream`] has been created
+static TAKEN: AtomicBool = AtomicBool::new(false);
+/// The number of received bytes lost because the queue was full
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Start handling interrupts for received data
+///
+/// The port itself is set up to raise them when first used. Must be called
+/// after the heap has been initialized.
+pub fn init() {
+    lazy_static::initialize(&SERIAL1);
+    RECEIVED.call_once(|| ArrayQueue::new(QUEUE_SIZE));
+    irq::register_irq(irq::line::COM1, serial_interrupt_handler, "serial")
+        .expect("Failed to register the serial interrupt handler");
+}
+
+/// The number of received bytes dropped because nobody was reading them
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+fn serial_interrupt_handler(_: u8) -> IrqReturn {
+    let mut line_status = Port::<u8>::new(COM1 + LINE_STATUS);
+    let mut data = Port::<u8>::new(COM1);
+    let queue = RECEIVED.get();
+
+    // Reading the data doesn't need the lock: only sending does
+    let mut handled = IrqReturn::NotMine;
+    while unsafe { line_status.read() } & DATA_READY != 0 {
+        let byte = unsafe { data.read() };
+        handled = IrqReturn::Handled;
+        if queue.map(|queue| queue.push(byte)) != Some(Ok(())) {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    if handled == IrqReturn::Handled {
+        WAKER.wake();
+    }
+    handled
+}
+
+/// The bytes received on COM1
+///
+/// There can only be one, since every byte is only read once.
+pub struct SerialStream {
+    queue: &'static ArrayQueue<u8>,
+}
+
+impl SerialStream {
+    /// Panics if a stream was already created, or if [`init`] hasn't been
+    /// called
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        assert!(
+            !TAKEN.swap(true, Ordering::AcqRel),
+            "There can only be one SerialStream"
+        );
+        let queue = RECEIVED
+            .get()
+            .expect("Serial input has not been initialized yet");
+        Self { queue }
+    }
+}
+
+impl Stream for SerialStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        if let Some(byte) = self.queue.pop() {
+            return Poll::Ready(Some(byte));
+        }
+
+        WAKER.register(cx.waker());
+        match self.queue.pop() {
+            Some(byte) => {
+                WAKER.take();
+                Poll::Ready(Some(byte))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Print every character received on the serial port
+pub async fn print_input() {
+    let mut input = SerialStream::new();
+    while let Some(byte) = input.next().await {
+        match byte {
+            b'\r' => print!("\n"),
+            _ => print!("{}", byte as char),
+        }
+    }
 }
 
 /// Prints to the host through the serial interface.
@@ -0,0 +1,105 @@
+use core::time::Duration;
+
+use super::{deadline, MutexGuard, WaitQueue};
+
+/// A condition variable, to wait for a change to data protected by a
+/// [`Mutex`](super::Mutex)
+///
+/// Wakeups can be spurious, so the condition must be checked again after
+/// every wait.
+pub struct Condvar {
+    queue: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            queue: WaitQueue::new(),
+        }
+    }
+
+    /// Unlock the mutex and block until notified, then lock it again
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        self.wait_deadline(guard, None).0
+    }
+
+    /// Like [`Condvar::wait`], but stop waiting after `timeout`. The
+    /// returned flag is `false` if the wait timed out.
+    pub fn wait_timeout<'a, T: ?Sized>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Duration,
+    ) -> (MutexGuard<'a, T>, bool) {
+        self.wait_deadline(guard, Some(deadline(timeout)))
+    }
+
+    /// Block until `condition` returns `false`, unlocking the mutex while
+    /// waiting
+    pub fn wait_while<'a, T: ?Sized>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    fn wait_deadline<'a, T: ?Sized>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        deadline: Option<u64>,
+    ) -> (MutexGuard<'a, T>, bool) {
+        let mutex = guard.mutex;
+        // Notifications can't be missed, since the task is on the queue
+        // before the mutex is unlocked
+        let woken = self.queue.wait(deadline, || drop(guard));
+        (mutex.lock(), woken)
+    }
+
+    /// Wake up one waiting task
+    pub fn notify_one(&self) {
+        self.queue.wake_one();
+    }
+
+    /// Wake up every waiting task
+    pub fn notify_all(&self) {
+        self.queue.wake_all();
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Condvar;
+    use crate::{sync::Mutex, thread};
+    use alloc::sync::Arc;
+    use core::time::Duration;
+
+    #[test_case]
+    fn notify_wakes_waiter() {
+        let pair = Arc::new((Mutex::new(false), Condvar::new()));
+        let notifier = {
+            let pair = pair.clone();
+            thread::spawn("notify", move || {
+                *pair.0.lock() = true;
+                pair.1.notify_one();
+            })
+        };
+
+        let (mutex, condvar) = &*pair;
+        let ready = condvar.wait_while(mutex.lock(), |ready| !*ready);
+        assert!(*ready);
+        drop(ready);
+        notifier.join().unwrap();
+
+        let (_, woken) = condvar.wait_timeout(mutex.lock(), Duration::from_millis(20));
+        assert!(!woken);
+    }
+}
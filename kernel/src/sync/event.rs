@@ -0,0 +1,87 @@
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use super::{deadline, WaitQueue};
+
+/// A flag that tasks can wait for
+///
+/// Once set, the event stays set (and waiting returns immediately) until
+/// it's [reset](Event::reset).
+pub struct Event {
+    set: AtomicBool,
+    queue: WaitQueue,
+}
+
+impl Event {
+    pub const fn new() -> Self {
+        Self {
+            set: AtomicBool::new(false),
+            queue: WaitQueue::new(),
+        }
+    }
+
+    /// Set the event, waking up every waiting task
+    pub fn set(&self) {
+        self.set.store(true, Ordering::Release);
+        self.queue.wake_all();
+    }
+
+    pub fn reset(&self) {
+        self.set.store(false, Ordering::Release);
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.set.load(Ordering::Acquire)
+    }
+
+    /// Block until the event is set
+    pub fn wait(&self) {
+        self.queue
+            .wait_until(None, || self.is_set().then_some(()))
+            .expect("Waited without a deadline, and timed out")
+    }
+
+    /// Block until the event is set, giving up after `timeout`. Returns
+    /// whether the event was set.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        self.queue
+            .wait_until(Some(deadline(timeout)), || self.is_set().then_some(()))
+            .is_some()
+    }
+}
+
+impl Default for Event {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Event;
+    use crate::thread;
+    use alloc::{sync::Arc, vec::Vec};
+    use core::time::Duration;
+
+    #[test_case]
+    fn set_wakes_every_waiter() {
+        let event = Arc::new(Event::new());
+        assert!(!event.wait_timeout(Duration::from_millis(20)));
+
+        let waiters: Vec<_> = (0..2)
+            .map(|_| {
+                let event = event.clone();
+                thread::spawn("event", move || event.wait())
+            })
+            .collect();
+        thread::yield_now();
+        event.set();
+
+        for waiter in waiters {
+            waiter.join().unwrap();
+        }
+        assert!(event.is_set());
+    }
+}
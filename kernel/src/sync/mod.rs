@@ -0,0 +1,123 @@
+//! Blocking synchronization primitives
+//!
+//! Unlike `spin::Mutex`, these put the waiting task to sleep on a
+//! [`WaitQueue`] instead of spinning, so they can be held for a long time
+//! (for example while doing I/O). They must not be used from interrupt
+//! handlers, which can't block: spinlocks are still the right tool there,
+//! and for very short critical sections.
+//!
+//! Every blocking operation has a variant with a timeout, measured with the
+//! [system timer](crate::time), so it has a resolution of one tick.
+
+mod condvar;
+mod event;
+mod mutex;
+mod rwlock;
+mod semaphore;
+
+pub use condvar::Condvar;
+pub use event::Event;
+pub use mutex::{Mutex, MutexGuard};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::Semaphore;
+
+use alloc::{collections::VecDeque, sync::Arc};
+use core::time::Duration;
+
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::{
+    sched::{self, Task},
+    time,
+};
+
+/// The tick at which a timeout starting now expires
+///
+/// The current tick is already partly over, so one more is needed.
+pub(crate) fn deadline(timeout: Duration) -> u64 {
+    time::ticks() + time::duration_to_ticks(timeout) + 1
+}
+
+/// A list of tasks waiting for something to happen
+pub struct WaitQueue {
+    /// Only locked with interrupts disabled
+    waiters: spin::Mutex<VecDeque<Arc<Task>>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: spin::Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Put the current task on the queue, call `before_block` and block
+    /// until woken up, or until the timer reaches `deadline`
+    ///
+    /// Nothing can wake the task between `before_block` and blocking, so it
+    /// can be used to release a lock. Returns `false` if the deadline passed.
+    pub fn wait(&self, deadline: Option<u64>, before_block: impl FnOnce()) -> bool {
+        without_interrupts(|| {
+            let current = sched::current();
+            self.waiters.lock().push_back(current.clone());
+            before_block();
+
+            let woken = sched::block(deadline);
+            if !woken {
+                self.waiters
+                    .lock()
+                    .retain(|task| !Arc::ptr_eq(task, &current));
+            }
+            woken
+        })
+    }
+
+    /// Block until `condition` returns `Some`, or until the timer reaches
+    /// `deadline`, in which case `None` is returned
+    ///
+    /// `condition` is checked again after every wakeup, with interrupts
+    /// disabled, so it must not block.
+    pub fn wait_until<T>(
+        &self,
+        deadline: Option<u64>,
+        mut condition: impl FnMut() -> Option<T>,
+    ) -> Option<T> {
+        without_interrupts(|| loop {
+            if let Some(value) = condition() {
+                return Some(value);
+            }
+            if !self.wait(deadline, || ()) {
+                return condition();
+            }
+        })
+    }
+
+    /// Wake up the task that has been waiting the longest, returning
+    /// whether there was one
+    pub fn wake_one(&self) -> bool {
+        without_interrupts(|| {
+            let mut waiters = self.waiters.lock();
+            while let Some(task) = waiters.pop_front() {
+                // It might have been woken up already, by its deadline
+                if sched::wake(&task) {
+                    return true;
+                }
+            }
+            false
+        })
+    }
+
+    /// Wake up every waiting task, returning how many there were
+    pub fn wake_all(&self) -> usize {
+        without_interrupts(|| {
+            let waiters = core::mem::take(&mut *self.waiters.lock());
+            waiters.iter().filter(|task| sched::wake(task)).count()
+        })
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
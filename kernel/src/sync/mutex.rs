@@ -0,0 +1,193 @@
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use types::held_locks;
+#[cfg(feature = "lockdep")]
+use {core::panic::Location, types::lockdep};
+
+use super::{deadline, WaitQueue};
+
+/// A mutual exclusion lock that blocks while waiting
+pub struct Mutex<T: ?Sized> {
+    locked: AtomicBool,
+    queue: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            queue: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        // Moving out skips `Drop`, which only informs lockdep
+        let this = ManuallyDrop::new(self);
+        #[cfg(feature = "lockdep")]
+        lockdep::forget(this.class());
+        unsafe { ptr::read(&this.data) }.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// Lock the mutex, blocking until it's available
+    #[track_caller]
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        // Reported before waiting, so that deadlocks are reported too
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(self.class(), Location::caller(), false);
+        self.queue
+            .wait_until(None, || self.acquire())
+            .expect("Waited without a deadline, and timed out")
+    }
+
+    /// Lock the mutex, giving up if it's not available within `timeout`
+    #[track_caller]
+    pub fn lock_timeout(&self, timeout: Duration) -> Option<MutexGuard<'_, T>> {
+        let guard = self
+            .queue
+            .wait_until(Some(deadline(timeout)), || self.acquire());
+        #[cfg(feature = "lockdep")]
+        if guard.is_some() {
+            lockdep::acquire(self.class(), Location::caller(), false);
+        }
+        guard
+    }
+
+    /// Lock the mutex only if it's available right now
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let guard = self.acquire();
+        #[cfg(feature = "lockdep")]
+        if guard.is_some() {
+            lockdep::acquire(self.class(), Location::caller(), false);
+        }
+        guard
+    }
+
+    fn acquire(&self) -> Option<MutexGuard<'_, T>> {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| {
+                held_locks::acquired();
+                MutexGuard { mutex: self }
+            })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    #[cfg(feature = "lockdep")]
+    fn class(&self) -> usize {
+        self as *const Self as *const () as usize
+    }
+}
+
+#[cfg(feature = "lockdep")]
+impl<T: ?Sized> Drop for Mutex<T> {
+    fn drop(&mut self) {
+        lockdep::forget(self.class());
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => f.debug_struct("Mutex").field("data", &&*guard).finish(),
+            None => f.write_str("Mutex { <locked> }"),
+        }
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// Unlocks the [`Mutex`] when dropped
+pub struct MutexGuard<'a, T: ?Sized> {
+    pub(super) mutex: &'a Mutex<T>,
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.locked.store(false, Ordering::Release);
+        held_locks::released();
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.mutex.class());
+        self.mutex.queue.wake_one();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Mutex;
+    use crate::thread;
+    use alloc::sync::Arc;
+    use core::time::Duration;
+
+    #[test_case]
+    fn threads_take_turns() {
+        let counter = Arc::new(Mutex::new(0));
+        let handles: alloc::vec::Vec<_> = (0..3)
+            .map(|_| {
+                let counter = counter.clone();
+                thread::spawn("mutex", move || {
+                    for _ in 0..100 {
+                        let mut guard = counter.lock();
+                        let value = *guard;
+                        // Give the other threads a chance to see the lock held
+                        thread::yield_now();
+                        *guard = value + 1;
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(*counter.lock(), 300);
+    }
+
+    #[test_case]
+    fn lock_times_out() {
+        let mutex = Mutex::new(());
+        let _guard = mutex.lock();
+        assert!(mutex.lock_timeout(Duration::from_millis(20)).is_none());
+    }
+}
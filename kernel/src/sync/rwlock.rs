@@ -0,0 +1,244 @@
+use core::{
+    cell::UnsafeCell,
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use types::held_locks;
+#[cfg(feature = "lockdep")]
+use {core::panic::Location, types::lockdep};
+
+use super::{deadline, WaitQueue};
+
+/// The value of `state` while a writer holds the lock
+const WRITER: usize = usize::MAX;
+
+/// A lock allowing either many readers or a single writer, which blocks
+/// while waiting
+///
+/// There's no priority between readers and writers: whoever runs first
+/// after the lock is released gets it.
+pub struct RwLock<T: ?Sized> {
+    /// The number of readers, or [`WRITER`]
+    state: AtomicUsize,
+    queue: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            queue: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        // Moving out skips `Drop`, which only informs lockdep
+        let this = ManuallyDrop::new(self);
+        #[cfg(feature = "lockdep")]
+        lockdep::forget(this.class());
+        unsafe { ptr::read(&this.data) }.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Lock for reading, blocking while there's a writer
+    #[track_caller]
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(self.class(), Location::caller(), true);
+        self.queue
+            .wait_until(None, || self.acquire_read())
+            .expect("Waited without a deadline, and timed out")
+    }
+
+    /// Lock for reading, giving up if it's not possible within `timeout`
+    #[track_caller]
+    pub fn read_timeout(&self, timeout: Duration) -> Option<RwLockReadGuard<'_, T>> {
+        let guard = self
+            .queue
+            .wait_until(Some(deadline(timeout)), || self.acquire_read());
+        #[cfg(feature = "lockdep")]
+        if guard.is_some() {
+            lockdep::acquire(self.class(), Location::caller(), true);
+        }
+        guard
+    }
+
+    /// Lock for reading only if there's no writer right now
+    #[track_caller]
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        let guard = self.acquire_read();
+        #[cfg(feature = "lockdep")]
+        if guard.is_some() {
+            lockdep::acquire(self.class(), Location::caller(), true);
+        }
+        guard
+    }
+
+    fn acquire_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        self.state
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |readers| {
+                match readers {
+                    WRITER => None,
+                    // Leave `WRITER` unreachable
+                    _ if readers == WRITER - 1 => None,
+                    _ => Some(readers + 1),
+                }
+            })
+            .ok()
+            .map(|_| {
+                held_locks::acquired();
+                RwLockReadGuard { lock: self }
+            })
+    }
+
+    /// Lock for writing, blocking while there are readers or a writer
+    #[track_caller]
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(self.class(), Location::caller(), false);
+        self.queue
+            .wait_until(None, || self.acquire_write())
+            .expect("Waited without a deadline, and timed out")
+    }
+
+    /// Lock for writing, giving up if it's not possible within `timeout`
+    #[track_caller]
+    pub fn write_timeout(&self, timeout: Duration) -> Option<RwLockWriteGuard<'_, T>> {
+        let guard = self
+            .queue
+            .wait_until(Some(deadline(timeout)), || self.acquire_write());
+        #[cfg(feature = "lockdep")]
+        if guard.is_some() {
+            lockdep::acquire(self.class(), Location::caller(), false);
+        }
+        guard
+    }
+
+    /// Lock for writing only if nobody holds the lock right now
+    #[track_caller]
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        let guard = self.acquire_write();
+        #[cfg(feature = "lockdep")]
+        if guard.is_some() {
+            lockdep::acquire(self.class(), Location::caller(), false);
+        }
+        guard
+    }
+
+    fn acquire_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        self.state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| {
+                held_locks::acquired();
+                RwLockWriteGuard { lock: self }
+            })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    #[cfg(feature = "lockdep")]
+    fn class(&self) -> usize {
+        self as *const Self as *const () as usize
+    }
+}
+
+#[cfg(feature = "lockdep")]
+impl<T: ?Sized> Drop for RwLock<T> {
+    fn drop(&mut self) {
+        lockdep::forget(self.class());
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// Releases a read lock on the [`RwLock`] when dropped
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        held_locks::released();
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.lock.class());
+        if self.lock.state.fetch_sub(1, Ordering::Release) == 1 {
+            // Only writers can be waiting for the last reader
+            self.lock.queue.wake_one();
+        }
+    }
+}
+
+/// Releases the write lock on the [`RwLock`] when dropped
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.store(0, Ordering::Release);
+        held_locks::released();
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.lock.class());
+        // Every waiting reader can go on, or one of the writers
+        self.lock.queue.wake_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RwLock;
+    use core::time::Duration;
+
+    #[test_case]
+    fn readers_exclude_writers() {
+        let lock = RwLock::new(1);
+        {
+            let first = lock.read();
+            let second = lock.read();
+            assert_eq!(*first + *second, 2);
+            assert!(lock.write_timeout(Duration::from_millis(20)).is_none());
+        }
+
+        *lock.write() = 2;
+        assert_eq!(*lock.read(), 2);
+    }
+}
@@ -0,0 +1,79 @@
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use super::{deadline, WaitQueue};
+
+/// A counting semaphore, which blocks while no permits are available
+pub struct Semaphore {
+    permits: AtomicUsize,
+    queue: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            permits: AtomicUsize::new(permits),
+            queue: WaitQueue::new(),
+        }
+    }
+
+    /// Take a permit, blocking until one is available
+    pub fn acquire(&self) {
+        self.queue
+            .wait_until(None, || self.try_acquire().then_some(()))
+            .expect("Waited without a deadline, and timed out")
+    }
+
+    /// Take a permit, giving up if none is available within `timeout`.
+    /// Returns whether a permit was taken.
+    pub fn acquire_timeout(&self, timeout: Duration) -> bool {
+        self.queue
+            .wait_until(Some(deadline(timeout)), || self.try_acquire().then_some(()))
+            .is_some()
+    }
+
+    /// Take a permit only if one is available right now
+    pub fn try_acquire(&self) -> bool {
+        self.permits
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |permits| {
+                permits.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    /// Give back a permit, waking up a task waiting for it
+    pub fn release(&self) {
+        self.permits.fetch_add(1, Ordering::Release);
+        self.queue.wake_one();
+    }
+
+    /// The number of permits available right now
+    pub fn available(&self) -> usize {
+        self.permits.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Semaphore;
+    use crate::thread;
+    use alloc::sync::Arc;
+    use core::time::Duration;
+
+    #[test_case]
+    fn acquire_waits_for_release() {
+        let semaphore = Arc::new(Semaphore::new(1));
+        semaphore.acquire();
+        assert!(!semaphore.acquire_timeout(Duration::from_millis(20)));
+
+        let releaser = {
+            let semaphore = semaphore.clone();
+            thread::spawn("release", move || semaphore.release())
+        };
+        semaphore.acquire();
+        releaser.join().unwrap();
+        assert_eq!(semaphore.available(), 0);
+    }
+}
@@ -0,0 +1,340 @@
+//! System calls
+//!
+//! User code enters the kernel with `syscall` (or `int 0x80`, which is
+//! slower but handy when debugging), passing the syscall number in `rax`
+//! and the arguments in `rdi`, `rsi`, `rdx`, `r10`, `r8` and `r9`. Numbers,
+//! argument order and error codes follow the Linux x86_64 ABI, so that
+//! ordinary toolchains can target the kernel.
+//!
+//! The result is returned in `rax`: negative values between -4095 and -1
+//! are an [`Errno`], anything else is a successful result.
+
+#[path = "../arch/x86_64/syscall.rs"]
+mod arch;
+
+pub use arch::SyscallFrame;
+
+use alloc::sync::Arc;
+use core::{marker::PhantomData, slice};
+
+use x86_64::{
+    registers::{
+        model_specific::{Efer, EferFlags, LStar, SFMask, Star},
+        rflags::RFlags,
+    },
+    VirtAddr,
+};
+
+use crate::{
+    gdt,
+    interrupts::exceptions::vector,
+    kernel_state,
+    process::{self, Console, File},
+    sched,
+    user::{self, UserExit, USER_END},
+};
+use types::Pager;
+
+/// The vector of the `int 0x80` fallback
+pub const INT80_VECTOR: u8 = 0x80;
+
+/// Syscall numbers
+pub mod number {
+    pub const WRITE: u64 = 1;
+    pub const SCHED_YIELD: u64 = 24;
+    pub const EXIT: u64 = 60;
+}
+
+/// Error codes returned by system calls
+#[derive(displaydoc::Display, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i64)]
+pub enum Errno {
+    /// operation not permitted
+    EPERM = 1,
+    /// no such file or directory
+    ENOENT = 2,
+    /// no such process
+    ESRCH = 3,
+    /// interrupted system call
+    EINTR = 4,
+    /// I/O error
+    EIO = 5,
+    /// bad file descriptor
+    EBADF = 9,
+    /// no child processes
+    ECHILD = 10,
+    /// resource temporarily unavailable
+    EAGAIN = 11,
+    /// out of memory
+    ENOMEM = 12,
+    /// bad address
+    EFAULT = 14,
+    /// invalid argument
+    EINVAL = 22,
+    /// function not implemented
+    ENOSYS = 38,
+}
+
+impl Errno {
+    /// The value returned to user code in `rax`
+    pub fn to_return(self) -> u64 {
+        (-(self as i64)) as u64
+    }
+}
+
+pub type SyscallResult = Result<u64, Errno>;
+
+/// A type that can be decoded from a raw syscall argument
+pub trait SyscallArg: Sized {
+    fn decode(raw: u64) -> Result<Self, Errno>;
+}
+
+impl SyscallArg for u64 {
+    fn decode(raw: u64) -> Result<Self, Errno> {
+        Ok(raw)
+    }
+}
+
+impl SyscallArg for usize {
+    fn decode(raw: u64) -> Result<Self, Errno> {
+        Ok(raw as usize)
+    }
+}
+
+/// Like Linux, `int` arguments only look at the lower 32 bits
+impl SyscallArg for i32 {
+    fn decode(raw: u64) -> Result<Self, Errno> {
+        Ok(raw as i32)
+    }
+}
+
+impl SyscallArg for u32 {
+    fn decode(raw: u64) -> Result<Self, Errno> {
+        Ok(raw as u32)
+    }
+}
+
+impl<T> SyscallArg for UserPtr<T> {
+    fn decode(raw: u64) -> Result<Self, Errno> {
+        Ok(UserPtr {
+            addr: raw,
+            _marker: PhantomData,
+        })
+    }
+}
+
+/// A pointer passed in by user code
+///
+/// It can't be dereferenced directly: it must first be checked to point to
+/// memory that user code is allowed to access.
+#[derive(Debug, Clone, Copy)]
+pub struct UserPtr<T> {
+    addr: u64,
+    _marker: PhantomData<*const T>,
+}
+
+impl<T> UserPtr<T> {
+    pub fn addr(&self) -> u64 {
+        self.addr
+    }
+
+    /// Borrow `len` elements starting at this pointer
+    ///
+    /// The memory is only checked once, so this relies on user code not
+    /// being able to change its own mappings in the meantime.
+    pub fn slice(&self, len: usize) -> Result<&[T], Errno> {
+        check_user_range(
+            self.addr,
+            len,
+            core::mem::size_of::<T>(),
+            core::mem::align_of::<T>(),
+            false,
+        )?;
+        if len == 0 {
+            return Ok(&[]);
+        }
+        Ok(unsafe { slice::from_raw_parts(self.addr as *const T, len) })
+    }
+
+    /// Mutably borrow `len` elements starting at this pointer
+    // The memory belongs to user code, not to the pointer
+    #[allow(clippy::mut_from_ref)]
+    pub fn slice_mut(&self, len: usize) -> Result<&mut [T], Errno> {
+        check_user_range(
+            self.addr,
+            len,
+            core::mem::size_of::<T>(),
+            core::mem::align_of::<T>(),
+            true,
+        )?;
+        if len == 0 {
+            return Ok(&mut []);
+        }
+        Ok(unsafe { slice::from_raw_parts_mut(self.addr as *mut T, len) })
+    }
+}
+
+/// Check that `len` elements of `size` bytes at `addr` are mapped in user
+/// space, and accessible by user code
+fn check_user_range(
+    addr: u64,
+    len: usize,
+    size: usize,
+    align: usize,
+    write: bool,
+) -> Result<(), Errno> {
+    let bytes = (len as u64).checked_mul(size as u64).ok_or(Errno::EFAULT)?;
+    let end = addr.checked_add(bytes).ok_or(Errno::EFAULT)?;
+    if bytes == 0 {
+        return Ok(());
+    }
+    if addr == 0 || !addr.is_multiple_of(align as u64) || end > USER_END {
+        return Err(Errno::EFAULT);
+    }
+
+    let pager = kernel_state().pager.lock();
+    let mut page = addr & !0xFFF;
+    while page < end {
+        match pager.flags(types::VirtAddr(page)) {
+            Some(flags) if flags.user && (flags.writable || !write) => {}
+            _ => return Err(Errno::EFAULT),
+        }
+        page += 0x1000;
+    }
+    Ok(())
+}
+
+struct Syscall {
+    number: u64,
+    name: &'static str,
+    handler: fn(&[u64; 6]) -> SyscallResult,
+}
+
+/// Build the syscall table, decoding every argument with [`SyscallArg`]
+macro_rules! syscall_table {
+    ($($number: path => $handler: ident($($arg: ty),*);)*) => {
+        static TABLE: &[Syscall] = &[$(
+            Syscall {
+                number: $number,
+                name: stringify!($handler),
+                handler: |args| {
+                    let mut _args = args.iter().copied();
+                    $handler($(<$arg as SyscallArg>::decode(_args.next().unwrap())?),*)
+                },
+            }
+        ),*];
+    };
+}
+
+syscall_table! {
+    number::WRITE => sys_write(i32, UserPtr<u8>, usize);
+    number::SCHED_YIELD => sys_sched_yield();
+    number::EXIT => sys_exit(i32);
+}
+
+/// The name of a syscall, for debugging
+pub fn name(number: u64) -> Option<&'static str> {
+    TABLE
+        .iter()
+        .find(|syscall| syscall.number == number)
+        .map(|syscall| syscall.name)
+}
+
+/// Enable the `syscall` instruction
+///
+/// Must be called after the GDT has been loaded
+pub fn init() {
+    let selectors = gdt::selectors();
+    Star::write(
+        selectors.user_code,
+        selectors.user_data,
+        selectors.kernel_code,
+        selectors.kernel_data,
+    )
+    .expect("The GDT layout doesn't work with sysret");
+    LStar::write(VirtAddr::new(
+        arch::syscall_entry as unsafe extern "C" fn() as usize as u64,
+    ));
+    // Enter with interrupts disabled, until the stack has been switched
+    SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::DIRECTION_FLAG | RFlags::TRAP_FLAG);
+
+    unsafe { Efer::update(|flags| flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS)) };
+}
+
+/// The address of the `int 0x80` handler
+pub fn int80_handler_addr() -> VirtAddr {
+    VirtAddr::new(arch::syscall_int80 as unsafe extern "C" fn() as usize as u64)
+}
+
+/// The entry point for all system calls, called by the entry stubs
+extern "C" fn dispatch(frame: &mut SyscallFrame) {
+    let result = match TABLE.iter().find(|syscall| syscall.number == frame.rax) {
+        Some(syscall) => (syscall.handler)(&frame.args()),
+        None => Err(Errno::ENOSYS),
+    };
+    frame.rax = match result {
+        Ok(value) => value,
+        Err(errno) => errno.to_return(),
+    };
+
+    // `sysret` to a non-canonical address raises #GP in ring 0, but with
+    // the user stack, so treat it as a fault of the user code instead
+    if frame.rip >= USER_END {
+        unsafe {
+            user::exit_to_kernel(UserExit::Fault {
+                vector: vector::GENERAL_PROTECTION,
+                error_code: 0,
+                rip: frame.rip,
+            })
+        }
+    }
+    unsafe { user::exit_if_killed() };
+}
+
+fn sys_write(fd: i32, buf: UserPtr<u8>, count: usize) -> SyscallResult {
+    let bytes = buf.slice(count)?;
+    let file: Arc<dyn File> = match process::current() {
+        Some(process) => process.files().get(fd).ok_or(Errno::EBADF)?,
+        // Tasks that aren't processes can only write to the console
+        None if fd == 1 || fd == 2 => Arc::new(Console),
+        None => return Err(Errno::EBADF),
+    };
+    file.write(bytes).map(|written| written as u64)
+}
+
+fn sys_sched_yield() -> SyscallResult {
+    sched::yield_now();
+    Ok(0)
+}
+
+fn sys_exit(status: i32) -> SyscallResult {
+    unsafe { user::exit_to_kernel(UserExit::Exited(status)) }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::user::{test_util::run_user_code, UserExit};
+
+    const CODE: u64 = 0x0000_1000_0010_0000;
+
+    #[test_case]
+    fn write_and_exit() {
+        // write(1, "hello\n", 6); exit(<result of write>)
+        let code = [
+            0x48, 0x8d, 0x35, 0x1a, 0x00, 0x00, 0x00, 0xbf, 0x01, 0x00, 0x00, 0x00, 0xba, 0x06,
+            0x00, 0x00, 0x00, 0xb8, 0x01, 0x00, 0x00, 0x00, 0x0f, 0x05, 0x89, 0xc7, 0xb8, 0x3c,
+            0x00, 0x00, 0x00, 0x0f, 0x05, b'h', b'e', b'l', b'l', b'o', b'\n',
+        ];
+        assert_eq!(run_user_code(CODE, &code), UserExit::Exited(6));
+    }
+
+    #[test_case]
+    fn int80_unknown_syscall() {
+        // exit(syscall 999), both through int 0x80
+        let code = [
+            0xb8, 0xe7, 0x03, 0x00, 0x00, 0xcd, 0x80, 0x89, 0xc7, 0xb8, 0x3c, 0x00, 0x00, 0x00,
+            0xcd, 0x80,
+        ];
+        assert_eq!(run_user_code(CODE + 0x10000, &code), UserExit::Exited(-38));
+    }
+}
@@ -0,0 +1,266 @@
+//! Kernel threads
+//!
+//! A thread is a [task](crate::sched::Task) running a closure, whose result
+//! can be retrieved with [`JoinHandle::join`]. Each thread also has its own
+//! copy of every [`kernel_local!`](crate::kernel_local) variable.
+//!
+//! The kernel can't unwind, so a thread that panics just stops running,
+//! without dropping anything: the panic is reported with the thread's name,
+//! and the rest of the kernel carries on. Since nothing is dropped, this is
+//! only done if the thread holds no [locks](types::held_locks), which would
+//! otherwise stay locked forever. The kernel also stops if the panic
+//! happened with interrupts disabled (for example in an interrupt handler),
+//! or in the boot or idle tasks. A panic in the task of a
+//! [process](crate::process) ends the process with [`ExitStatus::Crashed`].
+
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc};
+use core::{any::Any, marker::PhantomData, panic::PanicInfo, time::Duration};
+
+use spin::Mutex;
+use types::held_locks;
+use x86_64::instructions::interrupts;
+
+use crate::{
+    backtrace::Backtrace,
+    println,
+    process::{self, ExitStatus},
+    sched::{self, State, Task},
+    time,
+};
+
+pub use crate::sched::{yield_now, TaskId as ThreadId};
+
+/// A handle to a thread
+#[derive(Clone)]
+pub struct Thread {
+    task: Arc<Task>,
+}
+
+impl Thread {
+    pub fn id(&self) -> ThreadId {
+        self.task.id()
+    }
+
+    pub fn name(&self) -> &str {
+        self.task.name()
+    }
+}
+
+impl core::fmt::Debug for Thread {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Thread")
+            .field("id", &self.id())
+            .field("name", &self.name())
+            .finish()
+    }
+}
+
+/// The thread running this code
+pub fn current() -> Thread {
+    Thread {
+        task: sched::current(),
+    }
+}
+
+#[derive(displaydoc::Display, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    /// the thread panicked before returning
+    Panicked,
+}
+
+/// An owned permission to wait for a thread to end, and get its result
+pub struct JoinHandle<T> {
+    thread: Thread,
+    result: Arc<Mutex<Option<T>>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn thread(&self) -> &Thread {
+        &self.thread
+    }
+
+    /// Whether the thread has stopped running
+    pub fn is_finished(&self) -> bool {
+        self.thread.task.state() == State::Dead
+    }
+
+    /// Wait for the thread to end, and return the value its closure returned
+    pub fn join(self) -> Result<T, JoinError> {
+        sched::wait_for_exit(&self.thread.task);
+        self.result.lock().take().ok_or(JoinError::Panicked)
+    }
+}
+
+/// Start a new thread running `f`
+///
+/// Panics if there's no memory left for the thread's stack.
+pub fn spawn<F, T>(name: &str, f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let result = Arc::new(Mutex::new(None));
+    let task = {
+        let result = result.clone();
+        sched::spawn(name, move || {
+            let value = f();
+            *result.lock() = Some(value);
+        })
+    };
+
+    JoinHandle {
+        thread: Thread { task },
+        result,
+    }
+}
+
+/// Block the current thread for at least `duration`
+pub fn sleep(duration: Duration) {
+    if duration == Duration::ZERO {
+        return yield_now();
+    }
+    // The current tick is already partly over
+    sched::sleep_until(time::ticks() + time::duration_to_ticks(duration) + 1);
+}
+
+/// Called by the panic handlers: if the current thread can be stopped
+/// without taking down the kernel, report the panic and stop it
+///
+/// Returns if the kernel must stop instead.
+pub fn handle_panic(info: &PanicInfo) {
+    if !interrupts::are_enabled() || held_locks::count() != 0 {
+        return;
+    }
+    let task = match sched::try_current() {
+        Some(task) if sched::can_exit(&task) => task,
+        _ => return,
+    };
+
+    println!("thread '{}' {}", task.name(), info);
+    println!("{}", Backtrace::capture());
+    drop(task);
+    if process::current().is_some() {
+        process::exit(ExitStatus::Crashed)
+    }
+    sched::exit()
+}
+
+/// The kernel-local variables of a thread, created on first use
+#[derive(Default)]
+pub(crate) struct Locals(Mutex<BTreeMap<usize, Box<dyn Any>>>);
+
+/// A variable with a separate value for every thread
+///
+/// Created with [`kernel_local!`](crate::kernel_local).
+pub struct LocalKey<T: 'static> {
+    init: fn() -> T,
+    // `LocalKey` must be `Sync` to be put in a static even if `T` isn't,
+    // since each thread only gets to see its own value
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: 'static> LocalKey<T> {
+    #[doc(hidden)]
+    pub const fn new(init: fn() -> T) -> Self {
+        Self {
+            init,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Call `f` with the current thread's value, initializing it first if
+    /// this thread never used it
+    pub fn with<R>(&'static self, f: impl FnOnce(&T) -> R) -> R {
+        let task = sched::current();
+        let key = self as *const Self as usize;
+
+        // The value is boxed, so it doesn't move, and it's only dropped
+        // with the task, which is kept alive here
+        let value: *const T = {
+            let mut locals = task.locals.0.lock();
+            let value = locals.entry(key).or_insert_with(|| Box::new((self.init)()));
+            value.downcast_ref::<T>().unwrap()
+        };
+        f(unsafe { &*value })
+    }
+}
+
+/// Declare [`LocalKey`]s, in the same way as `std::thread_local!`
+///
+/// ```ignore
+/// kernel_local! {
+///     static COUNTER: Cell<u32> = Cell::new(0);
+/// }
+///
+/// COUNTER.with(|counter| counter.set(counter.get() + 1));
+/// ```
+#[macro_export]
+macro_rules! kernel_local {
+    ($($(#[$attr: meta])* $vis: vis static $name: ident: $ty: ty = $init: expr;)*) => {
+        $(
+            $(#[$attr])*
+            $vis static $name: $crate::thread::LocalKey<$ty> = {
+                fn init() -> $ty {
+                    $init
+                }
+                $crate::thread::LocalKey::new(init)
+            };
+        )*
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::{current, sleep, spawn, JoinError};
+    use crate::{sync::Mutex, time};
+    use core::{cell::Cell, time::Duration};
+    use types::held_locks;
+
+    #[test_case]
+    fn join_returns_result() {
+        let handle = spawn("worker", || current().name() == "worker");
+        assert_eq!(handle.thread().name(), "worker");
+        assert_eq!(handle.join(), Ok(true));
+    }
+
+    #[test_case]
+    fn panics_stop_only_the_thread() {
+        let handle = spawn("doomed", || -> u32 { panic!("expected panic") });
+        assert_eq!(handle.join(), Err(JoinError::Panicked));
+    }
+
+    #[test_case]
+    fn held_locks_are_counted_per_thread() {
+        let mutex = Mutex::new(());
+        let before = held_locks::count();
+        let guard = mutex.lock();
+        assert_eq!(held_locks::count(), before + 1);
+
+        // Switching to another thread while holding the lock
+        let other = spawn("no locks", held_locks::count);
+        assert_eq!(other.join(), Ok(0));
+        assert_eq!(held_locks::count(), before + 1);
+
+        drop(guard);
+        assert_eq!(held_locks::count(), before);
+    }
+
+    #[test_case]
+    fn sleep_waits() {
+        let start = time::ticks();
+        sleep(Duration::from_millis(30));
+        assert!(time::ticks() - start >= time::duration_to_ticks(Duration::from_millis(30)));
+    }
+
+    #[test_case]
+    fn kernel_locals_are_per_thread() {
+        kernel_local! {
+            static VALUE: Cell<u32> = Cell::new(1);
+        }
+
+        VALUE.with(|value| value.set(2));
+        let other = spawn("local", || VALUE.with(|value| value.get()));
+        assert_eq!(other.join(), Ok(1));
+        assert_eq!(VALUE.with(|value| value.get()), 2);
+    }
+}
@@ -0,0 +1,70 @@
+//! The system timer
+//!
+//! The PIT raises IRQ 0 [`TICKS_PER_SECOND`] times per second, and every
+//! interrupt is counted as a "tick". Ticks also drive preemption in the
+//! [scheduler](crate::sched).
+
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use x86_64::instructions::port::Port;
+
+use crate::{
+    interrupts::irq::{self, IrqReturn},
+    sched,
+};
+
+/// How many times per second the timer interrupt is raised
+pub const TICKS_PER_SECOND: u64 = 100;
+
+/// The frequency of the PIT's input clock, in Hz
+const PIT_FREQUENCY: u64 = 1_193_182;
+const PIT_CHANNEL0: u16 = 0x40;
+const PIT_COMMAND: u16 = 0x43;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Program the PIT, and start counting timer interrupts
+pub fn init() {
+    let divisor = (PIT_FREQUENCY / TICKS_PER_SECOND) as u16;
+    unsafe {
+        // Channel 0, low byte then high byte, rate generator
+        Port::<u8>::new(PIT_COMMAND).write(0x34);
+        let mut channel = Port::<u8>::new(PIT_CHANNEL0);
+        channel.write(divisor as u8);
+        channel.write((divisor >> 8) as u8);
+    }
+
+    irq::register_irq(irq::line::TIMER, timer_interrupt_handler, "timer")
+        .expect("Failed to register the timer interrupt handler");
+}
+
+/// The number of timer interrupts since the timer was initialized
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// The time since the timer was initialized
+pub fn uptime() -> Duration {
+    ticks_to_duration(ticks())
+}
+
+/// The time taken by a number of ticks
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    Duration::from_nanos(ticks * (1_000_000_000 / TICKS_PER_SECOND))
+}
+
+/// The number of ticks that take at least `duration`
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    let nanos_per_tick = u128::from(1_000_000_000 / TICKS_PER_SECOND);
+    let ticks = duration.as_nanos().div_ceil(nanos_per_tick);
+    ticks.min(u128::from(u64::MAX)) as u64
+}
+
+fn timer_interrupt_handler(_: u8) -> IrqReturn {
+    let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    sched::tick(now);
+    IrqReturn::Handled
+}
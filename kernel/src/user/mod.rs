@@ -0,0 +1,219 @@
+//! Running code in user mode (ring 3)
+//!
+//! [`enter_user`] runs user code until it stops being able to continue: the
+//! call only returns once the code exits through a system call, or raised
+//! an exception that the kernel attributed to it (see
+//! [`FaultAction::KillTask`]). Interrupts and system calls taken while in
+//! user mode are handled as usual, and then resume the user code.
+//!
+//! Every [task](crate::sched::Task) can run user code on its own kernel
+//! stack, so the state needed to return to the kernel is kept per task.
+//!
+//! A task can be asked to stop running user code with [`kill`]: this takes
+//! effect the next time it would return to user mode.
+//!
+//! [`FaultAction::KillTask`]: crate::interrupts::FaultAction::KillTask
+
+#[path = "../arch/x86_64/user.rs"]
+mod arch;
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use spin::Mutex;
+use x86_64::VirtAddr;
+
+use crate::{
+    fpu, gdt,
+    sched::{self, Task},
+};
+
+/// Why user code stopped running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserExit {
+    /// An exception was raised by user code
+    Fault {
+        vector: u8,
+        error_code: u64,
+        rip: u64,
+    },
+    /// User code called `exit` with the given status
+    Exited(i32),
+    /// The task was [killed](kill)
+    Killed,
+}
+
+/// The end of the lower half of the address space, the only part that
+/// user code can access
+pub const USER_END: u64 = 0x0000_8000_0000_0000;
+
+/// The user mode state of a task
+#[derive(Debug, Default)]
+pub struct UserContext {
+    /// The stack pointer to restore when leaving user mode, or 0 if the
+    /// task isn't running user code
+    return_rsp: AtomicU64,
+    /// Why the last call to [`enter_user`] returned
+    exit: Mutex<Option<UserExit>>,
+    /// Set by [`kill`]
+    killed: AtomicBool,
+}
+
+impl UserContext {
+    /// Where the saved kernel registers are, which is also the stack that
+    /// interrupts from user mode must use, or 0 if the task isn't running
+    /// user code
+    pub(crate) fn return_rsp(&self) -> u64 {
+        self.return_rsp.load(Ordering::Relaxed)
+    }
+
+    /// Whether the task was [killed](kill)
+    pub fn is_killed(&self) -> bool {
+        self.killed.load(Ordering::Acquire)
+    }
+}
+
+/// Stop a task from running user code
+///
+/// The task returns [`UserExit::Killed`] from [`enter_user`] instead of
+/// going back to user mode, and every later call returns it right away. If
+/// it's blocked, it's woken up, so that it notices.
+pub fn kill(task: &Arc<Task>) {
+    task.user.killed.store(true, Ordering::Release);
+    sched::wake(task);
+}
+
+/// Whether the current task is running user code (or the kernel is
+/// handling an interrupt that came from user code)
+pub fn in_user_mode() -> bool {
+    sched::current().user.return_rsp() != 0
+}
+
+/// Run code in ring 3, starting at `entry` with the stack pointer at `stack`
+///
+/// # Safety
+///
+/// Both `entry` and `stack` must be mapped as user-accessible, and nothing
+/// mapped as user-accessible may be relied upon by the kernel.
+pub unsafe fn enter_user(entry: VirtAddr, stack: VirtAddr) -> UserExit {
+    assert!(!in_user_mode(), "enter_user can't be nested");
+
+    let task = sched::current();
+    if task.user.is_killed() {
+        return UserExit::Killed;
+    }
+
+    // Don't let user code see what the previous user code left in the
+    // FPU and vector registers
+    fpu::reset();
+
+    let selectors = gdt::selectors();
+    arch::user_enter(
+        entry.as_u64(),
+        stack.as_u64(),
+        u64::from(selectors.user_code.0),
+        u64::from(selectors.user_data.0),
+        task.user.return_rsp.as_ptr(),
+        gdt::kernel_stack_ptr(),
+    );
+
+    task.user.return_rsp.store(0, Ordering::Relaxed);
+    let exit = task.user.exit.lock().take();
+    exit.expect("Returned from user mode without a reason")
+}
+
+/// Stop running user code, and return `exit` from [`enter_user`]
+///
+/// # Safety
+///
+/// Must only be called while handling an interrupt or exception that came
+/// from user mode. Everything on the stack below [`enter_user`] is thrown
+/// away without being dropped.
+pub(crate) unsafe fn exit_to_kernel(exit: UserExit) -> ! {
+    // The task must not be left on this stack, which is thrown away
+    let rsp = {
+        let task = sched::current();
+        let rsp = task.user.return_rsp();
+        assert_ne!(rsp, 0, "{:?} while not in user mode", exit);
+        *task.user.exit.lock() = Some(exit);
+        rsp
+    };
+    arch::user_return(rsp)
+}
+
+/// Return [`UserExit::Killed`] from [`enter_user`] if the current task was
+/// [killed](kill), instead of going back to user mode
+///
+/// # Safety
+///
+/// Same as [`exit_to_kernel`].
+pub(crate) unsafe fn exit_if_killed() {
+    let killed = sched::current().user.is_killed();
+    if killed {
+        exit_to_kernel(UserExit::Killed)
+    }
+}
+
+/// Helpers to run small snippets of machine code in user mode
+#[cfg(test)]
+pub(crate) mod test_util {
+    use crate::{kernel_state, memory};
+    use types::{MapFlags, Pager, VirtAddr};
+
+    /// Map a user page, and return a kernel pointer to it
+    fn map_user_page(addr: u64) -> *mut u8 {
+        let frame = memory::allocate_frame().unwrap();
+        let flags = MapFlags {
+            writable: true,
+            user: true,
+            executable: true,
+        };
+        unsafe {
+            kernel_state()
+                .pager
+                .lock()
+                .map_with_flags(VirtAddr(addr), frame, flags)
+                .unwrap()
+        };
+        addr as *mut u8
+    }
+
+    /// Copy `code` to a new user page at `base`, followed by a stack page,
+    /// and run it. Every test must use a different `base`.
+    pub fn run_user_code(base: u64, code: &[u8]) -> super::UserExit {
+        assert!(code.len() <= memory::PAGE_SIZE);
+        let page = map_user_page(base);
+        unsafe { page.copy_from_nonoverlapping(code.as_ptr(), code.len()) };
+
+        let stack = base + memory::PAGE_SIZE as u64;
+        map_user_page(stack);
+
+        unsafe {
+            super::enter_user(
+                x86_64::VirtAddr::new(base),
+                x86_64::VirtAddr::new(stack + memory::PAGE_SIZE as u64),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{test_util::run_user_code, UserExit};
+
+    const CODE: u64 = 0x0000_1000_0000_0000;
+
+    #[test_case]
+    fn user_fault_returns_to_kernel() {
+        // int3 (handled, resumes), then ud2
+        let exit = run_user_code(CODE, &[0xCC, 0x0F, 0x0B]);
+        assert_eq!(
+            exit,
+            UserExit::Fault {
+                vector: 6,
+                error_code: 0,
+                rip: CODE + 1,
+            }
+        );
+    }
+}
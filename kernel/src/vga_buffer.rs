@@ -2,11 +2,11 @@
 
 use core::fmt;
 use lazy_static::lazy_static;
-use spin::Mutex;
+use types::IrqSpinlock;
 use volatile::Volatile;
 
 lazy_static! {
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
+    pub static ref WRITER: IrqSpinlock<Writer> = IrqSpinlock::new(Writer {
         col_position: 0,
         color: ColorCode::new(Color::White, Color::Black),
         buffer: unsafe { &mut *(0xB8000 as *mut Buffer) },
@@ -27,9 +27,8 @@ macro_rules! println {
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
-    use x86_64::instructions::interrupts;
 
-    interrupts::without_interrupts(|| WRITER.lock().write_fmt(args).unwrap());
+    WRITER.lock().write_fmt(args).unwrap();
 }
 
 const BUFFER_WIDTH: usize = 80;
@@ -170,18 +169,15 @@ mod test {
     fn println_output() {
         use super::WRITER;
         use core::fmt::Write;
-        use x86_64::instructions::interrupts;
 
         let s = "Some test string that fits on a single line";
         println!("{}", s);
 
-        interrupts::without_interrupts(|| {
-            let mut writer = WRITER.lock();
-            writeln!(writer, "\n{}", s).expect("writeln failed");
-            for (i, c) in s.chars().enumerate() {
-                let screen_char = writer.buffer.0[super::BUFFER_HEIGHT - 2][i].read();
-                assert_eq!(char::from(screen_char.ascii_char), c);
-            }
-        });
+        let mut writer = WRITER.lock();
+        writeln!(writer, "\n{}", s).expect("writeln failed");
+        for (i, c) in s.chars().enumerate() {
+            let screen_char = writer.buffer.0[super::BUFFER_HEIGHT - 2][i].read();
+            assert_eq!(char::from(screen_char.ascii_char), c);
+        }
     }
 }
@@ -0,0 +1,98 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::{
+    delf::{self, process::STACK_TOP},
+    kernel_state,
+    process::{self, ExitStatus, File},
+    serial_print,
+    syscall::Errno,
+};
+use spin::Mutex;
+use types::{Pager, VirtAddr};
+
+/// Hello world programs, built from `fixtures/hello.S`
+static HELLO: &[u8] = include_bytes!("fixtures/hello");
+static HELLO_PIE: &[u8] = include_bytes!("fixtures/hello-pie");
+
+entry_point!(main);
+
+fn main(info: &'static BootInfo) -> ! {
+    kernel::init(info);
+    test_main();
+
+    loop {
+        x86_64::instructions::hlt()
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+/// Standard output for the program, which is printed to serial and kept to
+/// be checked
+#[derive(Default)]
+struct Output(Mutex<Vec<u8>>);
+
+impl File for Output {
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        serial_print!("{}", String::from_utf8_lossy(buf));
+        self.0.lock().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+}
+
+/// Run `program` in a new process, and return how it ended and what it wrote
+/// to standard output
+fn run(program: &'static [u8]) -> (ExitStatus, Vec<u8>) {
+    let output = Arc::new(Output::default());
+    let process = {
+        let output = output.clone();
+        process::spawn("hello", move || {
+            let process = process::current().unwrap();
+            let mut files = process.files();
+            files.close(1).unwrap();
+            assert_eq!(files.open(output), 1);
+            drop(files);
+
+            let mut elf = delf::process::Process::new();
+            elf.load_object(program).unwrap();
+            elf.apply_relocations().unwrap();
+            elf.start(&["hello"], &["HOME=/"]).unwrap().into()
+        })
+    };
+
+    let status = process::waitpid(process.pid()).unwrap();
+    // The program's memory is gone
+    assert!(kernel_state()
+        .pager
+        .lock()
+        .translate(VirtAddr(STACK_TOP - 1))
+        .is_none());
+    let output = output.0.lock().clone();
+    (status, output)
+}
+
+#[test_case]
+fn hello_world() {
+    let (status, output) = run(HELLO);
+    assert_eq!(status, ExitStatus::Exited(0));
+    assert_eq!(output, b"Hello from user mode!\n");
+}
+
+#[test_case]
+fn hello_world_position_independent() {
+    let (status, output) = run(HELLO_PIE);
+    assert_eq!(status, ExitStatus::Exited(0));
+    assert_eq!(output, b"Hello from user mode!\n");
+}
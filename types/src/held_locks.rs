@@ -0,0 +1,31 @@
+//! How many locks the running task holds
+//!
+//! Locks count themselves here while they're held, whether or not lockdep
+//! is enabled, so that the kernel can tell whether stopping a task would
+//! leave something locked forever. The count belongs to the task running on
+//! the CPU: the scheduler [swaps](swap) it on every context switch.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+static HELD: AtomicUsize = AtomicUsize::new(0);
+
+/// A lock was acquired by the running task
+pub fn acquired() {
+    HELD.fetch_add(1, Ordering::Relaxed);
+}
+
+/// A lock held by the running task was released
+pub fn released() {
+    HELD.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// The number of locks the running task holds
+pub fn count() -> usize {
+    HELD.load(Ordering::Relaxed)
+}
+
+/// Replace the count with the one of the task being switched to, and
+/// return the count of the task that was running
+pub fn swap(count: usize) -> usize {
+    HELD.swap(count, Ordering::Relaxed)
+}
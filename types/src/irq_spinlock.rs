@@ -0,0 +1,167 @@
+use core::{
+    fmt,
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+    ptr,
+};
+
+use spin::{Mutex, MutexGuard};
+use x86_64::instructions::interrupts;
+
+use crate::held_locks;
+
+#[cfg(feature = "lockdep")]
+use {crate::lockdep, core::panic::Location};
+
+/// A spinlock that disables interrupts while held
+///
+/// Code holding a plain spinlock can be interrupted by a handler that takes
+/// the same lock, which then spins forever. Disabling interrupts before
+/// locking rules that out, so this is the lock to use for anything shared
+/// with interrupt handlers. The guard restores the previous state of
+/// RFLAGS.IF when dropped, so these locks can be nested.
+///
+/// Critical sections should be short, since no interrupts are serviced
+/// in the meantime. With the `lockdep` feature, every acquisition is
+/// reported to the [lock dependency validator](crate::lockdep).
+pub struct IrqSpinlock<T: ?Sized> {
+    inner: Mutex<T>,
+}
+
+impl<T> IrqSpinlock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            inner: Mutex::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        // Moving out skips `Drop`, which only informs lockdep
+        let this = ManuallyDrop::new(self);
+        #[cfg(feature = "lockdep")]
+        lockdep::forget(this.class());
+        unsafe { ptr::read(&this.inner) }.into_inner()
+    }
+}
+
+impl<T: ?Sized> IrqSpinlock<T> {
+    /// Disable interrupts, and spin until the lock is available
+    #[track_caller]
+    pub fn lock(&self) -> IrqSpinlockGuard<'_, T> {
+        let enable = interrupts::are_enabled();
+        interrupts::disable();
+        // Reported before spinning, so that deadlocks are reported too
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(self.class(), Location::caller(), false);
+        let guard = self.inner.lock();
+        held_locks::acquired();
+        IrqSpinlockGuard {
+            guard: ManuallyDrop::new(guard),
+            enable,
+            #[cfg(feature = "lockdep")]
+            class: self.class(),
+        }
+    }
+
+    /// Lock only if the lock is available right now
+    ///
+    /// Interrupts are left untouched if it isn't.
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<IrqSpinlockGuard<'_, T>> {
+        let enable = interrupts::are_enabled();
+        interrupts::disable();
+        match self.inner.try_lock() {
+            Some(guard) => {
+                held_locks::acquired();
+                #[cfg(feature = "lockdep")]
+                lockdep::acquire(self.class(), Location::caller(), false);
+                Some(IrqSpinlockGuard {
+                    guard: ManuallyDrop::new(guard),
+                    enable,
+                    #[cfg(feature = "lockdep")]
+                    class: self.class(),
+                })
+            }
+            None => {
+                if enable {
+                    interrupts::enable();
+                }
+                None
+            }
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+
+    #[cfg(feature = "lockdep")]
+    fn class(&self) -> usize {
+        self as *const Self as *const () as usize
+    }
+}
+
+#[cfg(feature = "lockdep")]
+impl<T: ?Sized> Drop for IrqSpinlock<T> {
+    fn drop(&mut self) {
+        lockdep::forget(self.class());
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for IrqSpinlock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => f
+                .debug_struct("IrqSpinlock")
+                .field("data", &&*guard)
+                .finish(),
+            None => f.write_str("IrqSpinlock { <locked> }"),
+        }
+    }
+}
+
+impl<T: Default> Default for IrqSpinlock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// Unlocks the [`IrqSpinlock`] when dropped, then enables interrupts again
+/// if they were enabled before locking
+pub struct IrqSpinlockGuard<'a, T: ?Sized> {
+    guard: ManuallyDrop<MutexGuard<'a, T>>,
+    enable: bool,
+    #[cfg(feature = "lockdep")]
+    class: usize,
+}
+
+impl<T: ?Sized> Deref for IrqSpinlockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for IrqSpinlockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T: ?Sized> Drop for IrqSpinlockGuard<'_, T> {
+    fn drop(&mut self) {
+        // The lock must be released before an interrupt can try to take it
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        held_locks::released();
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.class);
+        if self.enable {
+            interrupts::enable();
+        }
+    }
+}
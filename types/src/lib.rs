@@ -2,13 +2,18 @@
 
 extern crate alloc;
 
-use alloc::boxed::Box;
+pub mod held_locks;
+mod irq_spinlock;
+#[cfg(feature = "lockdep")]
+pub mod lockdep;
+
+pub use irq_spinlock::{IrqSpinlock, IrqSpinlockGuard};
 
-use spin::Mutex;
+use alloc::boxed::Box;
 
 pub struct KernelState<P: Pager, F: FrameAllocator, V> {
-    pub pager: Mutex<P>,
-    pub frame_alloc: Mutex<F>,
+    pub pager: IrqSpinlock<P>,
+    pub frame_alloc: IrqSpinlock<F>,
     pub vga_buffer: V,
 }
 
@@ -44,9 +49,36 @@ pub struct PhysAddr(pub u64);
 /// Implementing this trait is unsafe, as it is possible to cause undefined
 /// behaviour by returning a frame that is already in use by some other code
 pub unsafe trait FrameAllocator {
-    // TODO: Handle deallocations
     /// Allocate a frame, and return its address
     fn next(&mut self) -> Option<PhysAddr>;
+
+    /// Give back a frame returned by [`next`](FrameAllocator::next), so that
+    /// it can be allocated again
+    ///
+    /// # Safety
+    /// Nothing may use the frame anymore
+    unsafe fn deallocate(&mut self, frame: PhysAddr);
+}
+
+/// The permissions of a page mapping
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MapFlags {
+    /// The page can be written to
+    pub writable: bool,
+    /// The page can be accessed from user mode
+    pub user: bool,
+    /// Code in the page can be executed. Every page is executable on CPUs
+    /// without no-execute support.
+    pub executable: bool,
+}
+
+impl MapFlags {
+    /// Kernel-only data, readable and writable
+    pub const KERNEL: Self = Self {
+        writable: true,
+        user: false,
+        executable: false,
+    };
 }
 
 /// Virtual memory mapping, and virtual-physical address translation
@@ -64,6 +96,12 @@ pub unsafe trait Pager {
     /// frame, then `None` is returned
     fn translate(&self, addr: VirtAddr) -> Option<PhysAddr>;
 
+    /// Get the permissions of the page containing `addr`
+    ///
+    /// If the provided virtual address is not mapped to any
+    /// frame, then `None` is returned
+    fn flags(&self, addr: VirtAddr) -> Option<MapFlags>;
+
     /// Create a mapping in the page table
     ///
     /// # Safety
@@ -71,7 +109,36 @@ pub unsafe trait Pager {
     /// is not already used, and also that nothing is stored in the page
     /// denoted by `addr`, unless everything is copied to the new location
     /// after the remapping
-    unsafe fn map(&mut self, addr: VirtAddr, to: PhysAddr) -> Option<()>;
+    unsafe fn map(&mut self, addr: VirtAddr, to: PhysAddr) -> Option<()> {
+        self.map_with_flags(addr, to, MapFlags::KERNEL)
+    }
+
+    /// Create a mapping in the page table, with the given permissions
+    ///
+    /// # Safety
+    /// The same as [`Pager::map`]. Additionally, user-accessible pages must
+    /// not contain anything the kernel relies upon
+    unsafe fn map_with_flags(&mut self, addr: VirtAddr, to: PhysAddr, flags: MapFlags) -> Option<()>;
+
+    /// Remove the mapping of the page containing `addr`, and return the
+    /// frame it pointed to
+    ///
+    /// If the page is not mapped, then `None` is returned
+    ///
+    /// # Safety
+    /// Nothing may access the page anymore, since it's not flushed from the
+    /// TLBs of other CPUs
+    unsafe fn unmap(&mut self, addr: VirtAddr) -> Option<PhysAddr>;
+
+    /// Change the permissions of the page containing `addr`
+    ///
+    /// If the page is not mapped, then `None` is returned
+    ///
+    /// # Safety
+    /// Nothing may rely on the page keeping its previous permissions. Like
+    /// [`Pager::map_with_flags`], user-accessible pages must not contain
+    /// anything the kernel relies upon
+    unsafe fn update_flags(&mut self, addr: VirtAddr, flags: MapFlags) -> Option<()>;
 }
 
 unsafe impl<P: Pager> Pager for Box<P> {
@@ -79,8 +146,20 @@ unsafe impl<P: Pager> Pager for Box<P> {
         Pager::translate(self.as_ref(), addr)
     }
 
-    unsafe fn map(&mut self, addr: VirtAddr, to: PhysAddr) -> Option<()> {
-        Pager::map(self.as_mut(), addr, to)
+    fn flags(&self, addr: VirtAddr) -> Option<MapFlags> {
+        Pager::flags(self.as_ref(), addr)
+    }
+
+    unsafe fn map_with_flags(&mut self, addr: VirtAddr, to: PhysAddr, flags: MapFlags) -> Option<()> {
+        Pager::map_with_flags(self.as_mut(), addr, to, flags)
+    }
+
+    unsafe fn unmap(&mut self, addr: VirtAddr) -> Option<PhysAddr> {
+        Pager::unmap(self.as_mut(), addr)
+    }
+
+    unsafe fn update_flags(&mut self, addr: VirtAddr, flags: MapFlags) -> Option<()> {
+        Pager::update_flags(self.as_mut(), addr, flags)
     }
 }
 
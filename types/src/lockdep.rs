@@ -0,0 +1,45 @@
+//! Hooks through which locks report to a lock dependency validator
+//!
+//! The validator itself lives in the kernel, which installs it with
+//! [`set_hooks`]. Until then, nothing is reported.
+
+use core::panic::Location;
+
+use spin::Once;
+
+/// The functions called by locks
+///
+/// Every lock is identified by its address, called its class.
+pub struct Hooks {
+    /// A lock was acquired at `site`, exclusively unless `shared` is set
+    pub acquire: fn(class: usize, site: &'static Location<'static>, shared: bool),
+    /// A lock was released
+    pub release: fn(class: usize),
+    /// A lock was destroyed, so its address may be reused by another lock
+    pub forget: fn(class: usize),
+}
+
+static HOOKS: Once<Hooks> = Once::new();
+
+/// Install the validator. Only the first call has an effect.
+pub fn set_hooks(hooks: Hooks) {
+    HOOKS.call_once(|| hooks);
+}
+
+pub fn acquire(class: usize, site: &'static Location<'static>, shared: bool) {
+    if let Some(hooks) = HOOKS.get() {
+        (hooks.acquire)(class, site, shared);
+    }
+}
+
+pub fn release(class: usize) {
+    if let Some(hooks) = HOOKS.get() {
+        (hooks.release)(class);
+    }
+}
+
+pub fn forget(class: usize) {
+    if let Some(hooks) = HOOKS.get() {
+        (hooks.forget)(class);
+    }
+}